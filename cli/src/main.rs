@@ -11,8 +11,126 @@ fn main() {
     if args.len() < 2 {
         println!("Processing sites");
 
-        let mut scrapper = scrapper::Scrapper::new();
-        tokio::runtime::Runtime::new().unwrap().block_on(scrapper.scrap_all());
+        let Some(mut scrapper) = new_scrapper() else {
+            return;
+        };
+        if let Err(e) = tokio::runtime::Runtime::new().unwrap().block_on(scrapper.scrap_all()) {
+            println!("Error: {}", e);
+        }
+    } else if args[1] == "add" {
+        if args.len() < 3 {
+            println!("Usage: cli add <url>");
+            return;
+        }
+
+        let Some(mut scrapper) = new_scrapper() else {
+            return;
+        };
+        match tokio::runtime::Runtime::new().unwrap().block_on(scrapper.submit_url(args[2].clone())) {
+            Ok(true) => println!("Added {}", args[2]),
+            Ok(false) => println!("{} was not added", args[2]),
+            Err(e) => println!("Error: {}", e),
+        }
+    } else if args[1] == "update-ranges" {
+        let Some(mut scrapper) = new_scrapper() else {
+            return;
+        };
+        match tokio::runtime::Runtime::new().unwrap().block_on(scrapper.update_ip_ranges()) {
+            Ok(count) => println!("{} ranges saved", count),
+            Err(e) => println!("Error: {}", e),
+        }
+    } else if args[1] == "refresh-ranks" {
+        let Some(mut scrapper) = new_scrapper() else {
+            return;
+        };
+        match tokio::runtime::Runtime::new().unwrap().block_on(scrapper.refresh_ranks()) {
+            Ok(count) => println!("{} domains processed", count),
+            Err(e) => println!("Error: {}", e),
+        }
+    } else if args[1] == "search" {
+        let min_pagerank = match args.get(3).map(|pagerank| pagerank.parse::<f64>()) {
+            Some(Ok(pagerank)) => pagerank,
+            None => 0.0,
+            Some(Err(_)) => {
+                println!("Usage: cli search <technology> [min_pagerank]");
+                return;
+            }
+        };
+        let technology = match args.get(2) {
+            Some(technology) => technology.clone(),
+            None => {
+                println!("Usage: cli search <technology> [min_pagerank]");
+                return;
+            }
+        };
+
+        let Some(mut scrapper) = new_scrapper() else {
+            return;
+        };
+        match tokio::runtime::Runtime::new().unwrap().block_on(scrapper.search_technology(technology, min_pagerank)) {
+            Ok(matches) => {
+                for webpage in matches.iter() {
+                    println!("{:>6.2} {} {}", webpage.pagerank.unwrap_or(0.0), webpage.host, webpage.url);
+                }
+                println!("{} pages", matches.len());
+            }
+            Err(e) => println!("Error: {}", e),
+        }
+    } else if args[1] == "migrate-pagerank" {
+        let Some(mut scrapper) = new_scrapper() else {
+            return;
+        };
+        match tokio::runtime::Runtime::new().unwrap().block_on(scrapper.migrate_pagerank()) {
+            Ok(count) => println!("{} pages migrated", count),
+            Err(e) => println!("Error: {}", e),
+        }
+    } else if args[1] == "check-pagerank" {
+        let Some(mut scrapper) = new_scrapper() else {
+            return;
+        };
+        match tokio::runtime::Runtime::new().unwrap().block_on(scrapper.check_pagerank()) {
+            Ok(divergences) => {
+                for divergence in divergences.iter() {
+                    println!(
+                        "{} {} (page = {:?}, domain = {:?})",
+                        divergence.reason, divergence.url, divergence.webpage_pagerank, divergence.domain_pagerank
+                    );
+                }
+                println!("{} divergent pages", divergences.len());
+            }
+            Err(e) => println!("Error: {}", e),
+        }
+    } else if args[1] == "favicon-hash" {
+        if args.len() < 3 {
+            println!("Usage: cli favicon-hash <url|file>");
+            return;
+        }
+
+        let Some(mut scrapper) = new_scrapper() else {
+            return;
+        };
+        match tokio::runtime::Runtime::new().unwrap().block_on(scrapper.favicon_hash(args[2].clone())) {
+            Ok(Some(hash)) => println!("{}", hash),
+            Ok(None) => println!("{} is not an image", args[2]),
+            Err(e) => println!("Error: {}", e),
+        }
+    } else if args[1] == "boost" {
+        let boost = match args.get(3).map(|boost| boost.parse::<f64>()) {
+            Some(Ok(boost)) => boost,
+            _ => {
+                println!("Usage: cli boost <url> <value>");
+                return;
+            }
+        };
+
+        let Some(mut scrapper) = new_scrapper() else {
+            return;
+        };
+        match tokio::runtime::Runtime::new().unwrap().block_on(scrapper.boost_url(args[2].clone(), boost)) {
+            Ok(true) => {}
+            Ok(false) => println!("{} is not in the queue", args[2]),
+            Err(e) => println!("Error: {}", e),
+        }
     } else {
         let site = args[1].clone();
         if !site.to_lowercase().starts_with("http") && !site.to_lowercase().starts_with("https") {
@@ -20,8 +138,25 @@ fn main() {
             return;
         }
 
-        let mut scrapper = scrapper::Scrapper::new();
+        let Some(mut scrapper) = new_scrapper() else {
+            return;
+        };
         //scrapper.scrap_site(site).await;
-        tokio::runtime::Runtime::new().unwrap().block_on(scrapper.scrap_site(site, None)).unwrap();
+        if let Err(e) = tokio::runtime::Runtime::new().unwrap().block_on(scrapper.scrap_site(site, None)) {
+            println!("Error: {}", e);
+        }
+    }
+}
+
+/*
+ * Crea el scrapper con la configuracion; si no es valida muestra el error
+ */
+fn new_scrapper() -> Option<scrapper::Scrapper> {
+    match scrapper::Scrapper::new() {
+        Ok(scrapper) => Some(scrapper),
+        Err(e) => {
+            println!("Error: {}", e);
+            None
+        }
     }
 }
@@ -0,0 +1,73 @@
+use serde::Deserialize;
+use std::env;
+
+use crate::contacts::ContactsConfig;
+use crate::dns::DnsConfig;
+use crate::error::ScrapperError;
+use crate::favicon::FaviconConfig;
+use crate::filter::DomainRule;
+use crate::geo::GeoConfig;
+use crate::hreflang::HreflangConfig;
+use crate::http::HttpConfig;
+use crate::language::LanguageConfig;
+use crate::priority::PriorityConfig;
+use crate::probe::ProbeConfig;
+use crate::rank::RankConfig;
+use crate::ranges::IpRangesConfig;
+use crate::retry::RetryConfig;
+use crate::schedule::RescrapConfig;
+use crate::tls::TlsConfig;
+
+const DEFAULT_CONFIG_FILE: &str = "config.yaml";
+
+/*
+ * Configuracion del scrapper, se lee de config.yaml (o de la ruta
+ * indicada en SITEMADE_CONFIG). Todas las secciones son opcionales.
+ */
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub public_suffix_list: Option<String>,
+    pub domain_filter: DomainFilterConfig,
+    pub priority: PriorityConfig,
+    pub rescrap: RescrapConfig,
+    pub retry: RetryConfig,
+    pub http: HttpConfig,
+    pub probe: ProbeConfig,
+    pub tls: TlsConfig,
+    pub dns: DnsConfig,
+    pub geo: GeoConfig,
+    pub ip_ranges: IpRangesConfig,
+    pub rank: RankConfig,
+    pub language: LanguageConfig,
+    pub hreflang: HreflangConfig,
+    pub contacts: ContactsConfig,
+    pub favicon: FaviconConfig,
+    pub parked_signatures: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DomainFilterConfig {
+    #[serde(with = "serde_yaml::with::singleton_map_recursive")]
+    pub allow: Vec<DomainRule>,
+    #[serde(with = "serde_yaml::with::singleton_map_recursive")]
+    pub block: Vec<DomainRule>,
+}
+
+impl Config {
+    pub fn load() -> Result<Self, ScrapperError> {
+        let path = env::var("SITEMADE_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG_FILE.to_string());
+
+        let file = match std::fs::File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("No se pudo abrir la configuracion {}: {:?}, usando valores por defecto", path, e);
+
+                return Ok(Self::default());
+            }
+        };
+
+        serde_yaml::from_reader(file).map_err(|e| ScrapperError::Config(format!("configuracion {} invalida: {}", path, e)))
+    }
+}
@@ -0,0 +1,273 @@
+use scraper::Html;
+use serde::Deserialize;
+
+use crate::db::{DatabaseContacts, DatabaseSocialProfile};
+use crate::error::ScrapperError;
+
+//Rutas de compartir, login, etc. que no son perfiles
+const NOT_PROFILE_PATHS: [&str; 16] = [
+    "share",
+    "sharer",
+    "sharer.php",
+    "sharearticle",
+    "intent",
+    "home",
+    "login",
+    "signup",
+    "dialog",
+    "plugins",
+    "hashtag",
+    "search",
+    "watch",
+    "embed",
+    "p",
+    "events",
+];
+
+/*
+ * Redes sociales cuyos enlaces se guardan como perfiles del dominio. Aunque
+ * estos dominios no se scrapean (ver domain_filter) sus enlaces son los
+ * perfiles de la empresa. max_per_domain limita cuantos perfiles, emails o
+ * telefonos se guardan de cada tipo.
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ContactsConfig {
+    pub networks: Vec<SocialNetwork>,
+    pub max_per_domain: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SocialNetwork {
+    pub name: String,
+    pub domains: Vec<String>,
+}
+
+impl Default for ContactsConfig {
+    fn default() -> Self {
+        let network = |name: &str, domains: &[&str]| SocialNetwork {
+            name: name.to_string(),
+            domains: domains.iter().map(|domain| domain.to_string()).collect(),
+        };
+
+        Self {
+            networks: vec![
+                network("facebook", &["facebook.com", "fb.com"]),
+                network("twitter", &["twitter.com", "x.com"]),
+                network("instagram", &["instagram.com"]),
+                network("linkedin", &["linkedin.com"]),
+                network("youtube", &["youtube.com"]),
+                network("tiktok", &["tiktok.com"]),
+                network("pinterest", &["pinterest.com"]),
+                network("github", &["github.com"]),
+                network("telegram", &["t.me"]),
+            ],
+            max_per_domain: 50,
+        }
+    }
+}
+
+/*
+ * Perfiles sociales (enlaces a las redes de config), emails (mailto:) y
+ * telefonos (tel:) de la pagina, normalizados y sin repetidos
+ */
+pub fn extract(config: &ContactsConfig, document: &Html) -> Result<DatabaseContacts, ScrapperError> {
+    let selector = scraper::Selector::parse("a[href]")
+        .map_err(|e| ScrapperError::Parse(format!("selector de enlaces: {:?}", e)))?;
+
+    let mut contacts = DatabaseContacts {
+        social: vec![],
+        emails: vec![],
+        phones: vec![],
+    };
+    for a in document.select(&selector) {
+        let Some(href) = a.value().attr("href") else {
+            continue;
+        };
+        let href = href.trim();
+        let scheme = href.split_once(':').map(|(scheme, _)| scheme.to_lowercase());
+
+        match scheme.as_deref() {
+            Some("mailto") => {
+                if let Some(email) = normalize_email(&href[7..]) {
+                    push_unique(&mut contacts.emails, email);
+                }
+            }
+            Some("tel") => {
+                if let Some(phone) = normalize_phone(&href[4..]) {
+                    push_unique(&mut contacts.phones, phone);
+                }
+            }
+            Some("http") | Some("https") => {
+                if let Some(profile) = social_profile(config, href) {
+                    if !contacts.social.iter().any(|existing| existing.url == profile.url) {
+                        contacts.social.push(profile);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(contacts)
+}
+
+/*
+ * Perfil de una red social a partir del enlace: https, con el dominio de
+ * la red (sin www, m. ni subdominios), sin query ni barra final. Los
+ * enlaces de compartir y similares no son perfiles.
+ */
+fn social_profile(config: &ContactsConfig, href: &str) -> Option<DatabaseSocialProfile> {
+    let url = reqwest::Url::parse(href).ok()?;
+    let host = url.host_str()?.to_lowercase();
+
+    //www, m. o los subdominios de idioma (es.linkedin.com) se quitan usando el dominio de la red
+    let (network, domain) = config.networks.iter().find_map(|network| {
+        network
+            .domains
+            .iter()
+            .find(|domain| host == **domain || host.ends_with(&format!(".{}", domain)))
+            .map(|domain| (network, domain))
+    })?;
+
+    let segments: Vec<&str> = url
+        .path_segments()?
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    let first = segments.first()?;
+    if NOT_PROFILE_PATHS.contains(&first.to_lowercase().as_str()) || first.ends_with(".php") {
+        return None;
+    }
+
+    //En linkedin y youtube el perfil tiene dos partes (company/acme, channel/xyz)
+    let prefixed = ["company", "in", "school", "showcase", "channel", "c", "user"].contains(first);
+    let profile_segments = if prefixed { segments.get(..2)? } else { &segments[..1] };
+    let handle = profile_segments.last()?.trim_start_matches('@').to_string();
+    if handle.is_empty() {
+        return None;
+    }
+
+    Some(DatabaseSocialProfile {
+        network: network.name.clone(),
+        handle,
+        url: format!("https://{}/{}", domain, profile_segments.join("/")),
+    })
+}
+
+fn normalize_email(mailto: &str) -> Option<String> {
+    let email = mailto.split(['?', ',']).next()?.trim().replace("%40", "@").to_lowercase();
+    let (user, domain) = email.split_once('@')?;
+    let valid = !user.is_empty()
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !email.contains(char::is_whitespace)
+        && !domain.contains('@');
+
+    valid.then_some(email)
+}
+
+/*
+ * Telefono solo con digitos y el + inicial. Los de menos de 6 digitos no
+ * son telefonos reales.
+ */
+fn normalize_phone(tel: &str) -> Option<String> {
+    let tel = tel.split([';', '?']).next()?.replace("%20", "").replace("%2B", "+");
+    let digits: String = tel.chars().filter(|c| c.is_ascii_digit()).collect();
+    if digits.len() < 6 {
+        return None;
+    }
+
+    if tel.trim_start().starts_with('+') {
+        Some(format!("+{}", digits))
+    } else if let Some(digits) = digits.strip_prefix("00") {
+        Some(format!("+{}", digits))
+    } else {
+        Some(digits)
+    }
+}
+
+fn push_unique(values: &mut Vec<String>, value: String) {
+    if !values.contains(&value) {
+        values.push(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract_html(html: &str) -> DatabaseContacts {
+        extract(&ContactsConfig::default(), &Html::parse_document(html)).unwrap()
+    }
+
+    #[test]
+    fn social_profiles_are_normalized() {
+        let config = ContactsConfig::default();
+        let profile = |href: &str| social_profile(&config, href).map(|profile| profile.url);
+
+        assert_eq!(profile("https://www.facebook.com/Acme/?ref=page").as_deref(), Some("https://facebook.com/Acme"));
+        assert_eq!(profile("http://m.facebook.com/acme").as_deref(), Some("https://facebook.com/acme"));
+        assert_eq!(
+            profile("https://es.linkedin.com/company/acme/about/").as_deref(),
+            Some("https://linkedin.com/company/acme")
+        );
+        assert_eq!(profile("https://x.com/acme").as_deref(), Some("https://x.com/acme"));
+
+        let youtube = social_profile(&config, "https://www.youtube.com/@acme/videos").unwrap();
+        assert_eq!(youtube.network, "youtube");
+        assert_eq!(youtube.handle, "acme");
+    }
+
+    #[test]
+    fn share_links_are_not_profiles() {
+        let config = ContactsConfig::default();
+
+        assert!(social_profile(&config, "https://www.facebook.com/sharer.php?u=https://acme.com").is_none());
+        assert!(social_profile(&config, "https://twitter.com/intent/tweet?text=hola").is_none());
+        assert!(social_profile(&config, "https://www.linkedin.com/shareArticle?url=x").is_none());
+        assert!(social_profile(&config, "https://www.linkedin.com/company/").is_none());
+        assert!(social_profile(&config, "https://www.youtube.com/").is_none());
+        assert!(social_profile(&config, "https://notfacebook.com/acme").is_none());
+    }
+
+    #[test]
+    fn emails_are_normalized() {
+        assert_eq!(normalize_email("Info@Acme.com?subject=Hola").as_deref(), Some("info@acme.com"));
+        assert_eq!(normalize_email("ventas%40acme.es").as_deref(), Some("ventas@acme.es"));
+        assert_eq!(normalize_email("a@acme.com,b@acme.com").as_deref(), Some("a@acme.com"));
+        assert_eq!(normalize_email("acme.com"), None);
+        assert_eq!(normalize_email("info@acme"), None);
+        assert_eq!(normalize_email("@acme.com"), None);
+    }
+
+    #[test]
+    fn phones_are_normalized() {
+        assert_eq!(normalize_phone("+34 912 345 678").as_deref(), Some("+34912345678"));
+        assert_eq!(normalize_phone("0034-912-345-678").as_deref(), Some("+34912345678"));
+        assert_eq!(normalize_phone("%2B34%20912345678").as_deref(), Some("+34912345678"));
+        assert_eq!(normalize_phone("(91) 234-5678;ext=2").as_deref(), Some("912345678"));
+        assert_eq!(normalize_phone("112"), None);
+    }
+
+    #[test]
+    fn extract_keeps_first_seen_order_without_repeats() {
+        let contacts = extract_html(
+            r#"<a href="mailto:b@acme.com">b</a>
+               <a href="MAILTO:a@acme.com">a</a>
+               <a href="mailto:B@acme.com">b</a>
+               <a href="tel:+34 912 345 678">tel</a>
+               <a href="tel:+34912345678">tel</a>
+               <a href="https://twitter.com/acme">tw</a>
+               <a href="https://www.facebook.com/acme">fb</a>
+               <a href="https://twitter.com/acme/">tw</a>
+               <a href="/contacto">contacto</a>
+               <a href="javascript:void(0)">js</a>"#,
+        );
+
+        assert_eq!(contacts.emails, vec!["b@acme.com", "a@acme.com"]);
+        assert_eq!(contacts.phones, vec!["+34912345678"]);
+        let networks: Vec<&str> = contacts.social.iter().map(|profile| profile.network.as_str()).collect();
+        assert_eq!(networks, vec!["twitter", "facebook"]);
+    }
+}
@@ -1,12 +1,35 @@
 use chrono::{DateTime, Utc};
 use futures_util::stream::StreamExt;
 use mongodb::{
-    options::{ClientOptions, ResolverConfig},
-    Client,
+    options::{ClientOptions, FindOneAndUpdateOptions, FindOneOptions, IndexOptions, ResolverConfig, ReturnDocument, UpdateOptions},
+    Client, IndexModel,
 };
 use serde::{Deserialize, Serialize};
 use std::env;
 
+use crate::error::ScrapperError;
+use crate::priority::{compute_priority, queue_priority, PriorityConfig};
+use crate::schedule::RescrapConfig;
+
+/*
+ * Igual que chrono_datetime_as_bson_datetime pero para campos opcionales
+ */
+mod optional_chrono_datetime {
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => bson::DateTime::from_chrono(*value).serialize(serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+        Option::<bson::DateTime>::deserialize(deserializer).map(|value| value.map(|value| value.to_chrono()))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DatabaseDomain {
     pub _id: mongodb::bson::oid::ObjectId,
@@ -15,8 +38,339 @@ pub struct DatabaseDomain {
     pub created_at: DateTime<Utc>,
     pub scrappeable: bool,
     pub pagerank: Option<f64>,
+    #[serde(default, with = "optional_chrono_datetime")]
+    pub pagerank_updated_at: Option<DateTime<Utc>>,
     pub ip: Option<String>,
     pub languages: Option<Vec<String>>,
+    pub aliases: Option<Vec<mongodb::bson::oid::ObjectId>>,
+    pub certificate: Option<DatabaseCertificate>,
+    pub probe: Option<DatabaseProbe>,
+    pub dns: Option<DatabaseDnsRecords>,
+    pub technologies: Option<Vec<DatabaseWebTechnology>>,
+    pub geo: Option<Vec<DatabaseGeoIp>>,
+    pub hosting: Option<DatabaseProviderMatch>,
+    pub cdn: Option<DatabaseProviderMatch>,
+    pub organization: Option<DatabaseOrganization>,
+    pub contacts: Option<DatabaseContacts>,
+    pub favicon: Option<DatabaseFavicon>,
+}
+
+impl DatabaseDomain {
+    pub fn new(host: String) -> Self {
+        Self {
+            _id: mongodb::bson::oid::ObjectId::new(),
+            host,
+            created_at: chrono::Utc::now(),
+            scrappeable: true,
+            pagerank: None,
+            pagerank_updated_at: None,
+            ip: None,
+            languages: None,
+            aliases: None,
+            certificate: None,
+            probe: None,
+            dns: None,
+            technologies: None,
+            geo: None,
+            hosting: None,
+            cdn: None,
+            organization: None,
+            contacts: None,
+            favicon: None,
+        }
+    }
+}
+
+/*
+ * Proveedor de hosting o cdn deducido de los rangos de ips. confidence es
+ * la fraccion de ips del dominio que estan en sus rangos.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseProviderMatch {
+    pub provider: String,
+    pub confidence: f64,
+}
+
+/*
+ * Idioma declarado por la pagina (normalizado a BCP 47) y el detectado en
+ * su texto visible. mismatch indica que no coinciden y la deteccion es fiable.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseLanguageDetection {
+    pub declared: Option<String>,
+    pub detected: Option<String>,
+    pub confidence: Option<f64>,
+    pub reliable: bool,
+    pub mismatch: bool,
+}
+
+/*
+ * Version de la pagina en otro idioma (hreflang en BCP 47 o x-default),
+ * declarada en el html o en el sitemap
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseAlternate {
+    pub hreflang: String,
+    pub url: String,
+    pub source: String,
+}
+
+/*
+ * Metadatos SEO de la pagina
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseSeoMetadata {
+    pub title: Option<String>,
+    pub title_length: Option<u32>,
+    pub description: Option<String>,
+    pub description_length: Option<u32>,
+    pub canonical: Option<String>,
+    pub robots: Vec<String>,
+    pub noindex: bool,
+    pub nofollow: bool,
+    pub open_graph: DatabaseOpenGraph,
+    pub twitter_card: DatabaseTwitterCard,
+    pub headings: DatabaseHeadingCounts,
+    pub favicon: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseOpenGraph {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<String>,
+    pub url: Option<String>,
+    pub og_type: Option<String>,
+    pub site_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseTwitterCard {
+    pub card: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<String>,
+    pub site: Option<String>,
+    pub creator: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseHeadingCounts {
+    pub h1: u32,
+    pub h2: u32,
+    pub h3: u32,
+    pub h4: u32,
+    pub h5: u32,
+    pub h6: u32,
+}
+
+/*
+ * Entidad de schema.org encontrada en la pagina (json-ld o microdata).
+ * category es Organization, LocalBusiness, Product o WebSite y entity_type
+ * el tipo concreto declarado.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseStructuredEntity {
+    pub entity_type: String,
+    pub category: String,
+    pub source: String,
+    pub name: Option<String>,
+    pub url: Option<String>,
+    pub logo: Option<String>,
+    pub telephone: Option<String>,
+    pub email: Option<String>,
+    pub address: Option<String>,
+    pub same_as: Vec<String>,
+    pub brand: Option<String>,
+    pub sku: Option<String>,
+    pub price: Option<String>,
+    pub currency: Option<String>,
+}
+
+/*
+ * Organizacion propietaria del dominio segun sus datos estructurados
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseOrganization {
+    pub name: Option<String>,
+    pub entity_type: String,
+    pub url: Option<String>,
+    pub logo: Option<String>,
+    pub telephone: Option<String>,
+    pub email: Option<String>,
+    pub address: Option<String>,
+    pub same_as: Vec<String>,
+}
+
+/*
+ * Perfiles sociales, emails y telefonos enlazados desde las paginas del dominio
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseContacts {
+    pub social: Vec<DatabaseSocialProfile>,
+    pub emails: Vec<String>,
+    pub phones: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseSocialProfile {
+    pub network: String,
+    pub handle: String,
+    pub url: String,
+}
+
+/*
+ * Favicon del dominio y su hash mmh3 (el de Shodan)
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseFavicon {
+    pub url: String,
+    pub hash: i32,
+    pub content_type: Option<String>,
+    pub size: i64,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub fetched_at: DateTime<Utc>,
+}
+
+/*
+ * Pagina con una tecnologia y el pagerank de su dominio
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseTechnologyMatch {
+    pub url: String,
+    pub host: String,
+    pub pagerank: Option<f64>,
+}
+
+/*
+ * Webpage que todavia tiene el campo pagerank antiguo o cuyo dominio no existe
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabasePagerankDivergence {
+    pub url: String,
+    pub webpage_pagerank: Option<f64>,
+    pub domain_pagerank: Option<f64>,
+    pub reason: String,
+}
+
+/*
+ * Estado de un trabajo por lotes, para poder continuarlo si se interrumpe.
+ * last_id es el ultimo documento procesado; finished_at es None mientras
+ * el trabajo esta en curso.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseJob {
+    pub _id: String,
+    pub last_id: Option<mongodb::bson::oid::ObjectId>,
+    pub processed: u64,
+    pub total: u64,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub started_at: DateTime<Utc>,
+    #[serde(default, with = "optional_chrono_datetime")]
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/*
+ * Geolocalizacion y ASN de cada ip del dominio
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseGeoIp {
+    pub ip: String,
+    pub country_code: Option<String>,
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub city: Option<String>,
+    pub asn: Option<u32>,
+    pub organization: Option<String>,
+}
+
+/*
+ * Registros dns del dominio. spf, dmarc y verifications se extraen de los
+ * TXT (los de dmarc son los de _dmarc.<dominio>).
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseDnsRecords {
+    pub a: Vec<String>,
+    pub aaaa: Vec<String>,
+    pub cname: Vec<String>,
+    pub mx: Vec<DatabaseMxRecord>,
+    pub ns: Vec<String>,
+    pub txt: Vec<String>,
+    pub spf: Option<String>,
+    pub dmarc: Option<String>,
+    pub verifications: Vec<String>,
+    pub caa: Vec<String>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub resolved_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseMxRecord {
+    pub preference: u16,
+    pub exchange: String,
+}
+
+/*
+ * Resultado de probar http/https y apex/www. canonical_origin es el origen
+ * (scheme://host) que se usa para scrapear las paginas del dominio.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseProbe {
+    pub results: Vec<DatabaseProbeResult>,
+    pub https: bool,
+    pub http_redirects_to_https: bool,
+    pub apex_live: bool,
+    pub www_live: bool,
+    pub canonical_origin: Option<String>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub probed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseProbeResult {
+    pub url: String,
+    pub status: Option<u16>,
+    pub final_url: Option<String>,
+    pub live: bool,
+    pub error: Option<String>,
+}
+
+/*
+ * Resumen del certificado tls del dominio. status es valid, expiring,
+ * expired, not_yet_valid o untrusted (autofirmado, de otro host...) en el
+ * momento de checked_at. chain empieza por el propio certificado.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseCertificate {
+    pub subject: Option<String>,
+    pub issuer: Option<String>,
+    pub issuer_organization: Option<String>,
+    pub issuer_category: String,
+    pub serial: String,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub not_before: DateTime<Utc>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub not_after: DateTime<Utc>,
+    pub san: Vec<String>,
+    pub key_type: String,
+    pub key_bits: u32,
+    pub self_signed: bool,
+    pub status: String,
+    #[serde(default)]
+    pub verify_error: Option<String>,
+    #[serde(default)]
+    pub chain: Vec<DatabaseChainCertificate>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub checked_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseChainCertificate {
+    pub subject: Option<String>,
+    pub issuer: Option<String>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub not_after: DateTime<Utc>,
+    pub self_signed: bool,
 }
 
 
@@ -35,11 +389,29 @@ pub struct DatabaseWebpage {
     pub technologies: Option<Vec<DatabaseWebTechnology>>,
     pub headers: Option<Vec<DatabaseWebTechnology>>,
     pub language: Option<String>,
-    pub pagerank: Option<f64>,
+    pub language_detection: Option<DatabaseLanguageDetection>,
+    pub alternates: Option<Vec<DatabaseAlternate>>,
+    pub seo: Option<DatabaseSeoMetadata>,
+    pub structured_data: Option<Vec<DatabaseStructuredEntity>>,
+    pub priority: Option<f64>,
+    pub queue_priority: Option<f64>,
+    pub inbound_links: Option<u64>,
+    pub boost: Option<f64>,
+    #[serde(default, with = "optional_chrono_datetime")]
+    pub next_scrap_at: Option<DateTime<Utc>>,
+    pub rescrap_interval_hours: Option<i64>,
+    pub failed_attempts: Option<u32>,
+    pub last_error: Option<String>,
+    #[serde(default, with = "optional_chrono_datetime")]
+    pub last_error_at: Option<DateTime<Utc>>,
+    pub redirects: Option<Vec<DatabaseRedirect>>,
+    pub final_url: Option<String>,
+    pub fetch: Option<DatabaseFetchInfo>,
+    pub security: Option<DatabaseSecurityAudit>,
 }
 
 impl DatabaseWebpage {
-    pub fn new(domain_id: mongodb::bson::oid::ObjectId, url: String, pagerank: Option<f64>) -> Self {
+    pub fn new(domain_id: mongodb::bson::oid::ObjectId, url: String) -> Self {
         let birthay: DateTime<Utc> = chrono::DateTime::parse_from_rfc3339("1970-01-01T00:00:00Z")
             .unwrap()
             .into();
@@ -55,7 +427,23 @@ impl DatabaseWebpage {
             technologies: None,
             headers: None,
             language: None,
-            pagerank: pagerank,
+            language_detection: None,
+            alternates: None,
+            seo: None,
+            structured_data: None,
+            priority: None,
+            queue_priority: None,
+            inbound_links: None,
+            boost: None,
+            next_scrap_at: Some(chrono::Utc::now()),
+            rescrap_interval_hours: None,
+            failed_attempts: None,
+            last_error: None,
+            last_error_at: None,
+            redirects: None,
+            final_url: None,
+            fetch: None,
+            security: None,
         }
     }
 }
@@ -66,454 +454,1395 @@ pub struct DatabaseWebTechnology {
     pub name: String,
 }
 
-pub async fn get_mongodb() -> Result<mongodb::Client, mongodb::error::Error> {
-    let client_uri =
-        env::var("MONGODB_URI").expect("You must set the MONGODB_URI environment var!");
+/*
+ * Datos de la ultima descarga de la pagina. ttfb_ms es el de la peticion
+ * final y total_ms incluye redirecciones y lectura del cuerpo.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseFetchInfo {
+    pub status: u16,
+    pub http_version: String,
+    pub content_type: Option<String>,
+    pub charset: Option<String>,
+    pub encoding: Option<String>,
+    pub compression: Option<String>,
+    pub transfer_size: u64,
+    pub body_size: u64,
+    pub ttfb_ms: u64,
+    pub total_ms: u64,
+    pub skipped: Option<String>,
+}
+
+/*
+ * Auditoria de las cabeceras de seguridad. score es la suma de los pesos
+ * de las comprobaciones que pasan (0 a 100) y grade su letra (A a F).
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseSecurityAudit {
+    pub checks: Vec<DatabaseSecurityCheck>,
+    pub score: u32,
+    pub grade: String,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub audited_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseSecurityCheck {
+    pub name: String,
+    pub passed: bool,
+    pub weight: u32,
+    pub value: Option<String>,
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseRedirect {
+    pub url: String,
+    pub status: u16,
+    pub location: String,
+    pub cross_domain: bool,
+}
+
+pub async fn get_mongodb() -> Result<mongodb::Client, ScrapperError> {
+    let client_uri = env::var("MONGODB_URI")
+        .map_err(|_| ScrapperError::Config("You must set the MONGODB_URI environment var!".to_string()))?;
 
     let options =
         ClientOptions::parse_with_resolver_config(&client_uri, ResolverConfig::cloudflare())
             .await?;
 
-    let db_client = Some(Client::with_options(options)?);
+    let db_client = Client::with_options(options)?;
 
-    return Ok(db_client.unwrap());
+    Ok(db_client)
 }
 
-pub async fn get_database_domain(db_client: mongodb::Client, domain: &str) -> DatabaseDomain {
+pub async fn get_database_domain(db_client: mongodb::Client, domain: &str) -> Result<DatabaseDomain, ScrapperError> {
     let domains_collection: mongodb::Collection<DatabaseDomain> =
         db_client.database("sitemade").collection("domains");
     let domain_doc = mongodb::bson::doc! { "host": domain };
 
-    let domain_result = domains_collection.find_one(domain_doc, None).await.unwrap_or_else(|_| None);
+    let domain_result = domains_collection.find_one(domain_doc, None).await?;
 
     let domain = match domain_result {
         Some(domain_doc) => domain_doc,
         None => {
-            let domain_doc = DatabaseDomain {
-                _id: mongodb::bson::oid::ObjectId::new(),
-                host: domain.to_string(),
-                created_at: chrono::Utc::now(),
-                scrappeable: true,
-                pagerank: None,
-                ip: None,
-                languages: None,
-            };
+            let domain_doc = DatabaseDomain::new(domain.to_string());
             domains_collection
                 .insert_one(&domain_doc, None)
-                .await
-                .unwrap();
+                .await?;
             domain_doc
         }
     };
 
-    domain
+    Ok(domain)
 }
 
-pub async fn update_database_domain_pagerank(db_client: mongodb::Client, domain_id: mongodb::bson::oid::ObjectId, pr: f64) {
+pub async fn update_database_domain_pagerank(
+    db_client: mongodb::Client,
+    domain_id: mongodb::bson::oid::ObjectId,
+    pr: f64,
+    priority_config: &PriorityConfig,
+) -> Result<(), ScrapperError> {
     let domains_collection: mongodb::Collection<DatabaseDomain> =
         db_client.database("sitemade").collection("domains");
-    let domain_doc = mongodb::bson::doc! { "_id": domain_id };
 
-    let domain_result = domains_collection.find_one(domain_doc, None).await.unwrap();
-    match domain_result {
-        Some(mut domain_doc) => {
-            domain_doc.pagerank = Some(pr);
-            let bson_doc = mongodb::bson::to_bson(&domain_doc).unwrap();
-            domains_collection
-                .update_one(
-                    mongodb::bson::doc! { "_id": domain_id },
-                    mongodb::bson::doc! { "$set": bson_doc },
-                    None,
-                )
-                .await
-                .unwrap();
-        }
-        None => {}
-    };
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::Before)
+        .build();
+    let previous = domains_collection
+        .find_one_and_update(
+            mongodb::bson::doc! { "_id": domain_id },
+            mongodb::bson::doc! { "$set": { "pagerank": pr, "pagerank_updated_at": chrono::Utc::now() } },
+            options,
+        )
+        .await?;
+
+    //El pagerank solo esta en el dominio; en las webpages se corrige la
+    //prioridad con la diferencia de pagerank, con una sola consulta
+    let previous_pr = previous.and_then(|domain| domain.pagerank).unwrap_or(0.0);
+    let delta = priority_config.pagerank_weight * (pr - previous_pr);
+    if delta != 0.0 {
+        let webpages_collection: mongodb::Collection<DatabaseWebpage> =
+            db_client.database("sitemade").collection("webpages");
+        webpages_collection
+            .update_many(
+                mongodb::bson::doc! { "domain_id": domain_id },
+                vec![
+                    mongodb::bson::doc! { "$set": {
+                        "priority": { "$add": [{ "$ifNull": ["$priority", 0.0] }, delta] },
+                    } },
+                    queue_priority_stage(priority_config),
+                ],
+                None,
+            )
+            .await?;
+    }
 
-    //actualizar el pagerank en database_webpages que sean de este dominio
-    let webpages_collection: mongodb::Collection<DatabaseWebpage> =
-        db_client.database("sitemade").collection("webpages");
-    let webpage_doc = mongodb::bson::doc! { "domain_id": domain_id };
+    Ok(())
+}
+
+pub async fn get_database_domain_pagerank(
+    db_client: mongodb::Client,
+    domain_id: mongodb::bson::oid::ObjectId,
+) -> Result<Option<f64>, ScrapperError> {
+    let domains_collection: mongodb::Collection<DatabaseDomain> =
+        db_client.database("sitemade").collection("domains");
 
-    let mut cursor = webpages_collection.find(webpage_doc, None).await.unwrap();
+    let domain = domains_collection
+        .find_one(mongodb::bson::doc! { "_id": domain_id }, None)
+        .await?;
 
-    while let Some(webpage) = cursor.next().await {
-        match webpage {
-            Ok(webpage_doc) => {
-                //let mut webpage_doc: DatabaseWebpage =
-                //    bson::from_document(webpage_doc).unwrap();
-                let mut webpage_doc: DatabaseWebpage = webpage_doc;
-                webpage_doc.pagerank = Some(pr);
-                let bson_doc = mongodb::bson::to_bson(&webpage_doc).unwrap();
-                webpages_collection
-                    .update_one(
-                        mongodb::bson::doc! { "_id": webpage_doc._id },
-                        mongodb::bson::doc! { "$set": bson_doc },
-                        None,
-                    )
-                    .await
-                    .unwrap();
-            }
-            Err(e) => {
-                eprintln!("Error: {}", e);
-            }
-        }
-    }
+    Ok(domain.and_then(|domain| domain.pagerank))
 }
 
-pub async fn update_database_domain_ip(db_client: mongodb::Client, domain_id: mongodb::bson::oid::ObjectId, ip: String) {
+pub async fn update_database_domain_ip(db_client: mongodb::Client, domain_id: mongodb::bson::oid::ObjectId, ip: String) -> Result<(), ScrapperError> {
     let domains_collection: mongodb::Collection<DatabaseDomain> =
         db_client.database("sitemade").collection("domains");
     let domain_doc = mongodb::bson::doc! { "_id": domain_id };
 
-    let domain_result = domains_collection.find_one(domain_doc, None).await.unwrap();
-    match domain_result {
-        Some(mut domain_doc) => {
-            domain_doc.ip = Some(ip);
-            let bson_doc = mongodb::bson::to_bson(&domain_doc).unwrap();
-            domains_collection
-                .update_one(
-                    mongodb::bson::doc! { "_id": domain_id },
-                    mongodb::bson::doc! { "$set": bson_doc },
-                    None,
-                )
-                .await
-                .unwrap();
-        }
-        None => {}
-    };
+    let domain_result = domains_collection.find_one(domain_doc, None).await?;
+    if let Some(mut domain_doc) = domain_result {
+        domain_doc.ip = Some(ip);
+        let bson_doc = mongodb::bson::to_bson(&domain_doc)?;
+        domains_collection
+            .update_one(
+                mongodb::bson::doc! { "_id": domain_id },
+                mongodb::bson::doc! { "$set": bson_doc },
+                None,
+            )
+            .await?;
+    }
+
+    Ok(())
 }
 
-pub async fn add_domain_to_database(db_client: mongodb::Client, domain: String) -> Option<mongodb::bson::oid::ObjectId> {
+pub async fn add_domain_to_database(db_client: mongodb::Client, domain: String) -> Result<mongodb::bson::oid::ObjectId, ScrapperError> {
     let domains_collection: mongodb::Collection<DatabaseDomain> =
         db_client.database("sitemade").collection("domains");
     let domain_doc = mongodb::bson::doc! { "host": domain.clone() };
 
-    let domain_result = domains_collection.find_one(domain_doc, None).await.unwrap();
+    let domain_result = domains_collection.find_one(domain_doc, None).await?;
 
     let domain_id = match domain_result {
         Some(domain_doc) => domain_doc._id,
         None => {
-            let domain_doc = DatabaseDomain {
-                _id: mongodb::bson::oid::ObjectId::new(),
-                host: domain.clone(),
-                created_at: chrono::Utc::now(),
-                scrappeable: true,
-                pagerank: None,
-                ip: None,
-                languages: None,
-            };
+            let domain_doc = DatabaseDomain::new(domain.clone());
             domains_collection
                 .insert_one(&domain_doc, None)
-                .await
-                .unwrap();
+                .await?;
             domain_doc._id
         }
     };
 
-    Some(domain_id)
+    Ok(domain_id)
 }
 
-pub async fn add_webpage_to_database(db_client: mongodb::Client, webpage: DatabaseWebpage) {
+pub async fn add_webpage_to_database(db_client: mongodb::Client, webpage: DatabaseWebpage) -> Result<(), ScrapperError> {
     let webpages_collection: mongodb::Collection<DatabaseWebpage> =
         db_client.database("sitemade").collection("webpages");
     let webpage_doc = mongodb::bson::doc! { "url": webpage.url.clone() };
 
     let webpage_result = webpages_collection
         .find_one(webpage_doc, None)
-        .await
-        .unwrap();
+        .await?;
 
     match webpage_result {
         Some(_webpage_doc) => {}
         None => {
             webpages_collection
                 .insert_one(&webpage, None)
-                .await
-                .unwrap();
+                .await?;
         }
     };
+
+    Ok(())
 }
 
 pub async fn set_database_webpage(
     db_client: mongodb::Client,
     webpage_url: String,
     domain_id: mongodb::bson::oid::ObjectId,
-) -> DatabaseWebpage {
+) -> Result<DatabaseWebpage, ScrapperError> {
     let webpages_collection: mongodb::Collection<DatabaseWebpage> =
         db_client.database("sitemade").collection("webpages");
     let webpage_doc = mongodb::bson::doc! { "url": webpage_url.clone() };
 
     let webpage_result = webpages_collection
         .find_one(webpage_doc, None)
-        .await
-        .unwrap();
+        .await?;
 
     let webpage = match webpage_result {
         Some(webpage_doc) => webpage_doc,
         None => {
-            let webpage_doc = DatabaseWebpage::new(domain_id, webpage_url.clone(), None);
+            let webpage_doc = DatabaseWebpage::new(domain_id, webpage_url.clone());
             webpages_collection
                 .insert_one(&webpage_doc, None)
-                .await
-                .unwrap();
+                .await?;
             webpage_doc
         }
     };
 
-    webpage
+    Ok(webpage)
 }
 
 pub async fn update_database_web_technologies(
     db_client: mongodb::Client,
-    dataabase_web_technologies: &Vec<DatabaseWebTechnology>,
+    dataabase_web_technologies: &[DatabaseWebTechnology],
     website_id: mongodb::bson::oid::ObjectId,
-) -> Result<(), mongodb::error::Error> {
+) -> Result<(), ScrapperError> {
     let webpages_collection: mongodb::Collection<DatabaseWebpage> =
         db_client.database("sitemade").collection("webpages");
     let webpage_doc = mongodb::bson::doc! { "_id": website_id };
 
     let webpage_result = webpages_collection
         .find_one(webpage_doc, None)
-        .await
-        .unwrap();
-
-    match webpage_result {
-        Some(mut webpage_doc) => {
-            webpage_doc.technologies = Some(dataabase_web_technologies.clone());
-            webpage_doc.updated_at = chrono::Utc::now();
-            webpage_doc.scrapped_at = chrono::Utc::now();
-
-            let bson_doc = mongodb::bson::to_bson(&webpage_doc).unwrap();
-            webpages_collection
-                .update_one(
-                    mongodb::bson::doc! { "_id": website_id },
-                    mongodb::bson::doc! { "$set": bson_doc },
-                    None,
-                )
-                .await
-                .unwrap();
-        }
-        None => {}
-    };
+        .await?;
+
+    if let Some(mut webpage_doc) = webpage_result {
+        webpage_doc.technologies = Some(dataabase_web_technologies.to_vec());
+        webpage_doc.updated_at = chrono::Utc::now();
+        webpage_doc.scrapped_at = chrono::Utc::now();
+
+        let bson_doc = mongodb::bson::to_bson(&webpage_doc)?;
+        webpages_collection
+            .update_one(
+                mongodb::bson::doc! { "_id": website_id },
+                mongodb::bson::doc! { "$set": bson_doc },
+                None,
+            )
+            .await?;
+    }
 
-    return Ok(());
+    Ok(())
 }
 
 pub async fn update_database_web_headers(
     db_client: mongodb::Client,
-    database_web_headers: &Vec<DatabaseWebTechnology>,
+    database_web_headers: &[DatabaseWebTechnology],
     website_id: mongodb::bson::oid::ObjectId,
-) -> Result<(), mongodb::error::Error> {
+) -> Result<(), ScrapperError> {
     let webpages_collection: mongodb::Collection<DatabaseWebpage> =
         db_client.database("sitemade").collection("webpages");
     let webpage_doc = mongodb::bson::doc! { "_id": website_id };
 
     let webpage_result = webpages_collection
         .find_one(webpage_doc, None)
-        .await
-        .unwrap();
-
-    match webpage_result {
-        Some(mut webpage_doc) => {
-            webpage_doc.headers = Some(database_web_headers.clone());
-            webpage_doc.updated_at = chrono::Utc::now();
-
-            let bson_doc = mongodb::bson::to_bson(&webpage_doc).unwrap();
-            webpages_collection
-                .update_one(
-                    mongodb::bson::doc! { "_id": website_id },
-                    mongodb::bson::doc! { "$set": bson_doc },
-                    None,
-                )
-                .await
-                .unwrap();
-        }
-        None => {}
-    };
+        .await?;
+
+    if let Some(mut webpage_doc) = webpage_result {
+        webpage_doc.headers = Some(database_web_headers.to_vec());
+        webpage_doc.updated_at = chrono::Utc::now();
+
+        let bson_doc = mongodb::bson::to_bson(&webpage_doc)?;
+        webpages_collection
+            .update_one(
+                mongodb::bson::doc! { "_id": website_id },
+                mongodb::bson::doc! { "$set": bson_doc },
+                None,
+            )
+            .await?;
+    }
 
-    return Ok(());
+    Ok(())
 }
 
 pub async fn update_database_webpage_language(
     db_client: mongodb::Client,
     language: String,
+    detection: &DatabaseLanguageDetection,
     domain_id: mongodb::bson::oid::ObjectId,
     website_id: mongodb::bson::oid::ObjectId,
-) -> Result<(), mongodb::error::Error> {
+) -> Result<(), ScrapperError> {
     let webpages_collection: mongodb::Collection<DatabaseWebpage> =
         db_client.database("sitemade").collection("webpages");
     let webpage_doc = mongodb::bson::doc! { "_id": website_id };
 
     let webpage_result = webpages_collection
         .find_one(webpage_doc, None)
-        .await
-        .unwrap();
-
-    match webpage_result {
-        Some(mut webpage_doc) => {
-            webpage_doc.language = Some(language.clone());
-            webpage_doc.updated_at = chrono::Utc::now();
-
-            let bson_doc = mongodb::bson::to_bson(&webpage_doc).unwrap();
-            webpages_collection
-                .update_one(
-                    mongodb::bson::doc! { "_id": website_id },
-                    mongodb::bson::doc! { "$set": bson_doc },
-                    None,
-                )
-                .await
-                .unwrap();
-        }
-        None => {}
-    };
+        .await?;
+
+    if let Some(mut webpage_doc) = webpage_result {
+        webpage_doc.language = Some(language.clone());
+        webpage_doc.language_detection = Some(detection.clone());
+        webpage_doc.updated_at = chrono::Utc::now();
+
+        let bson_doc = mongodb::bson::to_bson(&webpage_doc)?;
+        webpages_collection
+            .update_one(
+                mongodb::bson::doc! { "_id": website_id },
+                mongodb::bson::doc! { "$set": bson_doc },
+                None,
+            )
+            .await?;
+    }
 
     let domains_collection: mongodb::Collection<DatabaseDomain> =
         db_client.database("sitemade").collection("domains");
     let domain_doc = mongodb::bson::doc! { "_id": domain_id };
 
-    let domain_result = domains_collection.find_one(domain_doc, None).await.unwrap();
+    let domain_result = domains_collection.find_one(domain_doc, None).await?;
 
-    match domain_result {
-        Some(mut domain_doc) => {
-            match &mut domain_doc.languages {
-                Some(languages) => {
-                    if !languages.contains(&language) {
-                        languages.push(language.clone());
-                    }
-                }
-                None => {
-                    domain_doc.languages = Some(vec![language.clone()]);
+    if let Some(mut domain_doc) = domain_result {
+        match &mut domain_doc.languages {
+            Some(languages) => {
+                if !languages.contains(&language) {
+                    languages.push(language.clone());
                 }
             }
-            let bson_doc = mongodb::bson::to_bson(&domain_doc).unwrap();
-            domains_collection
-                .update_one(
-                    mongodb::bson::doc! { "_id": domain_id },
-                    mongodb::bson::doc! { "$set": bson_doc },
-                    None,
-                )
-                .await
-                .unwrap();
+            None => {
+                domain_doc.languages = Some(vec![language.clone()]);
+            }
         }
-        None => {}
+        let bson_doc = mongodb::bson::to_bson(&domain_doc)?;
+        domains_collection
+            .update_one(
+                mongodb::bson::doc! { "_id": domain_id },
+                mongodb::bson::doc! { "$set": bson_doc },
+                None,
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/*
+ * Siguiente pagina de la cola: la de mayor queue_priority de las que ya
+ * tienen que volver a scrapearse
+ */
+pub async fn get_database_webpage_to_scrap(db_client: mongodb::Client) -> Result<Option<DatabaseWebpage>, ScrapperError> {
+    let webpages_collection: mongodb::Collection<DatabaseWebpage> =
+        db_client.database("sitemade").collection("webpages");
+
+    let webpage_doc = mongodb::bson::doc! {
+        "scrappeable": true,
+        "next_scrap_at": { "$lte": chrono::Utc::now() },
+    };
+    let options = FindOneOptions::builder()
+        .sort(mongodb::bson::doc! { "queue_priority": -1 })
+        .build();
+
+    let webpage = webpages_collection.find_one(webpage_doc, options).await?;
+
+    Ok(webpage)
+}
+
+/*
+ * Etapa de una actualizacion por pipeline que recalcula queue_priority a
+ * partir de los campos ya actualizados. Tiene que ir en toda escritura que
+ * cambie priority, scrapped_at o next_scrap_at; es la misma cuenta que
+ * priority::queue_priority.
+ */
+fn queue_priority_stage(priority_config: &PriorityConfig) -> mongodb::bson::Document {
+    let cap_days = priority_config.freshness_cap_days.max(1);
+    let freshness_days = mongodb::bson::doc! {
+        "$min": [
+            cap_days,
+            {
+                "$max": [
+                    0,
+                    { "$floor": { "$divide": [
+                        { "$subtract": [{ "$ifNull": ["$next_scrap_at", "$$NOW"] }, "$scrapped_at"] },
+                        86_400_000,
+                    ] } },
+                ]
+            },
+        ]
     };
 
+    mongodb::bson::doc! { "$set": {
+        "queue_priority": { "$add": [
+            { "$ifNull": ["$priority", 0.0] },
+            { "$multiply": [priority_config.freshness_weight * 10.0 / cap_days as f64, freshness_days] },
+        ] },
+    } }
+}
+
+pub async fn get_webpages_count_from_domain(db_client: mongodb::Client, domain_id: mongodb::bson::oid::ObjectId) -> Result<u64, ScrapperError> {
+    let webpages_collection: mongodb::Collection<DatabaseWebpage> =
+        db_client.database("sitemade").collection("webpages");
+
+    let webpage_doc = mongodb::bson::doc! { "domain_id": domain_id };
+
+    let count = webpages_collection.count_documents(webpage_doc, None).await?;
+
+    Ok(count)
+}
+
+pub async fn ensure_webpages_indexes(db_client: mongodb::Client) -> Result<(), ScrapperError> {
+    let webpages_collection: mongodb::Collection<DatabaseWebpage> =
+        db_client.database("sitemade").collection("webpages");
+
+    let index = IndexModel::builder()
+        .keys(mongodb::bson::doc! { "scrappeable": 1, "next_scrap_at": 1, "queue_priority": -1 })
+        .options(IndexOptions::builder().name("webpage_queue".to_string()).build())
+        .build();
+    webpages_collection.create_index(index, None).await?;
+
+    let links_collection: mongodb::Collection<DatabaseWebpageLink> =
+        db_client.database("sitemade").collection("webpage_links");
+    let index = IndexModel::builder()
+        .keys(mongodb::bson::doc! { "source": 1, "target": 1 })
+        .options(IndexOptions::builder().name("webpage_link".to_string()).unique(true).build())
+        .build();
+    links_collection.create_index(index, None).await?;
+
     Ok(())
 }
 
-pub async fn update_database_webpages_set_scrappeable(
+/*
+ * Completa las webpages creadas antes de que existieran priority,
+ * queue_priority o next_scrap_at. Las que no tenian next_scrap_at vuelven a
+ * la cola default_hours despues de su ultimo scrapeo.
+ */
+pub async fn backfill_webpages_priority(
     db_client: mongodb::Client,
-    website_id: mongodb::bson::oid::ObjectId,
-    scrappeable: bool,
-) -> Result<(), mongodb::error::Error> {
+    rescrap_config: &RescrapConfig,
+    priority_config: &PriorityConfig,
+) -> Result<u64, ScrapperError> {
     let webpages_collection: mongodb::Collection<DatabaseWebpage> =
         db_client.database("sitemade").collection("webpages");
-    let webpage_doc = mongodb::bson::doc! { "_id": website_id };
+    let webpage_doc = mongodb::bson::doc! { "$or": [
+        { "queue_priority": { "$exists": false } },
+        { "scrappeable": true, "next_scrap_at": null },
+    ] };
 
-    let mut webpage_result = webpages_collection
-        .find(webpage_doc, None)
-        .await
-        .unwrap();
-
-    while let Some(webpage) = webpage_result.next().await {
-        match webpage {
-            Ok(mut webpage_doc) => {
-                webpage_doc.scrappeable = scrappeable;
-                webpage_doc.updated_at = chrono::Utc::now();
-
-                let bson_doc = mongodb::bson::to_bson(&webpage_doc).unwrap();
-                webpages_collection
-                    .update_one(
-                        mongodb::bson::doc! { "_id": website_id },
-                        mongodb::bson::doc! { "$set": bson_doc },
-                        None,
-                    )
-                    .await
-                    .unwrap();
-            }
-            Err(e) => {
-                eprintln!("Error: {}", e);
+    let mut cursor = webpages_collection.find(webpage_doc, None).await?;
+    let mut updated = 0;
+    let mut domain_pageranks = std::collections::HashMap::new();
+
+    while let Some(webpage) = cursor.next().await {
+        let mut webpage_doc = webpage?;
+        let domain_pagerank = match domain_pageranks.get(&webpage_doc.domain_id) {
+            Some(domain_pagerank) => *domain_pagerank,
+            None => {
+                let domain_pagerank = get_database_domain_pagerank(db_client.clone(), webpage_doc.domain_id).await?;
+                domain_pageranks.insert(webpage_doc.domain_id, domain_pagerank);
+                domain_pagerank
             }
+        };
+        if webpage_doc.priority.is_none() {
+            webpage_doc.priority = Some(compute_priority(priority_config, &webpage_doc, domain_pagerank));
         }
+        if webpage_doc.scrappeable && webpage_doc.next_scrap_at.is_none() {
+            webpage_doc.next_scrap_at =
+                Some(webpage_doc.scrapped_at + chrono::Duration::hours(rescrap_config.default_hours));
+        }
+        let queue_priority = queue_priority(priority_config, &webpage_doc);
+
+        webpages_collection
+            .update_one(
+                mongodb::bson::doc! { "_id": webpage_doc._id },
+                mongodb::bson::doc! { "$set": {
+                    "priority": webpage_doc.priority,
+                    "queue_priority": queue_priority,
+                    "next_scrap_at": webpage_doc.next_scrap_at,
+                } },
+                None,
+            )
+            .await?;
+        updated += 1;
     }
 
-    Ok(())
+    Ok(updated)
+}
+
+/*
+ * Enlace entre dos paginas, para contar cada pagina de origen una sola vez
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseWebpageLink {
+    pub source: String,
+    pub target: String,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+}
+
+/*
+ * Registra el enlace source -> target. Devuelve false si ya estaba registrado.
+ */
+pub async fn add_webpage_link(db_client: mongodb::Client, source: &str, target: &str) -> Result<bool, ScrapperError> {
+    let links_collection: mongodb::Collection<DatabaseWebpageLink> =
+        db_client.database("sitemade").collection("webpage_links");
+
+    let options = UpdateOptions::builder().upsert(true).build();
+    let link = DatabaseWebpageLink {
+        source: source.to_string(),
+        target: target.to_string(),
+        created_at: chrono::Utc::now(),
+    };
+
+    let result = links_collection
+        .update_one(
+            mongodb::bson::doc! { "source": source, "target": target },
+            mongodb::bson::doc! { "$setOnInsert": mongodb::bson::to_bson(&link)? },
+            options,
+        )
+        .await?;
+
+    Ok(result.upserted_id.is_some())
 }
 
-pub async fn get_database_webpage_to_scrap(db_client: mongodb::Client) -> Option<DatabaseWebpage> {
+/*
+ * Devuelve la webpage con esa url, si existe, sumandole un enlace entrante
+ * si count es true
+ */
+pub async fn register_webpage_inbound_link(
+    db_client: mongodb::Client,
+    webpage_url: &str,
+    count: bool,
+) -> Result<Option<DatabaseWebpage>, ScrapperError> {
     let webpages_collection: mongodb::Collection<DatabaseWebpage> =
         db_client.database("sitemade").collection("webpages");
 
-    let webpage_doc = mongodb::bson::doc! {
-        "scrappeable": true,
-        "scrapped_at": { "$lt": chrono::Utc::now() - chrono::Duration::days(10) }
-    };
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build();
 
-    //es necesario que scrapped_at al menos hace 10 díasS
-    /*
-    let webpage_doc = mongodb::bson::doc! {
-        "scrappeable": true, "scrapped_at": { "$lt": chrono::Utc::now() - chrono::Duration::days(10) }
-    };
-    */
-    //
-    let pipeline = vec![
-        mongodb::bson::doc! {
-            "$match": webpage_doc
-        },
-        mongodb::bson::doc! {
-            "$sort": {
-                "scrapped_at": 1,
-                //"pagerank": -1,
-            }
-        },
-        mongodb::bson::doc! {
-            "$limit": 1
-        },
-    ];
+    let webpage = webpages_collection
+        .find_one_and_update(
+            mongodb::bson::doc! { "url": webpage_url },
+            mongodb::bson::doc! { "$inc": { "inbound_links": i64::from(count) } },
+            options,
+        )
+        .await?;
 
-    let mut cursor = match webpages_collection.aggregate(pipeline, None).await {
-        Ok(cursor) => cursor,
-        Err(_) => {
-            return None;
-        }
-    };
+    Ok(webpage)
+}
 
-    //mostrar todos los resultados
-    /*
-     while let Some(result) = cursor.next().await {
-        match result {
-            Ok(document) => {
-                println!("{:?}", document);
-            },
-            Err(e) => {
-                eprintln!("Error: {}", e);
-            }
-        }
-    }
-    */
+pub async fn update_database_webpage_boost(
+    db_client: mongodb::Client,
+    webpage_url: &str,
+    boost: f64,
+) -> Result<Option<DatabaseWebpage>, ScrapperError> {
+    let webpages_collection: mongodb::Collection<DatabaseWebpage> =
+        db_client.database("sitemade").collection("webpages");
 
-    let webpage = match cursor.next().await {
-        Some(result) => {
-            let doc: DatabaseWebpage = bson::from_document(result.unwrap()).unwrap();
-            Some(doc)
-        },
-        None => {
-            return None;
-        }
-    };
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build();
+
+    let webpage = webpages_collection
+        .find_one_and_update(
+            mongodb::bson::doc! { "url": webpage_url },
+            mongodb::bson::doc! { "$set": { "boost": boost } },
+            options,
+        )
+        .await?;
 
-    return Some(webpage.unwrap());
+    Ok(webpage)
 }
 
-pub async fn get_webpages_count_from_domain(db_client: mongodb::Client, domain_id: mongodb::bson::oid::ObjectId) -> u64 {
+pub async fn get_database_webpage(
+    db_client: mongodb::Client,
+    website_id: mongodb::bson::oid::ObjectId,
+) -> Result<Option<DatabaseWebpage>, ScrapperError> {
     let webpages_collection: mongodb::Collection<DatabaseWebpage> =
         db_client.database("sitemade").collection("webpages");
 
-    let webpage_doc = mongodb::bson::doc! { "domain_id": domain_id };
+    let webpage = webpages_collection
+        .find_one(mongodb::bson::doc! { "_id": website_id }, None)
+        .await?;
 
-    let count = webpages_collection.count_documents(webpage_doc, None).await.unwrap_or_else(|_| 0);
+    Ok(webpage)
+}
 
-    count
+pub async fn update_database_webpage_priority(
+    db_client: mongodb::Client,
+    website_id: mongodb::bson::oid::ObjectId,
+    priority: f64,
+    priority_config: &PriorityConfig,
+) -> Result<(), ScrapperError> {
+    let webpages_collection: mongodb::Collection<DatabaseWebpage> =
+        db_client.database("sitemade").collection("webpages");
+
+    webpages_collection
+        .update_one(
+            mongodb::bson::doc! { "_id": website_id },
+            vec![
+                mongodb::bson::doc! { "$set": { "priority": priority } },
+                queue_priority_stage(priority_config),
+            ],
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn update_database_webpage_schedule(
+    db_client: mongodb::Client,
+    website_id: mongodb::bson::oid::ObjectId,
+    interval_hours: i64,
+    priority_config: &PriorityConfig,
+) -> Result<(), ScrapperError> {
+    let webpages_collection: mongodb::Collection<DatabaseWebpage> =
+        db_client.database("sitemade").collection("webpages");
+
+    let next_scrap_at = chrono::Utc::now() + chrono::Duration::hours(interval_hours);
+    webpages_collection
+        .update_one(
+            mongodb::bson::doc! { "_id": website_id },
+            //Una visita correcta limpia los fallos anteriores
+            vec![
+                mongodb::bson::doc! { "$set": {
+                    "next_scrap_at": next_scrap_at,
+                    "rescrap_interval_hours": interval_hours,
+                    "failed_attempts": 0,
+                    "last_error": null,
+                } },
+                queue_priority_stage(priority_config),
+            ],
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/*
+ * Guarda el fallo de la pagina. Con retry_at se reprograma el intento,
+ * sin el la pagina deja de ser scrappeable.
+ */
+pub async fn record_database_webpage_failure(
+    db_client: mongodb::Client,
+    website_id: mongodb::bson::oid::ObjectId,
+    failed_attempts: u32,
+    error: &ScrapperError,
+    retry_at: Option<DateTime<Utc>>,
+    priority_config: &PriorityConfig,
+) -> Result<(), ScrapperError> {
+    let webpages_collection: mongodb::Collection<DatabaseWebpage> =
+        db_client.database("sitemade").collection("webpages");
+
+    let update = failure_update(failed_attempts, error, retry_at, chrono::Utc::now());
+    webpages_collection
+        .update_one(
+            mongodb::bson::doc! { "_id": website_id },
+            vec![mongodb::bson::doc! { "$set": update }, queue_priority_stage(priority_config)],
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+fn failure_update(
+    failed_attempts: u32,
+    error: &ScrapperError,
+    retry_at: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> mongodb::bson::Document {
+    let mut update = mongodb::bson::doc! {
+        "failed_attempts": failed_attempts,
+        "last_error": format!("{}: {}", error.kind(), error),
+        "last_error_at": now,
+        "updated_at": now,
+    };
+    match retry_at {
+        Some(retry_at) => {
+            update.insert("next_scrap_at", retry_at);
+        }
+        None => {
+            update.insert("scrappeable", false);
+        }
+    }
+
+    update
+}
+
+/*
+ * Aplaza la pagina sin contarlo como fallo suyo, para errores de base de
+ * datos o configuracion. Sin esto la cola devolveria la misma pagina en
+ * cada vuelta.
+ */
+pub async fn postpone_database_webpage(
+    db_client: mongodb::Client,
+    website_id: mongodb::bson::oid::ObjectId,
+    error: &ScrapperError,
+    next_scrap_at: DateTime<Utc>,
+    priority_config: &PriorityConfig,
+) -> Result<(), ScrapperError> {
+    let webpages_collection: mongodb::Collection<DatabaseWebpage> =
+        db_client.database("sitemade").collection("webpages");
+
+    let now = chrono::Utc::now();
+    webpages_collection
+        .update_one(
+            mongodb::bson::doc! { "_id": website_id },
+            vec![
+                mongodb::bson::doc! { "$set": {
+                    "next_scrap_at": next_scrap_at,
+                    "last_error": format!("{}: {}", error.kind(), error),
+                    "last_error_at": now,
+                } },
+                queue_priority_stage(priority_config),
+            ],
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/*
+ * Guarda la cadena de redirecciones de la pagina y a donde termina.
+ * Cuenta como visita aunque el analisis se haga sobre la url final.
+ */
+pub async fn update_database_webpage_redirects(
+    db_client: mongodb::Client,
+    website_id: mongodb::bson::oid::ObjectId,
+    redirects: &Vec<DatabaseRedirect>,
+    final_url: String,
+) -> Result<(), ScrapperError> {
+    let webpages_collection: mongodb::Collection<DatabaseWebpage> =
+        db_client.database("sitemade").collection("webpages");
+
+    let now = chrono::Utc::now();
+    webpages_collection
+        .update_one(
+            mongodb::bson::doc! { "_id": website_id },
+            mongodb::bson::doc! { "$set": {
+                "redirects": mongodb::bson::to_bson(redirects)?,
+                "final_url": final_url,
+                "scrapped_at": now,
+                "updated_at": now,
+            } },
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/*
+ * Saca la webpage de la cola porque se scrapea con otra url (la de su origen
+ * canonico, guardada en final_url por update_database_webpage_redirects)
+ */
+pub async fn retire_database_webpage(
+    db_client: mongodb::Client,
+    webpage_id: mongodb::bson::oid::ObjectId,
+) -> Result<(), ScrapperError> {
+    let webpages_collection: mongodb::Collection<DatabaseWebpage> =
+        db_client.database("sitemade").collection("webpages");
+
+    webpages_collection
+        .update_one(
+            mongodb::bson::doc! { "_id": webpage_id },
+            mongodb::bson::doc! { "$set": { "scrappeable": false, "next_scrap_at": null } },
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/*
+ * Marca dos dominios como alias el uno del otro
+ */
+pub async fn link_database_domain_aliases(
+    db_client: mongodb::Client,
+    domain_id: mongodb::bson::oid::ObjectId,
+    alias_id: mongodb::bson::oid::ObjectId,
+) -> Result<(), ScrapperError> {
+    let domains_collection: mongodb::Collection<DatabaseDomain> =
+        db_client.database("sitemade").collection("domains");
+
+    for (id, alias) in [(domain_id, alias_id), (alias_id, domain_id)] {
+        domains_collection
+            .update_one(
+                mongodb::bson::doc! { "_id": id },
+                mongodb::bson::doc! { "$addToSet": { "aliases": alias } },
+                None,
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn update_database_webpage_fetch(
+    db_client: mongodb::Client,
+    website_id: mongodb::bson::oid::ObjectId,
+    fetch: &DatabaseFetchInfo,
+) -> Result<(), ScrapperError> {
+    let webpages_collection: mongodb::Collection<DatabaseWebpage> =
+        db_client.database("sitemade").collection("webpages");
+
+    let now = chrono::Utc::now();
+    webpages_collection
+        .update_one(
+            mongodb::bson::doc! { "_id": website_id },
+            mongodb::bson::doc! { "$set": {
+                "fetch": mongodb::bson::to_bson(fetch)?,
+                "scrapped_at": now,
+                "updated_at": now,
+            } },
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn update_database_domain_certificate(
+    db_client: mongodb::Client,
+    domain_id: mongodb::bson::oid::ObjectId,
+    certificate: &DatabaseCertificate,
+) -> Result<(), ScrapperError> {
+    let domains_collection: mongodb::Collection<DatabaseDomain> =
+        db_client.database("sitemade").collection("domains");
+
+    domains_collection
+        .update_one(
+            mongodb::bson::doc! { "_id": domain_id },
+            mongodb::bson::doc! { "$set": { "certificate": mongodb::bson::to_bson(certificate)? } },
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn update_database_webpage_security(
+    db_client: mongodb::Client,
+    website_id: mongodb::bson::oid::ObjectId,
+    security: &DatabaseSecurityAudit,
+) -> Result<(), ScrapperError> {
+    let webpages_collection: mongodb::Collection<DatabaseWebpage> =
+        db_client.database("sitemade").collection("webpages");
+
+    webpages_collection
+        .update_one(
+            mongodb::bson::doc! { "_id": website_id },
+            mongodb::bson::doc! { "$set": { "security": mongodb::bson::to_bson(security)? } },
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn update_database_webpage_seo(
+    db_client: mongodb::Client,
+    website_id: mongodb::bson::oid::ObjectId,
+    seo: &DatabaseSeoMetadata,
+) -> Result<(), ScrapperError> {
+    let webpages_collection: mongodb::Collection<DatabaseWebpage> =
+        db_client.database("sitemade").collection("webpages");
+
+    webpages_collection
+        .update_one(
+            mongodb::bson::doc! { "_id": website_id },
+            mongodb::bson::doc! { "$set": { "seo": mongodb::bson::to_bson(seo)? } },
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn update_database_webpage_structured_data(
+    db_client: mongodb::Client,
+    website_id: mongodb::bson::oid::ObjectId,
+    entities: &[DatabaseStructuredEntity],
+) -> Result<(), ScrapperError> {
+    let webpages_collection: mongodb::Collection<DatabaseWebpage> =
+        db_client.database("sitemade").collection("webpages");
+
+    webpages_collection
+        .update_one(
+            mongodb::bson::doc! { "_id": website_id },
+            mongodb::bson::doc! { "$set": { "structured_data": mongodb::bson::to_bson(entities)? } },
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn update_database_domain_favicon(
+    db_client: mongodb::Client,
+    domain_id: mongodb::bson::oid::ObjectId,
+    favicon: &DatabaseFavicon,
+) -> Result<(), ScrapperError> {
+    let domains_collection: mongodb::Collection<DatabaseDomain> =
+        db_client.database("sitemade").collection("domains");
+
+    domains_collection
+        .update_one(
+            mongodb::bson::doc! { "_id": domain_id },
+            mongodb::bson::doc! { "$set": { "favicon": mongodb::bson::to_bson(favicon)? } },
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/*
+ * Añade los contactos de una pagina a los del dominio, sin repetidos y con
+ * un maximo de max_per_domain de cada tipo
+ */
+pub async fn update_database_domain_contacts(
+    db_client: mongodb::Client,
+    domain_id: mongodb::bson::oid::ObjectId,
+    contacts: &DatabaseContacts,
+    max_per_domain: i64,
+) -> Result<(), ScrapperError> {
+    let domains_collection: mongodb::Collection<DatabaseDomain> =
+        db_client.database("sitemade").collection("domains");
+
+    //Los que ya tenia el dominio van primero y los nuevos despues, en el orden en que aparecen
+    let merge = |field: &str, values: mongodb::bson::Bson| {
+        let existing = mongodb::bson::doc! { "$ifNull": [format!("$contacts.{}", field), []] };
+        mongodb::bson::doc! { "$slice": [
+            { "$concatArrays": [
+                existing.clone(),
+                { "$filter": {
+                    "input": { "$literal": values },
+                    "cond": { "$not": [{ "$in": ["$$this", existing] }] },
+                } },
+            ] },
+            max_per_domain,
+        ] }
+    };
+    let update = vec![mongodb::bson::doc! { "$set": { "contacts": {
+        "social": merge("social", mongodb::bson::to_bson(&contacts.social)?),
+        "emails": merge("emails", mongodb::bson::to_bson(&contacts.emails)?),
+        "phones": merge("phones", mongodb::bson::to_bson(&contacts.phones)?),
+    } } }];
+
+    domains_collection
+        .update_one(mongodb::bson::doc! { "_id": domain_id }, update, None)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn update_database_domain_organization(
+    db_client: mongodb::Client,
+    domain_id: mongodb::bson::oid::ObjectId,
+    organization: &DatabaseOrganization,
+) -> Result<(), ScrapperError> {
+    let domains_collection: mongodb::Collection<DatabaseDomain> =
+        db_client.database("sitemade").collection("domains");
+
+    domains_collection
+        .update_one(
+            mongodb::bson::doc! { "_id": domain_id },
+            mongodb::bson::doc! { "$set": { "organization": mongodb::bson::to_bson(organization)? } },
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/*
+ * Guarda las alternativas de la pagina y añade sus idiomas a los del dominio
+ */
+pub async fn update_database_webpage_alternates(
+    db_client: mongodb::Client,
+    domain_id: mongodb::bson::oid::ObjectId,
+    website_id: mongodb::bson::oid::ObjectId,
+    alternates: &[DatabaseAlternate],
+    languages: &[String],
+) -> Result<(), ScrapperError> {
+    let webpages_collection: mongodb::Collection<DatabaseWebpage> =
+        db_client.database("sitemade").collection("webpages");
+
+    webpages_collection
+        .update_one(
+            mongodb::bson::doc! { "_id": website_id },
+            mongodb::bson::doc! { "$set": { "alternates": mongodb::bson::to_bson(alternates)? } },
+            None,
+        )
+        .await?;
+
+    if !languages.is_empty() {
+        let domains_collection: mongodb::Collection<DatabaseDomain> =
+            db_client.database("sitemade").collection("domains");
+
+        domains_collection
+            .update_one(
+                mongodb::bson::doc! { "_id": domain_id },
+                mongodb::bson::doc! { "$addToSet": { "languages": { "$each": languages } } },
+                None,
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn update_database_domain_probe(
+    db_client: mongodb::Client,
+    domain_id: mongodb::bson::oid::ObjectId,
+    probe: &DatabaseProbe,
+) -> Result<(), ScrapperError> {
+    let domains_collection: mongodb::Collection<DatabaseDomain> =
+        db_client.database("sitemade").collection("domains");
+
+    domains_collection
+        .update_one(
+            mongodb::bson::doc! { "_id": domain_id },
+            mongodb::bson::doc! { "$set": { "probe": mongodb::bson::to_bson(probe)? } },
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn update_database_domain_dns(
+    db_client: mongodb::Client,
+    domain_id: mongodb::bson::oid::ObjectId,
+    dns: &DatabaseDnsRecords,
+) -> Result<(), ScrapperError> {
+    let domains_collection: mongodb::Collection<DatabaseDomain> =
+        db_client.database("sitemade").collection("domains");
+
+    domains_collection
+        .update_one(
+            mongodb::bson::doc! { "_id": domain_id },
+            mongodb::bson::doc! { "$set": { "dns": mongodb::bson::to_bson(dns)? } },
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn update_database_domain_technologies(
+    db_client: mongodb::Client,
+    domain_id: mongodb::bson::oid::ObjectId,
+    technologies: &Vec<DatabaseWebTechnology>,
+) -> Result<(), ScrapperError> {
+    let domains_collection: mongodb::Collection<DatabaseDomain> =
+        db_client.database("sitemade").collection("domains");
+
+    domains_collection
+        .update_one(
+            mongodb::bson::doc! { "_id": domain_id },
+            mongodb::bson::doc! { "$set": { "technologies": mongodb::bson::to_bson(technologies)? } },
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn update_database_domain_geo(
+    db_client: mongodb::Client,
+    domain_id: mongodb::bson::oid::ObjectId,
+    geo: &Vec<DatabaseGeoIp>,
+) -> Result<(), ScrapperError> {
+    let domains_collection: mongodb::Collection<DatabaseDomain> =
+        db_client.database("sitemade").collection("domains");
+
+    domains_collection
+        .update_one(
+            mongodb::bson::doc! { "_id": domain_id },
+            mongodb::bson::doc! { "$set": { "geo": mongodb::bson::to_bson(geo)? } },
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn update_database_domain_providers(
+    db_client: mongodb::Client,
+    domain_id: mongodb::bson::oid::ObjectId,
+    hosting: &Option<DatabaseProviderMatch>,
+    cdn: &Option<DatabaseProviderMatch>,
+) -> Result<(), ScrapperError> {
+    let domains_collection: mongodb::Collection<DatabaseDomain> =
+        db_client.database("sitemade").collection("domains");
+
+    domains_collection
+        .update_one(
+            mongodb::bson::doc! { "_id": domain_id },
+            mongodb::bson::doc! { "$set": {
+                "hosting": mongodb::bson::to_bson(hosting)?,
+                "cdn": mongodb::bson::to_bson(cdn)?,
+            } },
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_database_job(db_client: mongodb::Client, name: &str) -> Result<Option<DatabaseJob>, ScrapperError> {
+    let jobs_collection: mongodb::Collection<DatabaseJob> =
+        db_client.database("sitemade").collection("jobs");
+
+    Ok(jobs_collection.find_one(mongodb::bson::doc! { "_id": name }, None).await?)
+}
+
+pub async fn save_database_job(db_client: mongodb::Client, job: &DatabaseJob) -> Result<(), ScrapperError> {
+    let jobs_collection: mongodb::Collection<DatabaseJob> =
+        db_client.database("sitemade").collection("jobs");
+
+    let options = mongodb::options::ReplaceOptions::builder().upsert(true).build();
+    jobs_collection
+        .replace_one(mongodb::bson::doc! { "_id": job._id.clone() }, job, options)
+        .await?;
+
+    Ok(())
+}
+
+fn domains_to_rank_filter(stale_before: DateTime<Utc>) -> mongodb::bson::Document {
+    mongodb::bson::doc! { "$or": [
+        { "pagerank_updated_at": null },
+        { "pagerank_updated_at": { "$lt": stale_before } },
+    ] }
+}
+
+pub async fn count_domains_to_rank(db_client: mongodb::Client, stale_before: DateTime<Utc>) -> Result<u64, ScrapperError> {
+    let domains_collection: mongodb::Collection<DatabaseDomain> =
+        db_client.database("sitemade").collection("domains");
+
+    Ok(domains_collection.count_documents(domains_to_rank_filter(stale_before), None).await?)
+}
+
+/*
+ * Siguiente lote de dominios sin pagerank o con pagerank anterior a
+ * stale_before, ordenados por _id a partir de after_id
+ */
+pub async fn get_domains_to_rank(
+    db_client: mongodb::Client,
+    stale_before: DateTime<Utc>,
+    after_id: Option<mongodb::bson::oid::ObjectId>,
+    limit: i64,
+) -> Result<Vec<DatabaseDomain>, ScrapperError> {
+    let domains_collection: mongodb::Collection<DatabaseDomain> =
+        db_client.database("sitemade").collection("domains");
+
+    let mut filter = domains_to_rank_filter(stale_before);
+    if let Some(after_id) = after_id {
+        filter.insert("_id", mongodb::bson::doc! { "$gt": after_id });
+    }
+    let options = mongodb::options::FindOptions::builder()
+        .sort(mongodb::bson::doc! { "_id": 1 })
+        .limit(limit)
+        .build();
+
+    let mut cursor = domains_collection.find(filter, options).await?;
+    let mut domains = vec![];
+    while let Some(domain) = cursor.next().await {
+        domains.push(domain?);
+    }
+
+    Ok(domains)
+}
+
+/*
+ * Paginas que usan una tecnologia (en el html o en las cabeceras) cuyo
+ * dominio tiene un pagerank mayor que min_pagerank. El pagerank se toma
+ * del dominio con $lookup.
+ */
+pub async fn find_webpages_by_technology(
+    db_client: mongodb::Client,
+    technology: &str,
+    min_pagerank: f64,
+) -> Result<Vec<DatabaseTechnologyMatch>, ScrapperError> {
+    let webpages_collection: mongodb::Collection<DatabaseWebpage> =
+        db_client.database("sitemade").collection("webpages");
+
+    let pipeline = vec![
+        mongodb::bson::doc! { "$match": { "$or": [
+            { "technologies.name": technology },
+            { "headers.name": technology },
+        ] } },
+        mongodb::bson::doc! { "$lookup": {
+            "from": "domains",
+            "localField": "domain_id",
+            "foreignField": "_id",
+            "as": "domain",
+        } },
+        mongodb::bson::doc! { "$unwind": "$domain" },
+        mongodb::bson::doc! { "$match": { "domain.pagerank": { "$gt": min_pagerank } } },
+        mongodb::bson::doc! { "$sort": { "domain.pagerank": -1 } },
+        mongodb::bson::doc! { "$project": {
+            "_id": 0,
+            "url": 1,
+            "host": "$domain.host",
+            "pagerank": "$domain.pagerank",
+        } },
+    ];
+
+    let mut cursor = webpages_collection.aggregate(pipeline, None).await?;
+    let mut matches = vec![];
+    while let Some(document) = cursor.next().await {
+        matches.push(mongodb::bson::from_document(document?)?);
+    }
+
+    Ok(matches)
+}
+
+/*
+ * Migracion: corrige la prioridad de las webpages que tenian su propia copia
+ * del pagerank usando el del dominio, y borra el campo. Devuelve cuantas
+ * webpages se han migrado.
+ */
+pub async fn migrate_webpages_pagerank(
+    db_client: mongodb::Client,
+    priority_config: &PriorityConfig,
+) -> Result<u64, ScrapperError> {
+    let webpages_collection: mongodb::Collection<DatabaseWebpage> =
+        db_client.database("sitemade").collection("webpages");
+
+    let domain_ids = webpages_collection
+        .distinct("domain_id", mongodb::bson::doc! { "pagerank": { "$exists": true } }, None)
+        .await?;
+
+    let mut migrated = 0;
+    for domain_id in domain_ids {
+        let Some(domain_id) = domain_id.as_object_id() else {
+            continue;
+        };
+        let domain_pagerank = get_database_domain_pagerank(db_client.clone(), domain_id).await?.unwrap_or(0.0);
+
+        let result = webpages_collection
+            .update_many(
+                mongodb::bson::doc! { "domain_id": domain_id, "pagerank": { "$exists": true } },
+                vec![
+                    mongodb::bson::doc! { "$set": {
+                        "priority": { "$add": [
+                            { "$ifNull": ["$priority", 0.0] },
+                            { "$multiply": [
+                                priority_config.pagerank_weight,
+                                { "$subtract": [domain_pagerank, { "$ifNull": ["$pagerank", 0.0] }] },
+                            ] },
+                        ] },
+                    } },
+                    mongodb::bson::doc! { "$unset": "pagerank" },
+                    queue_priority_stage(priority_config),
+                ],
+                None,
+            )
+            .await?;
+        migrated += result.modified_count;
+    }
+
+    Ok(migrated)
+}
+
+/*
+ * Comprobacion de consistencia: webpages que conservan el pagerank antiguo
+ * (y si coincide o no con el del dominio) y webpages cuyo dominio no existe
+ */
+pub async fn check_pagerank_consistency(
+    db_client: mongodb::Client,
+) -> Result<Vec<DatabasePagerankDivergence>, ScrapperError> {
+    let webpages_collection: mongodb::Collection<DatabaseWebpage> =
+        db_client.database("sitemade").collection("webpages");
+
+    let pipeline = vec![
+        mongodb::bson::doc! { "$lookup": {
+            "from": "domains",
+            "localField": "domain_id",
+            "foreignField": "_id",
+            "as": "domain",
+        } },
+        mongodb::bson::doc! { "$match": { "$or": [
+            { "pagerank": { "$exists": true } },
+            { "domain": { "$size": 0 } },
+        ] } },
+        mongodb::bson::doc! { "$project": {
+            "_id": 0,
+            "url": 1,
+            "webpage_pagerank": "$pagerank",
+            "domain_pagerank": { "$first": "$domain.pagerank" },
+            "domain_exists": { "$gt": [{ "$size": "$domain" }, 0] },
+        } },
+    ];
+
+    let mut cursor = webpages_collection.aggregate(pipeline, None).await?;
+    let mut divergences = vec![];
+    while let Some(document) = cursor.next().await {
+        let document = document?;
+        let pagerank = |field: &str| document.get(field).and_then(mongodb::bson::Bson::as_f64);
+        let webpage_pagerank = pagerank("webpage_pagerank");
+        let domain_pagerank = pagerank("domain_pagerank");
+        let domain_exists = document.get_bool("domain_exists").unwrap_or_default();
+
+        if let Some(reason) = pagerank_divergence(webpage_pagerank, domain_pagerank, domain_exists) {
+            divergences.push(DatabasePagerankDivergence {
+                url: document.get_str("url").unwrap_or_default().to_string(),
+                webpage_pagerank,
+                domain_pagerank,
+                reason: reason.to_string(),
+            });
+        }
+    }
+
+    Ok(divergences)
+}
+
+/*
+ * Motivo por el que una webpage no es consistente con su dominio, o None
+ * si lo es: el dominio no existe, la webpage conserva una copia del
+ * pagerank del dominio o conserva un pagerank que ya no coincide
+ */
+fn pagerank_divergence(
+    webpage_pagerank: Option<f64>,
+    domain_pagerank: Option<f64>,
+    domain_exists: bool,
+) -> Option<&'static str> {
+    if !domain_exists {
+        return Some("missing_domain");
+    }
+
+    match webpage_pagerank {
+        Some(pagerank) if Some(pagerank) == domain_pagerank => Some("duplicated_pagerank"),
+        Some(_) => Some("stale_pagerank"),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::FetchError;
+    use chrono::SubsecRound;
+
+    #[test]
+    fn pagerank_divergences_are_classified() {
+        assert_eq!(pagerank_divergence(Some(0.4), Some(0.4), false), Some("missing_domain"));
+        assert_eq!(pagerank_divergence(None, None, false), Some("missing_domain"));
+        assert_eq!(pagerank_divergence(Some(0.4), Some(0.4), true), Some("duplicated_pagerank"));
+        assert_eq!(pagerank_divergence(Some(0.4), Some(0.7), true), Some("stale_pagerank"));
+        assert_eq!(pagerank_divergence(Some(0.4), None, true), Some("stale_pagerank"));
+        assert_eq!(pagerank_divergence(None, Some(0.7), true), None);
+    }
+
+    #[test]
+    fn failure_with_retry_reschedules_the_page() {
+        let now = chrono::Utc::now();
+        let retry_at = now + chrono::Duration::minutes(30);
+        let error = ScrapperError::Fetch(FetchError::ServerError(503));
+
+        let update = failure_update(2, &error, Some(retry_at), now);
+
+        assert_eq!(update.get_i32("failed_attempts").unwrap(), 2);
+        assert_eq!(update.get_str("last_error").unwrap(), "http_5xx: error de servidor http 503");
+        assert_eq!(update.get_datetime("next_scrap_at").unwrap().to_chrono(), retry_at.trunc_subsecs(3));
+        assert!(!update.contains_key("scrappeable"));
+    }
+
+    #[test]
+    fn failure_without_retry_stops_the_page() {
+        let now = chrono::Utc::now();
+        let error = ScrapperError::Fetch(FetchError::ClientError(404));
+
+        let update = failure_update(1, &error, None, now);
+
+        assert!(!update.get_bool("scrappeable").unwrap());
+        assert!(!update.contains_key("next_scrap_at"));
+        assert_eq!(update.get_str("last_error").unwrap(), "http_4xx: error de cliente http 404");
+    }
 }
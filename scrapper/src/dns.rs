@@ -0,0 +1,426 @@
+use hickory_resolver::config::{NameServerConfig, NameServerConfigGroup, Protocol, ResolverConfig, ResolverOpts};
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::proto::op::ResponseCode;
+use hickory_resolver::proto::rr::{RData, RecordType};
+use hickory_resolver::TokioAsyncResolver;
+use hyper::client::connect::dns::Name;
+use serde::Deserialize;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use crate::db::{DatabaseDnsRecords, DatabaseDomain, DatabaseMxRecord};
+use crate::error::ScrapperError;
+
+//Longitud maxima de una cadena de CNAME antes de darla por rota
+const MAX_CNAME_CHAIN: usize = 10;
+
+/*
+ * Configuracion del resolver dns. Sin nameservers se usa la configuracion
+ * del sistema (/etc/resolv.conf). Los nameservers son "ip" o "ip:puerto".
+ * Los registros de un dominio se reutilizan durante refresh_hours.
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DnsConfig {
+    pub nameservers: Vec<String>,
+    pub timeout_secs: u64,
+    pub attempts: usize,
+    pub refresh_hours: i64,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            nameservers: vec![],
+            timeout_secs: 5,
+            attempts: 2,
+            refresh_hours: 24,
+        }
+    }
+}
+
+pub fn build_resolver(config: &DnsConfig) -> Result<TokioAsyncResolver, ScrapperError> {
+    let (resolver_config, mut options) = if config.nameservers.is_empty() {
+        hickory_resolver::system_conf::read_system_conf()
+            .map_err(|e| ScrapperError::Config(format!("resolver del sistema: {}", e)))?
+    } else {
+        let mut nameservers = vec![];
+        for nameserver in &config.nameservers {
+            let address = nameserver
+                .parse::<SocketAddr>()
+                .or_else(|_| nameserver.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                .map_err(|e| ScrapperError::Config(format!("nameserver {} invalido: {}", nameserver, e)))?;
+            nameservers.push(NameServerConfig::new(address, Protocol::Udp));
+            nameservers.push(NameServerConfig::new(address, Protocol::Tcp));
+        }
+
+        (
+            ResolverConfig::from_parts(None, vec![], NameServerConfigGroup::from(nameservers)),
+            ResolverOpts::default(),
+        )
+    };
+    options.timeout = Duration::from_secs(config.timeout_secs);
+    options.attempts = config.attempts;
+
+    Ok(TokioAsyncResolver::tokio(resolver_config, options))
+}
+
+/*
+ * Resolver de las descargas http. Usa el mismo resolver configurado que los
+ * registros del dominio, y asi un fallo de resolucion llega como
+ * ResolveError dentro del error de reqwest.
+ */
+pub struct HttpResolver(pub TokioAsyncResolver);
+
+impl reqwest::dns::Resolve for HttpResolver {
+    fn resolve(&self, name: Name) -> reqwest::dns::Resolving {
+        let resolver = self.0.clone();
+        Box::pin(async move {
+            let lookup = resolver.lookup_ip(name.as_str()).await?;
+            let addresses: Vec<SocketAddr> = lookup.iter().map(|ip| SocketAddr::new(ip, 0)).collect();
+            let addresses: reqwest::dns::Addrs = Box::new(addresses.into_iter());
+
+            Ok(addresses)
+        })
+    }
+}
+
+/*
+ * Indica si hay que volver a resolver el dominio o valen los registros que
+ * tiene guardados
+ */
+pub fn needs_refresh(config: &DnsConfig, domain: &DatabaseDomain) -> bool {
+    match &domain.dns {
+        Some(records) => chrono::Utc::now() - records.resolved_at > chrono::Duration::hours(config.refresh_hours),
+        None => true,
+    }
+}
+
+/*
+ * Obtiene los registros A, AAAA, CNAME (la cadena completa), MX, NS, TXT y
+ * CAA del host, y el TXT de _dmarc. Las consultas se hacen a la vez. Un tipo
+ * sin registros queda vacio; si falla la resolucion de todos los tipos se
+ * devuelve el error.
+ */
+pub async fn collect_records(resolver: &TokioAsyncResolver, host: &str) -> Result<DatabaseDnsRecords, ScrapperError> {
+    let dmarc_host = format!("_dmarc.{}", host);
+    let (a, aaaa, ns, txt, caa, dmarc_txt, mx, cname) = futures_util::join!(
+        records(resolver, host, RecordType::A),
+        records(resolver, host, RecordType::AAAA),
+        records(resolver, host, RecordType::NS),
+        records(resolver, host, RecordType::TXT),
+        records(resolver, host, RecordType::CAA),
+        records(resolver, &dmarc_host, RecordType::TXT),
+        mx_records(resolver, host),
+        cname_chain(resolver, host),
+    );
+
+    let mut failures = vec![];
+    let a = found(a, &mut failures);
+    let aaaa = found(aaaa, &mut failures);
+    let ns = found(ns, &mut failures);
+    let txt = found(txt, &mut failures);
+    let caa = found(caa, &mut failures);
+    let dmarc_txt = found(dmarc_txt, &mut failures);
+    let mx = found(mx, &mut failures);
+    let cname = found(cname, &mut failures);
+
+    //Si no hay ningun registro y alguna consulta ha fallado no hay nada que guardar
+    let empty = a.is_empty()
+        && aaaa.is_empty()
+        && ns.is_empty()
+        && txt.is_empty()
+        && mx.is_empty()
+        && cname.is_empty();
+    if empty && !failures.is_empty() {
+        return Err(ScrapperError::Dns(format!("{}: {}", host, failures.join(", "))));
+    }
+
+    let spf = txt.iter().find(|record| record.to_lowercase().starts_with("v=spf1")).cloned();
+    let dmarc = dmarc_txt.into_iter().find(|record| record.to_lowercase().starts_with("v=dmarc1"));
+    let verifications = txt
+        .iter()
+        .filter(|record| {
+            let record = record.to_lowercase();
+            record.contains("-verification=")
+                || record.contains("-site-verification")
+                || record.contains("-domain-verification")
+        })
+        .cloned()
+        .collect();
+
+    Ok(DatabaseDnsRecords {
+        a,
+        aaaa,
+        cname,
+        mx,
+        ns,
+        txt,
+        spf,
+        dmarc,
+        verifications,
+        caa,
+        resolved_at: chrono::Utc::now(),
+    })
+}
+
+async fn records(resolver: &TokioAsyncResolver, name: &str, record_type: RecordType) -> Result<Vec<String>, ResolveError> {
+    let lookup = resolver.lookup(name, record_type).await?;
+
+    Ok(lookup
+        .record_iter()
+        .filter(|record| record.record_type() == record_type)
+        .filter_map(|record| record.data())
+        .map(|data| match data {
+            RData::A(ip) => ip.to_string(),
+            RData::AAAA(ip) => ip.to_string(),
+            RData::NS(name) => name_to_string(&name.to_utf8()),
+            RData::TXT(txt) => txt
+                .txt_data()
+                .iter()
+                .map(|part| String::from_utf8_lossy(part).into_owned())
+                .collect::<String>(),
+            data => data.to_string(),
+        })
+        .collect())
+}
+
+async fn mx_records(resolver: &TokioAsyncResolver, host: &str) -> Result<Vec<DatabaseMxRecord>, ResolveError> {
+    let lookup = resolver.mx_lookup(host).await?;
+
+    let mut mx: Vec<DatabaseMxRecord> = lookup
+        .iter()
+        .map(|record| DatabaseMxRecord {
+            preference: record.preference(),
+            exchange: name_to_string(&record.exchange().to_utf8()),
+        })
+        .collect();
+    mx.sort_by_key(|record| record.preference);
+
+    Ok(mx)
+}
+
+/*
+ * Sigue la cadena de CNAME desde el host. Un fallo a mitad de cadena deja
+ * la parte ya resuelta.
+ */
+async fn cname_chain(resolver: &TokioAsyncResolver, host: &str) -> Result<Vec<String>, ResolveError> {
+    let mut cname: Vec<String> = vec![];
+    let mut name = host.to_string();
+    while cname.len() < MAX_CNAME_CHAIN {
+        let lookup = match resolver.lookup(name.as_str(), RecordType::CNAME).await {
+            Ok(lookup) => lookup,
+            Err(e) if cname.is_empty() => return Err(e),
+            Err(_) => break,
+        };
+        let target = lookup.record_iter().find_map(|record| match record.data() {
+            Some(RData::CNAME(target)) => Some(name_to_string(&target.to_utf8())),
+            _ => None,
+        });
+
+        match target {
+            Some(target) if !cname.contains(&target) => {
+                cname.push(target.clone());
+                name = target;
+            }
+            _ => break,
+        }
+    }
+
+    Ok(cname)
+}
+
+/*
+ * No tener registros de un tipo no es un error, pero un SERVFAIL o un
+ * REFUSED si: el servidor no ha contestado
+ */
+fn found<T: Default>(result: Result<T, ResolveError>, failures: &mut Vec<String>) -> T {
+    match result {
+        Ok(records) => records,
+        Err(e) => {
+            let no_records = matches!(
+                e.kind(),
+                ResolveErrorKind::NoRecordsFound { response_code: ResponseCode::NoError | ResponseCode::NXDomain, .. }
+            );
+            if !no_records {
+                failures.push(e.to_string());
+            }
+            T::default()
+        }
+    }
+}
+
+fn name_to_string(name: &str) -> String {
+    name.trim_end_matches('.').to_lowercase()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use hickory_resolver::proto::op::{Message, MessageType};
+    use hickory_resolver::proto::rr::rdata::{A, AAAA, CAA, CNAME, MX, NS, TXT};
+    use hickory_resolver::proto::rr::{Name, Record};
+
+    fn name(name: &str) -> Name {
+        Name::from_ascii(name).unwrap()
+    }
+
+    fn txt(text: &str) -> RData {
+        RData::TXT(TXT::new(vec![text.to_string()]))
+    }
+
+    /*
+     * Servidor dns local que contesta con los registros de la zona. Los
+     * nombres sin registros dan NXDOMAIN y los que empiezan por servfail
+     * dan SERVFAIL.
+     */
+    pub(crate) async fn serve(zone: Vec<(&str, RData)>) -> DnsConfig {
+        let zone: Vec<Record> = zone
+            .into_iter()
+            .map(|(host, data)| Record::from_rdata(name(host), 300, data))
+            .collect();
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 4096];
+            while let Ok((len, peer)) = socket.recv_from(&mut buffer).await {
+                let request = Message::from_vec(&buffer[..len]).unwrap();
+                let query = request.queries()[0].clone();
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_op_code(request.op_code())
+                    .set_recursion_desired(true)
+                    .set_recursion_available(true)
+                    .add_query(query.clone());
+
+                let known = zone.iter().any(|record| record.name() == query.name());
+                if query.name().to_ascii().starts_with("servfail") {
+                    response.set_response_code(ResponseCode::ServFail);
+                } else if !known {
+                    response.set_response_code(ResponseCode::NXDomain);
+                }
+                for record in zone
+                    .iter()
+                    .filter(|record| record.name() == query.name() && record.record_type() == query.query_type())
+                {
+                    response.add_answer(record.clone());
+                }
+
+                let _ = socket.send_to(&response.to_vec().unwrap(), peer).await;
+            }
+        });
+
+        DnsConfig {
+            nameservers: vec![address],
+            timeout_secs: 1,
+            attempts: 1,
+            ..DnsConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn collects_each_record_type() {
+        let config = serve(vec![
+            ("acme.test.", RData::A(A::new(192, 0, 2, 10))),
+            ("acme.test.", RData::AAAA(AAAA::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 10))),
+            ("acme.test.", RData::NS(NS(name("NS1.Provider.test.")))),
+            ("acme.test.", RData::MX(MX::new(20, name("mx2.acme.test.")))),
+            ("acme.test.", RData::MX(MX::new(10, name("mx1.acme.test.")))),
+            ("acme.test.", txt("v=spf1 include:_spf.google.com ~all")),
+            ("acme.test.", txt("google-site-verification=abc123")),
+            ("acme.test.", txt("MS=ms12345")),
+            ("acme.test.", RData::CAA(CAA::new_issue(false, Some(name("letsencrypt.org")), vec![]))),
+            ("_dmarc.acme.test.", txt("v=DMARC1; p=reject")),
+        ])
+        .await;
+        let resolver = build_resolver(&config).unwrap();
+
+        let records = collect_records(&resolver, "acme.test").await.unwrap();
+
+        assert_eq!(records.a, vec!["192.0.2.10"]);
+        assert_eq!(records.aaaa, vec!["2001:db8::a"]);
+        assert_eq!(records.ns, vec!["ns1.provider.test"]);
+        let mx: Vec<(u16, &str)> = records.mx.iter().map(|mx| (mx.preference, mx.exchange.as_str())).collect();
+        assert_eq!(mx, vec![(10, "mx1.acme.test"), (20, "mx2.acme.test")]);
+        assert_eq!(records.txt.len(), 3);
+        assert_eq!(records.spf.as_deref(), Some("v=spf1 include:_spf.google.com ~all"));
+        assert_eq!(records.verifications, vec!["google-site-verification=abc123"]);
+        assert_eq!(records.dmarc.as_deref(), Some("v=DMARC1; p=reject"));
+        assert_eq!(records.caa.len(), 1);
+        assert!(records.caa[0].contains("letsencrypt.org"));
+        assert!(records.cname.is_empty());
+    }
+
+    #[tokio::test]
+    async fn follows_the_cname_chain() {
+        let config = serve(vec![
+            ("www.acme.test.", RData::CNAME(CNAME(name("acme.edgekey.test.")))),
+            ("acme.edgekey.test.", RData::CNAME(CNAME(name("e1.akamaiedge.test.")))),
+            ("e1.akamaiedge.test.", RData::A(A::new(192, 0, 2, 20))),
+            ("loop.test.", RData::CNAME(CNAME(name("loop2.test.")))),
+            ("loop2.test.", RData::CNAME(CNAME(name("loop.test.")))),
+        ])
+        .await;
+        let resolver = build_resolver(&config).unwrap();
+
+        let records = collect_records(&resolver, "www.acme.test").await.unwrap();
+        assert_eq!(records.cname, vec!["acme.edgekey.test", "e1.akamaiedge.test"]);
+
+        //Una cadena circular se corta al repetirse un nombre
+        let records = collect_records(&resolver, "loop.test").await.unwrap();
+        assert_eq!(records.cname, vec!["loop2.test", "loop.test"]);
+    }
+
+    #[tokio::test]
+    async fn missing_records_are_not_an_error() {
+        let config = serve(vec![("acme.test.", RData::A(A::new(192, 0, 2, 10)))]).await;
+        let resolver = build_resolver(&config).unwrap();
+
+        //Respuesta vacia para los tipos que no tiene
+        let records = collect_records(&resolver, "acme.test").await.unwrap();
+        assert_eq!(records.a, vec!["192.0.2.10"]);
+        assert!(records.mx.is_empty() && records.txt.is_empty() && records.spf.is_none() && records.dmarc.is_none());
+
+        //NXDOMAIN
+        let records = collect_records(&resolver, "missing.test").await.unwrap();
+        assert!(records.a.is_empty() && records.ns.is_empty() && records.cname.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_resolution_is_an_error() {
+        let config = serve(vec![]).await;
+        let resolver = build_resolver(&config).unwrap();
+
+        let result = collect_records(&resolver, "servfail.test").await;
+
+        assert!(matches!(result, Err(ScrapperError::Dns(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn stored_records_are_reused_until_they_expire() {
+        let config = DnsConfig::default();
+        let resolved = |hours: i64| DatabaseDnsRecords {
+            a: vec![],
+            aaaa: vec![],
+            cname: vec![],
+            mx: vec![],
+            ns: vec![],
+            txt: vec![],
+            spf: None,
+            dmarc: None,
+            verifications: vec![],
+            caa: vec![],
+            resolved_at: chrono::Utc::now() - chrono::Duration::hours(hours),
+        };
+        let mut domain = DatabaseDomain::new("acme.test".to_string());
+        assert!(needs_refresh(&config, &domain));
+
+        domain.dns = Some(resolved(1));
+        assert!(!needs_refresh(&config, &domain));
+
+        domain.dns = Some(resolved(25));
+        assert!(needs_refresh(&config, &domain));
+    }
+}
@@ -0,0 +1,118 @@
+use publicsuffix::{List, Psl};
+
+//Segundos niveles habituales bajo dominios de pais (com.ar, gob.es, co.uk...)
+//que se usan cuando no hay lista de sufijos publicos cargada
+const COMMON_SECOND_LEVELS: [&str; 10] = [
+    "com", "net", "org", "gob", "gov", "edu", "co", "ac", "mil", "nom",
+];
+
+/*
+ * Lista de sufijos publicos para calcular el dominio registrable
+ * (eTLD+1) de un host. Si no se indica fichero se usa una heuristica.
+ */
+pub struct SuffixList {
+    list: Option<List>,
+}
+
+impl SuffixList {
+    pub fn load(path: Option<&str>) -> Self {
+        let list = path.and_then(|path| match std::fs::read_to_string(path) {
+            Ok(content) => match content.parse::<List>() {
+                Ok(list) => Some(list),
+                Err(e) => {
+                    warn!("Lista de sufijos publicos {} invalida: {:?}", path, e);
+                    None
+                }
+            },
+            Err(e) => {
+                warn!("No se pudo leer la lista de sufijos publicos {}: {:?}", path, e);
+                None
+            }
+        });
+
+        Self { list }
+    }
+
+    pub fn registrable_domain(&self, host: &str) -> String {
+        let host = normalize_host(host);
+
+        if let Some(list) = &self.list {
+            if let Some(domain) = list.domain(host.as_bytes()) {
+                return String::from_utf8_lossy(domain.as_bytes()).to_string();
+            }
+        }
+
+        let labels: Vec<&str> = host.split('.').collect();
+        if labels.len() <= 2 {
+            return host;
+        }
+
+        let tld = labels[labels.len() - 1];
+        let second = labels[labels.len() - 2];
+        let take = if tld.len() == 2 && COMMON_SECOND_LEVELS.contains(&second) {
+            3
+        } else {
+            2
+        };
+
+        labels[labels.len() - take..].join(".")
+    }
+}
+
+/*
+ * Host en minusculas, sin punto final ni prefijo www.
+ * Es el formato con el que se guardan los dominios en la base de datos
+ */
+pub fn normalize_host(host: &str) -> String {
+    let host = host.trim().trim_end_matches('.').to_lowercase();
+
+    match host.strip_prefix("www.") {
+        Some(host) => host.to_string(),
+        None => host,
+    }
+}
+
+pub fn host_from_url(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return None;
+    }
+
+    url.host_str().map(normalize_host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registrable_domain_heuristic() {
+        let suffixes = SuffixList::load(None);
+
+        assert_eq!(suffixes.registrable_domain("example.com"), "example.com");
+        assert_eq!(suffixes.registrable_domain("blog.example.com"), "example.com");
+        assert_eq!(suffixes.registrable_domain("a.b.example.es"), "example.es");
+        assert_eq!(suffixes.registrable_domain("shop.example.com.ar"), "example.com.ar");
+        assert_eq!(suffixes.registrable_domain("sede.madrid.gob.es"), "madrid.gob.es");
+        assert_eq!(suffixes.registrable_domain("www.bbc.co.uk"), "bbc.co.uk");
+        //Los dominios de pais con segundos niveles poco habituales no se reconocen
+        assert_eq!(suffixes.registrable_domain("foo.example.ltd.uk"), "ltd.uk");
+    }
+
+    #[test]
+    fn registrable_domain_normalizes_the_host() {
+        let suffixes = SuffixList::load(None);
+
+        assert_eq!(suffixes.registrable_domain("WWW.Example.COM."), "example.com");
+        assert_eq!(suffixes.registrable_domain("localhost"), "localhost");
+    }
+
+    #[test]
+    fn host_from_url_only_accepts_http() {
+        assert_eq!(host_from_url("https://www.Example.com/path"), Some("example.com".to_string()));
+        assert_eq!(host_from_url("http://blog.example.com:8080/"), Some("blog.example.com".to_string()));
+        assert_eq!(host_from_url("mailto:info@example.com"), None);
+        assert_eq!(host_from_url("/relative/path"), None);
+    }
+}
@@ -0,0 +1,403 @@
+use std::error::Error as StdError;
+
+/*
+ * Error general del scrapper. Los errores de una pagina no deben tumbar
+ * el bucle de scrap_all, que los registra y sigue con la siguiente.
+ */
+#[derive(Debug, thiserror::Error)]
+pub enum ScrapperError {
+    #[error(transparent)]
+    Fetch(#[from] FetchError),
+    #[error("error de base de datos: {0}")]
+    Db(#[from] mongodb::error::Error),
+    #[error("error de dns: {0}")]
+    Dns(String),
+    #[error("error al analizar {0}")]
+    Parse(String),
+    #[error("error de configuracion: {0}")]
+    Config(String),
+}
+
+impl ScrapperError {
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ScrapperError::Fetch(e) => e.is_transient(),
+            ScrapperError::Db(_) | ScrapperError::Dns(_) => true,
+            ScrapperError::Parse(_) | ScrapperError::Config(_) => false,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ScrapperError::Fetch(e) => e.kind(),
+            ScrapperError::Db(_) => "db",
+            ScrapperError::Dns(_) => "dns",
+            ScrapperError::Parse(_) => "parse",
+            ScrapperError::Config(_) => "config",
+        }
+    }
+
+    /*
+     * Errores propios de la pagina, que cuentan como fallo de la misma.
+     * Los de base de datos o configuracion no son culpa de la pagina.
+     */
+    pub fn is_page_error(&self) -> bool {
+        !matches!(self, ScrapperError::Db(_) | ScrapperError::Config(_))
+    }
+}
+
+impl From<bson::ser::Error> for ScrapperError {
+    fn from(e: bson::ser::Error) -> Self {
+        ScrapperError::Db(e.into())
+    }
+}
+
+impl From<bson::de::Error> for ScrapperError {
+    fn from(e: bson::de::Error) -> Self {
+        ScrapperError::Db(e.into())
+    }
+}
+
+impl From<reqwest::Error> for ScrapperError {
+    fn from(e: reqwest::Error) -> Self {
+        ScrapperError::Fetch(e.into())
+    }
+}
+
+/*
+ * Errores al descargar una pagina, clasificados para decidir si se reintenta
+ */
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum FetchError {
+    #[error("error de dns: {0}")]
+    Dns(String),
+    #[error("error de conexion: {0}")]
+    Connect(String),
+    #[error("error de tls: {0}")]
+    Tls(String),
+    #[error("timeout")]
+    Timeout,
+    #[error("error de cliente http {0}")]
+    ClientError(u16),
+    #[error("error de servidor http {0}")]
+    ServerError(u16),
+    #[error("demasiadas peticiones (retry-after = {retry_after:?})")]
+    RateLimited { retry_after: Option<u64> },
+    #[error("error al decodificar el cuerpo: {0}")]
+    BodyDecode(String),
+    #[error("cuerpo demasiado grande ({0} bytes)")]
+    BodyTooLarge(usize),
+    #[error("demasiadas redirecciones ({0})")]
+    TooManyRedirects(usize),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl FetchError {
+    pub fn from_status(status: reqwest::StatusCode, headers: &reqwest::header::HeaderMap) -> Option<Self> {
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = headers
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_retry_after);
+
+            return Some(FetchError::RateLimited { retry_after });
+        }
+
+        if status.is_client_error() {
+            Some(FetchError::ClientError(status.as_u16()))
+        } else if status.is_server_error() {
+            Some(FetchError::ServerError(status.as_u16()))
+        } else {
+            None
+        }
+    }
+
+    /*
+     * Errores que pueden desaparecer por si solos y merece la pena reintentar
+     */
+    pub fn is_transient(&self) -> bool {
+        match self {
+            FetchError::Dns(_)
+            | FetchError::Connect(_)
+            | FetchError::Timeout
+            | FetchError::ServerError(_)
+            | FetchError::RateLimited { .. }
+            | FetchError::Other(_) => true,
+            FetchError::ClientError(status) => *status == 408,
+            FetchError::Tls(_)
+            | FetchError::BodyDecode(_)
+            | FetchError::BodyTooLarge(_)
+            | FetchError::TooManyRedirects(_) => false,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FetchError::Dns(_) => "dns",
+            FetchError::Connect(_) => "connect",
+            FetchError::Tls(_) => "tls",
+            FetchError::Timeout => "timeout",
+            FetchError::ClientError(_) => "http_4xx",
+            FetchError::ServerError(_) => "http_5xx",
+            FetchError::RateLimited { .. } => "http_429",
+            FetchError::BodyDecode(_) => "body_decode",
+            FetchError::BodyTooLarge(_) => "body_too_large",
+            FetchError::TooManyRedirects(_) => "too_many_redirects",
+            FetchError::Other(_) => "other",
+        }
+    }
+}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            return FetchError::Timeout;
+        }
+        if let Some(status) = e.status() {
+            if let Some(error) = FetchError::from_status(status, &reqwest::header::HeaderMap::new()) {
+                return error;
+            }
+        }
+
+        let mut messages = vec![e.to_string()];
+        let mut source = e.source();
+        while let Some(error) = source {
+            messages.push(error.to_string());
+            source = error.source();
+        }
+        let message = messages.join(": ");
+
+        //reqwest no distingue dns ni tls dentro de los errores de conexion,
+        //se sabe por el tipo del error que los ha causado
+        if e.is_connect() {
+            if caused_by::<hickory_resolver::error::ResolveError>(&e) {
+                FetchError::Dns(message)
+            } else if caused_by::<native_tls::Error>(&e) {
+                FetchError::Tls(message)
+            } else {
+                FetchError::Connect(message)
+            }
+        } else if e.is_decode() || e.is_body() {
+            FetchError::BodyDecode(message)
+        } else {
+            FetchError::Other(message)
+        }
+    }
+}
+
+fn caused_by<T: StdError + 'static>(e: &reqwest::Error) -> bool {
+    let mut source = e.source();
+    while let Some(error) = source {
+        if error.is::<T>() {
+            return true;
+        }
+        source = error.source();
+    }
+
+    false
+}
+
+/*
+ * Retry-After en segundos o como fecha http
+ */
+fn parse_retry_after(value: &str) -> Option<u64> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(seconds);
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    let seconds = date.signed_duration_since(chrono::Utc::now()).num_seconds();
+
+    Some(seconds.max(0) as u64)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::dns;
+    use crate::http::{self, HttpConfig};
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+    use reqwest::StatusCode;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /*
+     * Descarga la url con un cliente de timeout corto que resuelve contra
+     * un dns local sin registros, y devuelve el error
+     */
+    async fn fetch_error(url: &str) -> FetchError {
+        let config = HttpConfig {
+            connect_timeout_secs: 1,
+            timeout_secs: 1,
+            ..HttpConfig::default()
+        };
+        let resolver = dns::build_resolver(&dns::tests::serve(vec![]).await).unwrap();
+        let client = http::build_client(&config, resolver).unwrap();
+
+        match http::fetch(&client, &config, url).await {
+            Ok(_) => panic!("{} no ha fallado", url),
+            Err(e) => e,
+        }
+    }
+
+    /*
+     * Servidor http local que contesta siempre lo mismo. Sin respuesta
+     * acepta la conexion y no contesta nunca.
+     */
+    pub(crate) async fn serve(response: Option<Vec<u8>>) -> u16 {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).await;
+                match &response {
+                    Some(response) => {
+                        let _ = stream.write_all(response).await;
+                    }
+                    None => tokio::time::sleep(std::time::Duration::from_secs(10)).await,
+                }
+            }
+        });
+
+        port
+    }
+
+    fn retry_after(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn page_errors_count_as_failures_and_the_rest_postpone() {
+        let fetch = ScrapperError::Fetch(FetchError::ClientError(404));
+        let dns = ScrapperError::Dns("acme.test: servfail".to_string());
+        let parse = ScrapperError::Parse("selector".to_string());
+        let config = ScrapperError::Config("config.yaml".to_string());
+        let db = ScrapperError::from(bson::ser::Error::UnsignedIntegerExceededRange(u64::MAX));
+
+        assert!(fetch.is_page_error() && dns.is_page_error() && parse.is_page_error());
+        assert!(!config.is_page_error() && !db.is_page_error());
+        assert_eq!(
+            [&fetch, &dns, &parse, &config, &db].map(|e| e.kind()),
+            ["http_4xx", "dns", "parse", "config", "db"]
+        );
+        assert!(!fetch.is_transient() && !parse.is_transient() && !config.is_transient());
+        assert!(dns.is_transient() && db.is_transient());
+    }
+
+    #[test]
+    fn fetch_errors_keep_their_kind() {
+        let transient = [
+            FetchError::Dns(String::new()),
+            FetchError::Connect(String::new()),
+            FetchError::Timeout,
+            FetchError::ServerError(502),
+            FetchError::RateLimited { retry_after: None },
+            FetchError::Other(String::new()),
+        ];
+        let permanent = [
+            FetchError::Tls(String::new()),
+            FetchError::ClientError(410),
+            FetchError::BodyDecode(String::new()),
+            FetchError::BodyTooLarge(1),
+            FetchError::TooManyRedirects(11),
+        ];
+
+        assert!(transient.iter().all(|e| e.is_transient()));
+        assert!(permanent.iter().all(|e| !e.is_transient()));
+        assert_eq!(ScrapperError::from(FetchError::Timeout).kind(), "timeout");
+    }
+
+    #[tokio::test]
+    async fn timeout_is_transient() {
+        let port = serve(None).await;
+
+        let error = fetch_error(&format!("http://127.0.0.1:{}/", port)).await;
+
+        assert!(matches!(error, FetchError::Timeout), "{:?}", error);
+        assert!(error.is_transient());
+    }
+
+    #[tokio::test]
+    async fn refused_connection_is_a_connect_error() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let error = fetch_error(&format!("http://127.0.0.1:{}/", port)).await;
+
+        assert!(matches!(error, FetchError::Connect(_)), "{:?}", error);
+        assert_eq!(error.kind(), "connect");
+        assert!(error.is_transient());
+    }
+
+    #[tokio::test]
+    async fn unknown_host_is_a_dns_error() {
+        let error = fetch_error("http://missing.test/").await;
+
+        assert!(matches!(error, FetchError::Dns(_)), "{:?}", error);
+        assert_eq!(error.kind(), "dns");
+        assert!(error.is_transient());
+    }
+
+    #[tokio::test]
+    async fn invalid_certificate_is_a_tls_error() {
+        let (key, certificate) = crate::tls::tests::self_signed("sitemade.test", 1, 90);
+        let port = crate::tls::tests::serve(key, certificate).await;
+
+        let error = fetch_error(&format!("https://127.0.0.1:{}/", port)).await;
+
+        assert!(matches!(error, FetchError::Tls(_)), "{:?}", error);
+        assert!(!error.is_transient());
+    }
+
+    #[tokio::test]
+    async fn http_errors_are_classified_by_status() {
+        let not_found = serve(Some("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".into())).await;
+        let unavailable = serve(Some("HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n".into())).await;
+        let limited = serve(Some("HTTP/1.1 429 Too Many Requests\r\nRetry-After: 120\r\nContent-Length: 0\r\n\r\n".into())).await;
+
+        let error = fetch_error(&format!("http://127.0.0.1:{}/", not_found)).await;
+        assert!(matches!(error, FetchError::ClientError(404)), "{:?}", error);
+        assert!(!error.is_transient());
+
+        let error = fetch_error(&format!("http://127.0.0.1:{}/", unavailable)).await;
+        assert!(matches!(error, FetchError::ServerError(503)), "{:?}", error);
+        assert!(error.is_transient());
+
+        let error = fetch_error(&format!("http://127.0.0.1:{}/", limited)).await;
+        assert!(matches!(error, FetchError::RateLimited { retry_after: Some(120) }), "{:?}", error);
+        assert!(error.is_transient());
+    }
+
+    #[test]
+    fn request_timeout_is_the_only_transient_client_error() {
+        let headers = HeaderMap::new();
+
+        let timeout = FetchError::from_status(StatusCode::REQUEST_TIMEOUT, &headers).unwrap();
+        let forbidden = FetchError::from_status(StatusCode::FORBIDDEN, &headers).unwrap();
+
+        assert!(timeout.is_transient());
+        assert!(!forbidden.is_transient());
+        assert!(FetchError::from_status(StatusCode::OK, &headers).is_none());
+    }
+
+    #[test]
+    fn retry_after_in_seconds_or_http_date() {
+        let rate_limited = |headers: &HeaderMap| match FetchError::from_status(StatusCode::TOO_MANY_REQUESTS, headers) {
+            Some(FetchError::RateLimited { retry_after }) => retry_after,
+            error => panic!("{:?}", error),
+        };
+        let in_two_minutes = (chrono::Utc::now() + chrono::Duration::seconds(120))
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string();
+
+        assert_eq!(rate_limited(&retry_after(" 30 ")), Some(30));
+        assert!(rate_limited(&retry_after(&in_two_minutes)).is_some_and(|seconds| (115..=120).contains(&seconds)));
+        assert_eq!(rate_limited(&retry_after("Wed, 21 Oct 2015 07:28:00 GMT")), Some(0));
+        assert_eq!(rate_limited(&retry_after("pronto")), None);
+        assert_eq!(rate_limited(&HeaderMap::new()), None);
+    }
+}
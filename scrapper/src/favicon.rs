@@ -0,0 +1,163 @@
+use base64::Engine;
+use serde::Deserialize;
+
+use crate::db::{DatabaseDomain, DatabaseFavicon};
+use crate::error::ScrapperError;
+use crate::http::{self, HttpConfig};
+
+//Python (base64.encodebytes) parte el base64 en lineas de 76 caracteres
+const BASE64_LINE: usize = 76;
+
+/*
+ * Hash del favicon del dominio. Se vuelve a descargar si cambia la url o si
+ * han pasado refresh_hours desde la ultima vez.
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FaviconConfig {
+    pub enabled: bool,
+    pub refresh_hours: i64,
+}
+
+impl Default for FaviconConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            refresh_hours: 168,
+        }
+    }
+}
+
+/*
+ * Hash como el de Shodan (http.favicon.hash): mmh3 de 32 bits con signo del
+ * base64 del fichero, con saltos de linea cada 76 caracteres y al final
+ */
+pub fn favicon_hash(bytes: &[u8]) -> i32 {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(bytes);
+    let mut wrapped = String::with_capacity(encoded.len() + encoded.len() / BASE64_LINE + 1);
+    for line in encoded.as_bytes().chunks(BASE64_LINE) {
+        wrapped.push_str(std::str::from_utf8(line).unwrap_or_default());
+        wrapped.push('\n');
+    }
+
+    //La lectura de un slice en memoria no puede fallar
+    murmur3::murmur3_32(&mut wrapped.as_bytes(), 0).unwrap_or_default() as i32
+}
+
+/*
+ * Indica si hay que descargar el favicon o vale el que ya tiene el dominio
+ */
+pub fn needs_refresh(config: &FaviconConfig, domain: &DatabaseDomain, url: &str) -> bool {
+    match &domain.favicon {
+        Some(favicon) => {
+            favicon.url != url || chrono::Utc::now() - favicon.fetched_at > chrono::Duration::hours(config.refresh_hours)
+        }
+        None => true,
+    }
+}
+
+/*
+ * Descarga el favicon y calcula su hash. Si no existe o no es una imagen
+ * devuelve None; no es un error del scrapeo.
+ */
+pub async fn fetch(
+    client: &reqwest::Client,
+    http_config: &HttpConfig,
+    url: &str,
+) -> Result<Option<DatabaseFavicon>, ScrapperError> {
+    let page = match http::fetch(client, http_config, url).await {
+        Ok(page) => page,
+        Err(e) => {
+            info!("     No se pudo descargar el favicon {}: {}", url, e);
+            return Ok(None);
+        }
+    };
+
+    //Muchos servidores devuelven la pagina de inicio o un 404 en html con codigo 200
+    let is_html = page.content_type.as_deref().is_some_and(|content_type| content_type.contains("html"));
+    if page.bytes.is_empty() || is_html {
+        info!("     El favicon {} no es una imagen ({:?})", url, page.content_type);
+        return Ok(None);
+    }
+
+    Ok(Some(DatabaseFavicon {
+        url: url.to_string(),
+        hash: favicon_hash(&page.bytes),
+        content_type: page.content_type.clone(),
+        size: page.bytes.len() as i64,
+        fetched_at: chrono::Utc::now(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns;
+    use crate::error::tests::serve;
+
+    //Favicon de la documentacion de rust (static.files/favicon-32x32.png)
+    const ICON: &[u8] = include_bytes!("../tests/fixtures/favicon-32x32.png");
+
+    /*
+     * Descarga el favicon servido por un servidor local que contesta con
+     * la cabecera y el cuerpo indicados
+     */
+    async fn fetch_served(content_type: &str, body: &[u8]) -> Option<DatabaseFavicon> {
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            content_type,
+            body.len()
+        )
+        .into_bytes();
+        response.extend_from_slice(body);
+        let port = serve(Some(response)).await;
+
+        let config = HttpConfig::default();
+        let resolver = dns::build_resolver(&dns::tests::serve(vec![]).await).unwrap();
+        let client = http::build_client(&config, resolver).unwrap();
+        fetch(&client, &config, &format!("http://127.0.0.1:{}/favicon.ico", port))
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn hash_matches_shodan() {
+        //mmh3.hash(codecs.encode(icon, "base64")), como en http.favicon.hash
+        assert_eq!(favicon_hash(ICON), 1649464826);
+        //Como encodebytes, un fichero vacio no lleva salto de linea
+        assert_eq!(favicon_hash(b""), 0);
+        assert_eq!(favicon_hash(b"foo"), murmur3::murmur3_32(&mut "Zm9v\n".as_bytes(), 0).unwrap() as i32);
+    }
+
+    #[test]
+    fn base64_is_wrapped_every_76_characters() {
+        //57 bytes son justo una linea de 76 caracteres; 58 abren una segunda
+        let line = base64::engine::general_purpose::STANDARD.encode([0u8; 57]);
+        let second = base64::engine::general_purpose::STANDARD.encode([0u8; 1]);
+        let mmh3 = |text: String| murmur3::murmur3_32(&mut text.as_bytes(), 0).unwrap() as i32;
+
+        assert_eq!(favicon_hash(&[0u8; 57]), mmh3(format!("{}\n", line)));
+        assert_eq!(favicon_hash(&[0u8; 58]), mmh3(format!("{}\n{}\n", line, second)));
+    }
+
+    #[test]
+    fn murmur3_is_signed() {
+        //Vector publicado de mmh3: hash("foo") = -156908512
+        assert_eq!(murmur3::murmur3_32(&mut "foo".as_bytes(), 0).unwrap() as i32, -156908512);
+    }
+
+    #[tokio::test]
+    async fn images_are_hashed() {
+        let favicon = fetch_served("image/png", ICON).await.unwrap();
+
+        assert_eq!(favicon.hash, 1649464826);
+        assert_eq!(favicon.size, ICON.len() as i64);
+        assert_eq!(favicon.content_type.as_deref(), Some("image/png"));
+    }
+
+    #[tokio::test]
+    async fn html_and_empty_bodies_are_not_favicons() {
+        assert!(fetch_served("text/html; charset=utf-8", b"<html>404</html>").await.is_none());
+        assert!(fetch_served("image/x-icon", b"").await.is_none());
+    }
+}
@@ -0,0 +1,225 @@
+use regex::Regex;
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::config::DomainFilterConfig;
+use crate::domain::SuffixList;
+use crate::error::ScrapperError;
+
+/*
+ * Regla de filtrado de dominios tal y como se escribe en config.yaml:
+ *
+ *   - exact: bit.ly            solo ese host
+ *   - suffix: youtube.com      el host y cualquier subdominio
+ *   - registrable: google.com  cualquier host cuyo dominio registrable coincida
+ *   - regex: '^cdn\d+\.'       expresion regular sobre el host
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DomainRule {
+    Exact(String),
+    Suffix(String),
+    Registrable(String),
+    Regex(String),
+}
+
+enum Matcher {
+    Exact(String),
+    Suffix(String),
+    Registrable(String),
+    Regex(Regex),
+}
+
+struct CompiledRule {
+    label: String,
+    matcher: Matcher,
+    dropped: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterVerdict {
+    Allowed,
+    Blocked(String),
+}
+
+/*
+ * Filtro de dominios compartido por el descubrimiento de enlaces y el alta
+ * manual de urls. Las reglas de allow tienen prioridad sobre las de block.
+ */
+pub struct DomainFilter {
+    allow: Vec<CompiledRule>,
+    block: Vec<CompiledRule>,
+    suffixes: Arc<SuffixList>,
+}
+
+impl DomainFilter {
+    pub fn new(config: &DomainFilterConfig, suffixes: Arc<SuffixList>) -> Result<Self, ScrapperError> {
+        Ok(Self {
+            allow: compile_rules(&config.allow)?,
+            block: compile_rules(&config.block)?,
+            suffixes,
+        })
+    }
+
+    pub fn check(&mut self, host: &str) -> FilterVerdict {
+        let host = clean_host(host);
+        let registrable = self.suffixes.registrable_domain(&host);
+
+        if self
+            .allow
+            .iter()
+            .any(|rule| rule.matches(&host, &registrable))
+        {
+            return FilterVerdict::Allowed;
+        }
+
+        match self
+            .block
+            .iter_mut()
+            .find(|rule| rule.matches(&host, &registrable))
+        {
+            Some(rule) => {
+                rule.dropped += 1;
+
+                FilterVerdict::Blocked(rule.label.clone())
+            }
+            None => FilterVerdict::Allowed,
+        }
+    }
+
+    /*
+     * Numero de enlaces descartados por cada regla de block
+     */
+    pub fn dropped_counts(&self) -> Vec<(String, u64)> {
+        self.block
+            .iter()
+            .filter(|rule| rule.dropped > 0)
+            .map(|rule| (rule.label.clone(), rule.dropped))
+            .collect()
+    }
+}
+
+impl CompiledRule {
+    fn matches(&self, host: &str, registrable: &str) -> bool {
+        match &self.matcher {
+            Matcher::Exact(value) => host == value,
+            Matcher::Suffix(value) => host == value || host.ends_with(&format!(".{}", value)),
+            Matcher::Registrable(value) => registrable == value,
+            Matcher::Regex(regex) => regex.is_match(host),
+        }
+    }
+}
+
+/*
+ * Una regex invalida es un error de configuracion: ignorarla dejaria pasar
+ * los dominios que debia bloquear
+ */
+fn compile_rules(rules: &[DomainRule]) -> Result<Vec<CompiledRule>, ScrapperError> {
+    rules
+        .iter()
+        .map(|rule| {
+            let (label, matcher) = match rule {
+                DomainRule::Exact(value) => {
+                    (format!("exact:{}", value), Matcher::Exact(clean_host(value)))
+                }
+                DomainRule::Suffix(value) => {
+                    (format!("suffix:{}", value), Matcher::Suffix(clean_host(value)))
+                }
+                DomainRule::Registrable(value) => (
+                    format!("registrable:{}", value),
+                    Matcher::Registrable(clean_host(value)),
+                ),
+                DomainRule::Regex(value) => {
+                    let regex = Regex::new(value).map_err(|e| {
+                        ScrapperError::Config(format!("regla de dominio invalida {:?}: {}", value, e))
+                    })?;
+                    (format!("regex:{}", value), Matcher::Regex(regex))
+                }
+            };
+
+            Ok(CompiledRule {
+                label,
+                matcher,
+                dropped: 0,
+            })
+        })
+        .collect()
+}
+
+fn clean_host(host: &str) -> String {
+    host.trim().trim_end_matches('.').to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(allow: Vec<DomainRule>, block: Vec<DomainRule>) -> DomainFilter {
+        DomainFilter::new(&DomainFilterConfig { allow, block }, Arc::new(SuffixList::load(None))).unwrap()
+    }
+
+    #[test]
+    fn block_rules_match_by_kind() {
+        let mut filter = filter(
+            vec![],
+            vec![
+                DomainRule::Exact("bit.ly".to_string()),
+                DomainRule::Suffix("youtube.com".to_string()),
+                DomainRule::Registrable("google.com".to_string()),
+                DomainRule::Regex(r"^cdn\d+\.".to_string()),
+            ],
+        );
+
+        assert_eq!(filter.check("bit.ly"), FilterVerdict::Blocked("exact:bit.ly".to_string()));
+        assert_eq!(filter.check("www.bit.ly"), FilterVerdict::Allowed);
+        assert_eq!(filter.check("m.youtube.com"), FilterVerdict::Blocked("suffix:youtube.com".to_string()));
+        assert_eq!(filter.check("notyoutube.com"), FilterVerdict::Allowed);
+        assert_eq!(filter.check("maps.google.com"), FilterVerdict::Blocked("registrable:google.com".to_string()));
+        assert_eq!(filter.check("cdn12.example.com"), FilterVerdict::Blocked(r"regex:^cdn\d+\.".to_string()));
+        assert_eq!(filter.check("example.com"), FilterVerdict::Allowed);
+    }
+
+    #[test]
+    fn hosts_are_normalized_before_matching() {
+        let mut filter = filter(vec![], vec![DomainRule::Exact("Bit.ly.".to_string())]);
+
+        assert_eq!(filter.check(" BIT.LY. "), FilterVerdict::Blocked("exact:Bit.ly.".to_string()));
+    }
+
+    #[test]
+    fn allow_rules_win_over_block_rules() {
+        let mut filter = filter(
+            vec![DomainRule::Exact("developers.google.com".to_string())],
+            vec![DomainRule::Registrable("google.com".to_string())],
+        );
+
+        assert_eq!(filter.check("developers.google.com"), FilterVerdict::Allowed);
+        assert_eq!(filter.check("www.google.com"), FilterVerdict::Blocked("registrable:google.com".to_string()));
+    }
+
+    #[test]
+    fn invalid_regex_rules_are_a_config_error() {
+        let config = DomainFilterConfig {
+            allow: vec![],
+            block: vec![DomainRule::Exact("bit.ly".to_string()), DomainRule::Regex("(".to_string())],
+        };
+
+        let result = DomainFilter::new(&config, Arc::new(SuffixList::load(None)));
+
+        assert!(matches!(result, Err(ScrapperError::Config(message)) if message.contains("\"(\"")));
+    }
+
+    #[test]
+    fn dropped_links_are_counted_per_rule() {
+        let mut filter = filter(
+            vec![],
+            vec![DomainRule::Exact("bit.ly".to_string()), DomainRule::Exact("t.co".to_string())],
+        );
+
+        filter.check("bit.ly");
+        filter.check("bit.ly");
+        filter.check("example.com");
+
+        assert_eq!(filter.dropped_counts(), vec![("exact:bit.ly".to_string(), 2)]);
+    }
+}
@@ -0,0 +1,230 @@
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+use crate::db::DatabaseGeoIp;
+use crate::error::ScrapperError;
+
+/*
+ * Ficheros mmdb de geolocalizacion (tipo GeoLite2-City) y de ASN (tipo
+ * GeoLite2-ASN). Los nombres se guardan en language, o en ingles si no hay.
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GeoConfig {
+    pub city_db: Option<String>,
+    pub asn_db: Option<String>,
+    pub language: String,
+}
+
+impl Default for GeoConfig {
+    fn default() -> Self {
+        Self {
+            city_db: None,
+            asn_db: None,
+            language: "es".to_string(),
+        }
+    }
+}
+
+/*
+ * Proveedor de geolocalizacion de ips. Devuelve None si no sabe nada de la ip.
+ */
+pub trait GeoProvider: Send + Sync {
+    fn lookup(&self, ip: IpAddr) -> Result<Option<DatabaseGeoIp>, ScrapperError>;
+}
+
+/*
+ * Proveedor vacio, para cuando no hay ficheros configurados
+ */
+pub struct NoGeoProvider;
+
+impl GeoProvider for NoGeoProvider {
+    fn lookup(&self, _ip: IpAddr) -> Result<Option<DatabaseGeoIp>, ScrapperError> {
+        Ok(None)
+    }
+}
+
+pub struct MmdbGeoProvider {
+    city: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+    language: String,
+}
+
+impl MmdbGeoProvider {
+    pub fn open(config: &GeoConfig) -> Result<Self, ScrapperError> {
+        let open = |path: &Option<String>| -> Result<Option<Reader<Vec<u8>>>, ScrapperError> {
+            match path {
+                Some(path) => Reader::open_readfile(path)
+                    .map(Some)
+                    .map_err(|e| ScrapperError::Config(format!("no se pudo abrir {}: {}", path, e))),
+                None => Ok(None),
+            }
+        };
+
+        Ok(Self {
+            city: open(&config.city_db)?,
+            asn: open(&config.asn_db)?,
+            language: config.language.clone(),
+        })
+    }
+
+    fn name(&self, names: &Option<BTreeMap<&str, &str>>) -> Option<String> {
+        let names = names.as_ref()?;
+
+        names
+            .get(self.language.as_str())
+            .or_else(|| names.get("en"))
+            .map(|name| name.to_string())
+    }
+}
+
+impl GeoProvider for MmdbGeoProvider {
+    fn lookup(&self, ip: IpAddr) -> Result<Option<DatabaseGeoIp>, ScrapperError> {
+        let mut geo = DatabaseGeoIp {
+            ip: ip.to_string(),
+            country_code: None,
+            country: None,
+            region: None,
+            city: None,
+            asn: None,
+            organization: None,
+        };
+        let mut found = false;
+
+        if let Some(reader) = &self.city {
+            if let Some(city) = not_found_as_none(reader.lookup::<geoip2::City>(ip))? {
+                if let Some(country) = &city.country {
+                    geo.country_code = country.iso_code.map(|code| code.to_string());
+                    geo.country = self.name(&country.names);
+                }
+                geo.region = city
+                    .subdivisions
+                    .as_ref()
+                    .and_then(|subdivisions| subdivisions.first())
+                    .and_then(|subdivision| self.name(&subdivision.names));
+                geo.city = city.city.as_ref().and_then(|city| self.name(&city.names));
+                found = true;
+            }
+        }
+
+        if let Some(reader) = &self.asn {
+            if let Some(asn) = not_found_as_none(reader.lookup::<geoip2::Asn>(ip))? {
+                geo.asn = asn.autonomous_system_number;
+                geo.organization = asn.autonomous_system_organization.map(|organization| organization.to_string());
+                found = true;
+            }
+        }
+
+        Ok(found.then_some(geo))
+    }
+}
+
+/*
+ * Proveedor segun la configuracion: mmdb si hay algun fichero, si no ninguno
+ */
+pub fn build_provider(config: &GeoConfig) -> Result<Box<dyn GeoProvider>, ScrapperError> {
+    if config.city_db.is_none() && config.asn_db.is_none() {
+        warn!("No hay ficheros de geolocalizacion configurados, no se geolocalizaran las ips");
+
+        return Ok(Box::new(NoGeoProvider));
+    }
+
+    Ok(Box::new(MmdbGeoProvider::open(config)?))
+}
+
+fn not_found_as_none<T>(result: Result<T, MaxMindDBError>) -> Result<Option<T>, ScrapperError> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(MaxMindDBError::AddressNotFoundError(_)) => Ok(None),
+        Err(e) => Err(ScrapperError::Parse(format!("mmdb: {}", e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Generados con tests/fixtures/mmdb.py
+    const CITY_DB: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/GeoIP2-City-Test.mmdb");
+    const ASN_DB: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/GeoLite2-ASN-Test.mmdb");
+
+    fn provider(language: &str) -> Box<dyn GeoProvider> {
+        build_provider(&GeoConfig {
+            city_db: Some(CITY_DB.to_string()),
+            asn_db: Some(ASN_DB.to_string()),
+            language: language.to_string(),
+        })
+        .unwrap()
+    }
+
+    fn lookup(provider: &dyn GeoProvider, ip: &str) -> Option<DatabaseGeoIp> {
+        provider.lookup(ip.parse().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn city_and_asn_are_merged() {
+        let geo = lookup(provider("es").as_ref(), "81.2.69.142").unwrap();
+
+        assert_eq!(geo.ip, "81.2.69.142");
+        assert_eq!(geo.country_code.as_deref(), Some("GB"));
+        assert_eq!(geo.country.as_deref(), Some("Reino Unido"));
+        assert_eq!(geo.region.as_deref(), Some("Inglaterra"));
+        assert_eq!(geo.city.as_deref(), Some("Londres"));
+        assert_eq!(geo.asn, Some(20712));
+        assert_eq!(geo.organization.as_deref(), Some("Andrews & Arnold Ltd"));
+    }
+
+    #[test]
+    fn names_fall_back_to_english() {
+        let geo = lookup(provider("es").as_ref(), "2.125.160.217").unwrap();
+        assert_eq!(geo.city.as_deref(), Some("Boxford"));
+        assert_eq!(geo.region.as_deref(), Some("Inglaterra"));
+
+        let geo = lookup(provider("fr").as_ref(), "81.2.69.143").unwrap();
+        assert_eq!(geo.country.as_deref(), Some("United Kingdom"));
+        assert_eq!(geo.city.as_deref(), Some("London"));
+    }
+
+    #[test]
+    fn ips_only_in_one_database_are_found() {
+        let provider = provider("es");
+
+        let geo = lookup(provider.as_ref(), "1.128.0.1").unwrap();
+        assert_eq!(geo.asn, Some(1221));
+        assert_eq!(geo.country_code, None);
+
+        let geo = lookup(provider.as_ref(), "2001:218:1::1").unwrap();
+        assert_eq!(geo.country_code.as_deref(), Some("JP"));
+        assert_eq!(geo.region, None);
+        assert_eq!(geo.asn, Some(2914));
+    }
+
+    #[test]
+    fn private_and_reserved_ips_are_not_found() {
+        let provider = provider("es");
+
+        for ip in ["10.0.0.1", "127.0.0.1", "192.168.1.1", "100.64.0.1", "0.0.0.0", "::1", "fd00::1", "fe80::1"] {
+            assert!(lookup(provider.as_ref(), ip).is_none(), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn missing_database_is_a_config_error() {
+        let config = GeoConfig {
+            city_db: Some(CITY_DB.to_string()),
+            asn_db: Some("/no/existe/GeoLite2-ASN.mmdb".to_string()),
+            ..GeoConfig::default()
+        };
+
+        assert!(matches!(build_provider(&config), Err(ScrapperError::Config(_))));
+    }
+
+    #[test]
+    fn without_databases_nothing_is_found() {
+        let provider = build_provider(&GeoConfig::default()).unwrap();
+
+        assert!(lookup(provider.as_ref(), "81.2.69.142").is_none());
+    }
+}
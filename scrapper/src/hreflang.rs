@@ -0,0 +1,362 @@
+use scraper::Html;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::db::DatabaseAlternate;
+use crate::error::ScrapperError;
+use crate::http::{self, HttpConfig};
+use crate::language;
+
+const SITEMAP_NAMESPACE: &str = "http://www.sitemaps.org/schemas/sitemap/0.9";
+const XHTML_NAMESPACE: &str = "http://www.w3.org/1999/xhtml";
+const X_DEFAULT: &str = "x-default";
+
+/*
+ * Versiones de la pagina en otros idiomas. Se leen de los
+ * <link rel="alternate" hreflang> y, si sitemap esta activo, de las entradas
+ * xhtml:link del /sitemap.xml del sitio (siguiendo como mucho max_sitemaps
+ * sitemaps de un indice). Los sitemaps de cada origen se guardan en memoria
+ * durante sitemap_cache_ttl_hours. Con enqueue se añaden a la cola hasta
+ * max_enqueued alternativas por pagina.
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HreflangConfig {
+    pub sitemap: bool,
+    pub max_sitemaps: usize,
+    pub sitemap_cache_ttl_hours: u64,
+    pub enqueue: bool,
+    pub max_enqueued: usize,
+}
+
+impl Default for HreflangConfig {
+    fn default() -> Self {
+        Self {
+            sitemap: true,
+            max_sitemaps: 5,
+            sitemap_cache_ttl_hours: 24,
+            enqueue: false,
+            max_enqueued: 20,
+        }
+    }
+}
+
+/*
+ * Alternativas declaradas en el html. Las urls relativas se resuelven
+ * respecto a la url de la pagina.
+ */
+pub fn from_document(document: &Html, page_url: &str) -> Result<Vec<DatabaseAlternate>, ScrapperError> {
+    let selector = scraper::Selector::parse("link[rel~=alternate][hreflang][href]")
+        .map_err(|e| ScrapperError::Parse(format!("selector de hreflang: {:?}", e)))?;
+    let base = reqwest::Url::parse(page_url).ok();
+
+    let mut alternates = vec![];
+    for link in document.select(&selector) {
+        let (Some(hreflang), Some(href)) = (link.value().attr("hreflang"), link.value().attr("href")) else {
+            continue;
+        };
+        if let Some(alternate) = alternate(hreflang, href, base.as_ref(), "html") {
+            push_unique(&mut alternates, alternate);
+        }
+    }
+
+    Ok(alternates)
+}
+
+//Alternativas de cada pagina del sitemap, por url sin la barra final
+type SitemapAlternates = HashMap<String, Vec<DatabaseAlternate>>;
+//loc de una entrada del sitemap y sus alternativas
+type SitemapEntry = (String, Vec<DatabaseAlternate>);
+
+/*
+ * Alternativas de los sitemaps de cada origen. Se guardan durante
+ * sitemap_cache_ttl_hours para no descargar los sitemaps en cada pagina del
+ * sitio.
+ */
+pub struct SitemapCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Arc<SitemapAlternates>, Instant)>>,
+}
+
+impl SitemapCache {
+    pub fn new(config: &HreflangConfig) -> Self {
+        Self {
+            ttl: Duration::from_secs(config.sitemap_cache_ttl_hours * 3600),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /*
+     * Alternativas de la pagina declaradas en el sitemap del sitio. Un error
+     * al descargar o leer el sitemap no es un error del scrapeo, se devuelve
+     * vacio.
+     */
+    pub async fn alternates(
+        &self,
+        client: &reqwest::Client,
+        http_config: &HttpConfig,
+        config: &HreflangConfig,
+        page_url: &str,
+    ) -> Vec<DatabaseAlternate> {
+        let Ok(url) = reqwest::Url::parse(page_url) else {
+            return vec![];
+        };
+        let origin = url.origin().ascii_serialization();
+
+        let cached = {
+            let entries = self.entries.lock().unwrap();
+            match entries.get(&origin) {
+                Some((alternates, fetched_at)) if fetched_at.elapsed() < self.ttl => Some(alternates.clone()),
+                _ => None,
+            }
+        };
+        let alternates = match cached {
+            Some(alternates) => alternates,
+            None => {
+                let alternates = Arc::new(from_sitemap(client, http_config, config, &url).await);
+                let mut entries = self.entries.lock().unwrap();
+                entries.retain(|_, (_, fetched_at)| fetched_at.elapsed() < self.ttl);
+                entries.insert(origin, (alternates.clone(), Instant::now()));
+                alternates
+            }
+        };
+
+        alternates.get(page_url.trim_end_matches('/')).cloned().unwrap_or_default()
+    }
+}
+
+/*
+ * Descarga el /sitemap.xml del origen de la url y los sitemaps de su indice
+ * (como mucho max_sitemaps) y devuelve las alternativas de todas sus entradas
+ */
+async fn from_sitemap(
+    client: &reqwest::Client,
+    http_config: &HttpConfig,
+    config: &HreflangConfig,
+    page_url: &reqwest::Url,
+) -> SitemapAlternates {
+    let mut sitemap_url = page_url.clone();
+    sitemap_url.set_path("/sitemap.xml");
+    sitemap_url.set_query(None);
+    sitemap_url.set_fragment(None);
+
+    let mut pending = vec![sitemap_url.to_string()];
+    let mut fetched = 0;
+    let mut alternates = HashMap::new();
+    while let Some(url) = pending.pop() {
+        if fetched >= config.max_sitemaps {
+            break;
+        }
+        fetched += 1;
+
+        let body = match http::fetch(client, http_config, &url).await {
+            Ok(page) if page.status.is_success() => page.body,
+            Ok(page) => {
+                info!("     Sitemap {} no disponible ({})", url, page.status);
+                continue;
+            }
+            Err(e) => {
+                info!("     No se pudo descargar el sitemap {}: {}", url, e);
+                continue;
+            }
+        };
+
+        match parse_sitemap(&body) {
+            Ok((sitemaps, entries)) => {
+                pending.extend(sitemaps.into_iter().rev());
+                for (loc, entry_alternates) in entries {
+                    let page_alternates: &mut Vec<DatabaseAlternate> =
+                        alternates.entry(loc.trim_end_matches('/').to_string()).or_default();
+                    for alternate in entry_alternates {
+                        push_unique(page_alternates, alternate);
+                    }
+                }
+            }
+            Err(e) => warn!("     Sitemap {} invalido: {}", url, e),
+        }
+    }
+
+    alternates
+}
+
+/*
+ * Devuelve los sitemaps de un indice y, de cada entrada con alternativas,
+ * su loc y sus alternativas (las urls relativas se resuelven respecto al loc)
+ */
+fn parse_sitemap(body: &str) -> Result<(Vec<String>, Vec<SitemapEntry>), ScrapperError> {
+    let document = roxmltree::Document::parse(body).map_err(|e| ScrapperError::Parse(e.to_string()))?;
+    let root = document.root_element();
+    let loc = |node: roxmltree::Node| {
+        node.children()
+            .find(|child| child.has_tag_name((SITEMAP_NAMESPACE, "loc")))
+            .and_then(|loc| loc.text())
+            .map(|loc| loc.trim().to_string())
+    };
+
+    if root.has_tag_name((SITEMAP_NAMESPACE, "sitemapindex")) {
+        let sitemaps = root
+            .children()
+            .filter(|child| child.has_tag_name((SITEMAP_NAMESPACE, "sitemap")))
+            .filter_map(loc)
+            .collect();
+
+        return Ok((sitemaps, vec![]));
+    }
+
+    let mut entries = vec![];
+    for url in root.children().filter(|child| child.has_tag_name((SITEMAP_NAMESPACE, "url"))) {
+        let Some(page_url) = loc(url) else {
+            continue;
+        };
+        let base = reqwest::Url::parse(&page_url).ok();
+
+        let mut alternates = vec![];
+        for link in url.children() {
+            if !link.has_tag_name((XHTML_NAMESPACE, "link")) || link.attribute("rel") != Some("alternate") {
+                continue;
+            }
+            let (Some(hreflang), Some(href)) = (link.attribute("hreflang"), link.attribute("href")) else {
+                continue;
+            };
+            if let Some(alternate) = alternate(hreflang, href, base.as_ref(), "sitemap") {
+                push_unique(&mut alternates, alternate);
+            }
+        }
+        if !alternates.is_empty() {
+            entries.push((page_url, alternates));
+        }
+    }
+
+    Ok((vec![], entries))
+}
+
+fn alternate(hreflang: &str, href: &str, base: Option<&reqwest::Url>, source: &str) -> Option<DatabaseAlternate> {
+    let hreflang = if hreflang.trim().eq_ignore_ascii_case(X_DEFAULT) {
+        X_DEFAULT.to_string()
+    } else {
+        language::normalize_tag(hreflang)?
+    };
+    let url = match base {
+        Some(base) => base.join(href.trim()).ok()?,
+        None => reqwest::Url::parse(href.trim()).ok()?,
+    };
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return None;
+    }
+
+    Some(DatabaseAlternate {
+        hreflang,
+        url: url.to_string(),
+        source: source.to_string(),
+    })
+}
+
+/*
+ * Une las alternativas del html y del sitemap. Si un idioma aparece en los
+ * dos se queda la del html.
+ */
+pub fn merge(html: Vec<DatabaseAlternate>, sitemap: Vec<DatabaseAlternate>) -> Vec<DatabaseAlternate> {
+    let mut alternates = html;
+    for alternate in sitemap {
+        if !alternates.iter().any(|existing| existing.hreflang == alternate.hreflang) {
+            alternates.push(alternate);
+        }
+    }
+
+    alternates
+}
+
+/*
+ * Idiomas declarados, sin x-default ni repetidos
+ */
+pub fn languages(alternates: &[DatabaseAlternate]) -> Vec<String> {
+    let mut languages: Vec<String> = vec![];
+    for alternate in alternates {
+        if alternate.hreflang != X_DEFAULT && !languages.contains(&alternate.hreflang) {
+            languages.push(alternate.hreflang.clone());
+        }
+    }
+
+    languages
+}
+
+fn push_unique(alternates: &mut Vec<DatabaseAlternate>, alternate: DatabaseAlternate) {
+    let exists = alternates
+        .iter()
+        .any(|existing| existing.hreflang == alternate.hreflang && existing.url == alternate.url);
+    if !exists {
+        alternates.push(alternate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SITEMAP: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9" xmlns:xhtml="http://www.w3.org/1999/xhtml">
+  <url>
+    <loc>https://acme.com/es/</loc>
+    <xhtml:link rel="alternate" hreflang="es_ES" href="https://acme.com/es/"/>
+    <xhtml:link rel="alternate" hreflang="en" href="/en/"/>
+    <xhtml:link rel="alternate" hreflang="x-default" href="https://acme.com/"/>
+  </url>
+  <url>
+    <loc>https://acme.com/contacto</loc>
+  </url>
+</urlset>"#;
+
+    #[test]
+    fn sitemap_entries_with_alternates() {
+        let (sitemaps, entries) = parse_sitemap(SITEMAP).unwrap();
+
+        assert!(sitemaps.is_empty());
+        assert_eq!(entries.len(), 1);
+        let (loc, alternates) = &entries[0];
+        assert_eq!(loc, "https://acme.com/es/");
+        let alternates: Vec<(&str, &str)> = alternates
+            .iter()
+            .map(|alternate| (alternate.hreflang.as_str(), alternate.url.as_str()))
+            .collect();
+        assert_eq!(
+            alternates,
+            vec![
+                ("es-ES", "https://acme.com/es/"),
+                ("en", "https://acme.com/en/"),
+                ("x-default", "https://acme.com/"),
+            ]
+        );
+    }
+
+    #[test]
+    fn sitemap_index_returns_sitemaps() {
+        let index = r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+            <sitemap><loc> https://acme.com/sitemap-es.xml </loc></sitemap>
+            <sitemap><loc>https://acme.com/sitemap-en.xml</loc></sitemap>
+        </sitemapindex>"#;
+
+        let (sitemaps, entries) = parse_sitemap(index).unwrap();
+
+        assert_eq!(sitemaps, vec!["https://acme.com/sitemap-es.xml", "https://acme.com/sitemap-en.xml"]);
+        assert!(entries.is_empty());
+    }
+
+    #[test]
+    fn html_alternates_win_when_merging() {
+        let document = Html::parse_document(
+            r#"<link rel="alternate" hreflang="en" href="/english/">
+               <link rel="alternate" hreflang="fr" href="javascript:void(0)">"#,
+        );
+        let html = from_document(&document, "https://acme.com/es/").unwrap();
+        let (_, mut entries) = parse_sitemap(SITEMAP).unwrap();
+
+        let alternates = merge(html, entries.remove(0).1);
+
+        assert_eq!(alternates[0].url, "https://acme.com/english/");
+        assert_eq!(alternates[0].source, "html");
+        assert_eq!(alternates.len(), 3);
+        assert_eq!(languages(&alternates), vec!["en", "es-ES"]);
+    }
+}
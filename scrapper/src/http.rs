@@ -0,0 +1,498 @@
+use hickory_resolver::TokioAsyncResolver;
+use serde::Deserialize;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::dns::HttpResolver;
+use crate::error::{FetchError, ScrapperError};
+
+/*
+ * Configuracion del cliente http compartido por todas las descargas
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub user_agent: String,
+    pub connect_timeout_secs: u64,
+    pub read_timeout_secs: u64,
+    pub timeout_secs: u64,
+    pub max_body_bytes: usize,
+    pub pool_max_idle_per_host: usize,
+    pub pool_idle_timeout_secs: u64,
+    pub gzip: bool,
+    pub brotli: bool,
+    pub proxy: Option<String>,
+    pub max_redirects: usize,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            user_agent: format!("SiteMadeBot/{}", env!("CARGO_PKG_VERSION")),
+            connect_timeout_secs: 10,
+            read_timeout_secs: 20,
+            timeout_secs: 60,
+            max_body_bytes: 5 * 1024 * 1024,
+            pool_max_idle_per_host: 4,
+            pool_idle_timeout_secs: 90,
+            gzip: true,
+            brotli: true,
+            proxy: None,
+            max_redirects: 10,
+        }
+    }
+}
+
+pub struct RedirectHop {
+    pub url: String,
+    pub status: u16,
+    pub location: String,
+}
+
+pub struct FetchedPage {
+    pub status: reqwest::StatusCode,
+    pub headers: reqwest::header::HeaderMap,
+    pub body: String,
+    //Cuerpo descomprimido sin decodificar, para los ficheros binarios
+    pub bytes: Vec<u8>,
+    pub final_url: reqwest::Url,
+    pub redirects: Vec<RedirectHop>,
+    pub http_version: String,
+    pub content_type: Option<String>,
+    pub charset: Option<String>,
+    pub encoding: String,
+    pub compression: Option<String>,
+    pub transfer_size: usize,
+    pub body_size: usize,
+    pub ttfb: Duration,
+    pub total_time: Duration,
+}
+
+pub fn build_client(config: &HttpConfig, resolver: TokioAsyncResolver) -> Result<reqwest::Client, ScrapperError> {
+    let mut builder = reqwest::Client::builder()
+        .dns_resolver(Arc::new(HttpResolver(resolver)))
+        .user_agent(config.user_agent.clone())
+        .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .pool_max_idle_per_host(config.pool_max_idle_per_host)
+        .pool_idle_timeout(Duration::from_secs(config.pool_idle_timeout_secs))
+        .redirect(reqwest::redirect::Policy::none());
+
+    //La descompresion se hace a mano para saber si el servidor comprime
+    let accept_encoding: Vec<&str> = [
+        (config.gzip, "gzip"),
+        (config.gzip, "deflate"),
+        (config.brotli, "br"),
+    ]
+    .iter()
+    .filter(|(enabled, _)| *enabled)
+    .map(|(_, encoding)| *encoding)
+    .collect();
+    if !accept_encoding.is_empty() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        let value = reqwest::header::HeaderValue::from_str(&accept_encoding.join(", "))
+            .map_err(|e| ScrapperError::Config(format!("accept-encoding: {}", e)))?;
+        headers.insert(reqwest::header::ACCEPT_ENCODING, value);
+        builder = builder.default_headers(headers);
+    }
+
+    if let Some(proxy) = &config.proxy {
+        let proxy = reqwest::Proxy::all(proxy.as_str())
+            .map_err(|e| ScrapperError::Config(format!("proxy {} invalido: {}", proxy, e)))?;
+        builder = builder.proxy(proxy);
+    }
+
+    builder
+        .build()
+        .map_err(|e| ScrapperError::Config(format!("cliente http: {}", e)))
+}
+
+/*
+ * Descarga la url con el cliente compartido. Las redirecciones se siguen a
+ * mano para guardar la cadena completa, los codigos de error http se
+ * devuelven como FetchError y el cuerpo se corta en max_body_bytes.
+ */
+pub async fn fetch(client: &reqwest::Client, config: &HttpConfig, url: &str) -> Result<FetchedPage, FetchError> {
+    let mut current_url = reqwest::Url::parse(url).map_err(|e| FetchError::Other(format!("url {}: {}", url, e)))?;
+    let mut redirects = vec![];
+
+    let started_at = Instant::now();
+    let (response, ttfb) = loop {
+        let request_started_at = Instant::now();
+        let response = client.get(current_url.clone()).send().await?;
+        let ttfb = request_started_at.elapsed();
+        if !response.status().is_redirection() {
+            break (response, ttfb);
+        }
+
+        let location = match response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|value| value.to_str().ok())
+        {
+            Some(location) => location.to_string(),
+            None => break (response, ttfb),
+        };
+        let next_url = current_url
+            .join(&location)
+            .map_err(|e| FetchError::Other(format!("location {}: {}", location, e)))?;
+
+        redirects.push(RedirectHop {
+            url: current_url.to_string(),
+            status: response.status().as_u16(),
+            location,
+        });
+        if redirects.len() > config.max_redirects {
+            return Err(FetchError::TooManyRedirects(redirects.len()));
+        }
+
+        current_url = next_url;
+    };
+
+    let status = response.status();
+    let http_version = format!("{:?}", response.version());
+    let headers = response.headers().clone();
+    if let Some(e) = FetchError::from_status(status, &headers) {
+        return Err(e);
+    }
+
+    let raw_body = read_body(response, config).await?;
+    let transfer_size = raw_body.len();
+
+    let compression = header_str(&headers, reqwest::header::CONTENT_ENCODING)
+        .map(|encoding| encoding.trim().to_ascii_lowercase())
+        .filter(|encoding| !encoding.is_empty() && encoding != "identity");
+    let body = match &compression {
+        Some(encoding) => decompress(&raw_body, encoding, config.max_body_bytes)?,
+        None => raw_body,
+    };
+
+    let content_type = header_str(&headers, reqwest::header::CONTENT_TYPE).map(|content_type| {
+        content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase()
+    });
+    let charset = header_charset(&headers);
+    let (text, encoding) = decode_body(&body, charset.as_deref());
+
+    Ok(FetchedPage {
+        status,
+        body_size: body.len(),
+        body: text,
+        headers,
+        final_url: current_url,
+        redirects,
+        http_version,
+        content_type,
+        charset,
+        encoding: encoding.name().to_string(),
+        compression,
+        transfer_size,
+        ttfb,
+        total_time: started_at.elapsed(),
+        bytes: body,
+    })
+}
+
+async fn read_body(mut response: reqwest::Response, config: &HttpConfig) -> Result<Vec<u8>, FetchError> {
+    if let Some(length) = response.content_length() {
+        if length as usize > config.max_body_bytes {
+            return Err(FetchError::BodyTooLarge(length as usize));
+        }
+    }
+
+    //reqwest no tiene timeout de lectura, se aplica a cada trozo del cuerpo
+    let read_timeout = Duration::from_secs(config.read_timeout_secs);
+    let mut body = Vec::new();
+    loop {
+        let chunk = tokio::time::timeout(read_timeout, response.chunk())
+            .await
+            .map_err(|_| FetchError::Timeout)??;
+
+        match chunk {
+            Some(chunk) => {
+                if body.len() + chunk.len() > config.max_body_bytes {
+                    return Err(FetchError::BodyTooLarge(body.len() + chunk.len()));
+                }
+                body.extend_from_slice(&chunk);
+            }
+            None => break,
+        }
+    }
+
+    Ok(body)
+}
+
+/*
+ * Motivo para no analizar la pagina: no es html, esta vacia o es una
+ * pagina de dominio aparcado. Las firmas de aparcado solo se buscan en el
+ * title, el meta refresh y los src de los scripts; en el cuerpo aparecen
+ * tambien en paginas reales que hablan de dominios o enlazan a Sedo.
+ */
+pub fn skip_reason(page: &FetchedPage, parked_signatures: &[String]) -> Option<String> {
+    if let Some(content_type) = &page.content_type {
+        if !content_type.contains("html") {
+            return Some(format!("content_type:{}", content_type));
+        }
+    }
+
+    if page.body.trim().is_empty() {
+        return Some("empty".to_string());
+    }
+
+    if parked_signatures.is_empty() {
+        return None;
+    }
+
+    let markers = parked_markers(&page.body);
+    parked_signatures
+        .iter()
+        .find(|signature| {
+            let signature = signature.to_lowercase();
+            markers.iter().any(|marker| marker.contains(&signature))
+        })
+        .map(|signature| format!("parked:{}", signature))
+}
+
+/*
+ * Textos de la pagina donde se buscan las firmas de aparcado, en minusculas
+ */
+fn parked_markers(body: &str) -> Vec<String> {
+    let document = scraper::Html::parse_document(body);
+    let mut markers = vec![];
+
+    if let Ok(selector) = crate::parse_selector("title") {
+        markers.extend(document.select(&selector).map(|title| title.text().collect::<String>()));
+    }
+    if let Ok(selector) = crate::parse_selector("meta[http-equiv][content]") {
+        markers.extend(
+            document
+                .select(&selector)
+                .filter(|meta| {
+                    meta.value()
+                        .attr("http-equiv")
+                        .is_some_and(|value| value.eq_ignore_ascii_case("refresh"))
+                })
+                .filter_map(|meta| meta.value().attr("content").map(str::to_string)),
+        );
+    }
+    if let Ok(selector) = crate::parse_selector("script[src]") {
+        markers.extend(document.select(&selector).filter_map(|script| script.value().attr("src").map(str::to_string)));
+    }
+
+    markers.iter().map(|marker| marker.to_lowercase()).collect()
+}
+
+fn decompress(body: &[u8], encoding: &str, max_body_bytes: usize) -> Result<Vec<u8>, FetchError> {
+    let reader: Box<dyn Read + '_> = match encoding {
+        "gzip" | "x-gzip" => Box::new(flate2::read::MultiGzDecoder::new(body)),
+        "deflate" => Box::new(flate2::read::ZlibDecoder::new(body)),
+        "br" => Box::new(brotli_decompressor::Decompressor::new(body, 4096)),
+        _ => return Err(FetchError::BodyDecode(format!("content-encoding {} no soportado", encoding))),
+    };
+
+    let mut decoded = Vec::new();
+    reader
+        .take(max_body_bytes as u64 + 1)
+        .read_to_end(&mut decoded)
+        .map_err(|e| FetchError::BodyDecode(format!("{}: {}", encoding, e)))?;
+    if decoded.len() > max_body_bytes {
+        return Err(FetchError::BodyTooLarge(decoded.len()));
+    }
+
+    Ok(decoded)
+}
+
+fn header_str(headers: &reqwest::header::HeaderMap, name: reqwest::header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn header_charset(headers: &reqwest::header::HeaderMap) -> Option<String> {
+    header_str(headers, reqwest::header::CONTENT_TYPE).and_then(|content_type| {
+        content_type
+            .split(';')
+            .map(|param| param.trim().to_ascii_lowercase())
+            .find_map(|param| param.strip_prefix("charset=").map(|charset| charset.trim_matches('"').to_string()))
+    })
+}
+
+/*
+ * Decodifica el cuerpo buscando la codificacion por este orden: BOM, charset
+ * de la cabecera Content-Type y <meta charset> del documento. Si no hay
+ * ninguna se usa utf-8. Devuelve el texto y la codificacion usada.
+ */
+fn decode_body(body: &[u8], charset: Option<&str>) -> (String, &'static encoding_rs::Encoding) {
+    let encoding = encoding_rs::Encoding::for_bom(body)
+        .map(|(encoding, _)| encoding)
+        .or_else(|| charset.and_then(|charset| encoding_rs::Encoding::for_label(charset.as_bytes())))
+        .or_else(|| meta_charset(body))
+        .unwrap_or(encoding_rs::UTF_8);
+
+    let (text, _) = encoding.decode_with_bom_removal(body);
+
+    (text.into_owned(), encoding)
+}
+
+/*
+ * Busca el charset en las etiquetas meta del principio del documento, tanto
+ * <meta charset="..."> como <meta http-equiv="Content-Type" content="...; charset=...">
+ */
+fn meta_charset(body: &[u8]) -> Option<&'static encoding_rs::Encoding> {
+    const META_PRESCAN_BYTES: usize = 4096;
+
+    let head = String::from_utf8_lossy(&body[..body.len().min(META_PRESCAN_BYTES)]).to_ascii_lowercase();
+    let encoding = head.match_indices("<meta").find_map(|(start, _)| {
+        let tag = &head[start..];
+        let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+        //Se admiten espacios alrededor del = (charset = "utf-8")
+        let value = tag[tag.find("charset")? + "charset".len()..].trim_start().strip_prefix('=')?;
+        let value = value.trim_start_matches(|c: char| c == '"' || c == '\'' || c.is_whitespace());
+        let label = &value[..value
+            .find(|c: char| c == '"' || c == '\'' || c == ';' || c == '/' || c.is_whitespace())
+            .unwrap_or(value.len())];
+
+        encoding_rs::Encoding::for_label(label.as_bytes())
+    })?;
+
+    //Un meta no puede declarar utf-16, el documento ya se ha leido como ascii
+    if encoding == encoding_rs::UTF_16LE || encoding == encoding_rs::UTF_16BE {
+        Some(encoding_rs::UTF_8)
+    } else {
+        Some(encoding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bom_wins_over_header_and_meta() {
+        let body = b"\xEF\xBB\xBF<meta charset=\"iso-8859-1\">caf\xC3\xA9";
+
+        let (text, encoding) = decode_body(body, Some("iso-8859-1"));
+
+        assert_eq!(encoding, encoding_rs::UTF_8);
+        assert_eq!(text, "<meta charset=\"iso-8859-1\">café");
+    }
+
+    #[test]
+    fn header_charset_wins_over_meta() {
+        let body = b"<meta charset=\"utf-8\">caf\xE9";
+
+        let (text, encoding) = decode_body(body, Some("iso-8859-1"));
+
+        assert_eq!(encoding, encoding_rs::WINDOWS_1252);
+        assert_eq!(text, "<meta charset=\"utf-8\">café");
+    }
+
+    #[test]
+    fn meta_charset_is_used_without_header() {
+        let body = b"<html><head><meta http-equiv=\"Content-Type\" content=\"text/html; charset=ISO-8859-1\"></head>caf\xE9";
+
+        let (text, encoding) = decode_body(body, None);
+
+        assert_eq!(encoding, encoding_rs::WINDOWS_1252);
+        assert!(text.ends_with("café"));
+    }
+
+    #[test]
+    fn unknown_header_charset_falls_back_to_meta_and_then_utf8() {
+        let (_, encoding) = decode_body(b"<meta charset='shift_jis'>", Some("bogus"));
+        assert_eq!(encoding, encoding_rs::SHIFT_JIS);
+
+        let (text, encoding) = decode_body("café".as_bytes(), None);
+        assert_eq!(encoding, encoding_rs::UTF_8);
+        assert_eq!(text, "café");
+    }
+
+    #[test]
+    fn meta_charset_formats() {
+        assert_eq!(meta_charset(b"<META CHARSET=windows-1251>"), Some(encoding_rs::WINDOWS_1251));
+        assert_eq!(meta_charset(b"<meta charset = \"euc-kr\" />"), Some(encoding_rs::EUC_KR));
+        assert_eq!(meta_charset(b"<meta charset=\"utf-16le\">"), Some(encoding_rs::UTF_8));
+        assert_eq!(meta_charset(b"<meta name=\"description\" content=\"x\">"), None);
+    }
+
+    #[test]
+    fn meta_charset_only_scans_the_start_of_the_document() {
+        let mut body = vec![b' '; 5000];
+        body.extend_from_slice(b"<meta charset=\"iso-8859-1\">");
+
+        assert_eq!(meta_charset(&body), None);
+    }
+
+    #[test]
+    fn header_charset_parameter() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            reqwest::header::HeaderValue::from_static("text/html; Charset=\"ISO-8859-1\""),
+        );
+
+        assert_eq!(header_charset(&headers), Some("iso-8859-1".to_string()));
+    }
+
+    fn page(content_type: &str, body: &str) -> FetchedPage {
+        FetchedPage {
+            status: reqwest::StatusCode::OK,
+            headers: reqwest::header::HeaderMap::new(),
+            body: body.to_string(),
+            bytes: body.as_bytes().to_vec(),
+            final_url: reqwest::Url::parse("https://acme.test/").unwrap(),
+            redirects: vec![],
+            http_version: "HTTP/1.1".to_string(),
+            content_type: Some(content_type.to_string()),
+            charset: None,
+            encoding: "utf-8".to_string(),
+            compression: None,
+            transfer_size: body.len(),
+            body_size: body.len(),
+            ttfb: Duration::ZERO,
+            total_time: Duration::ZERO,
+        }
+    }
+
+    fn signatures() -> Vec<String> {
+        ["This domain is for sale", "sedoparking.com", "dan.com/buy-domain"]
+            .iter()
+            .map(|signature| signature.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn non_html_and_empty_pages_are_skipped() {
+        assert_eq!(
+            skip_reason(&page("application/pdf", "%PDF"), &signatures()).as_deref(),
+            Some("content_type:application/pdf")
+        );
+        assert_eq!(skip_reason(&page("text/html", " \n "), &signatures()).as_deref(), Some("empty"));
+    }
+
+    #[test]
+    fn parked_signatures_match_title_refresh_and_scripts() {
+        let title = page("text/html", "<html><head><title>acme.test: THIS DOMAIN IS FOR SALE</title></head></html>");
+        let refresh = page(
+            "text/html",
+            r#"<meta http-equiv="Refresh" content="0; url=https://dan.com/buy-domain/acme.test">"#,
+        );
+        let script = page("text/html", r#"<script src="https://sedoparking.com/frmpark/acme.test/js"></script>"#);
+
+        assert_eq!(skip_reason(&title, &signatures()).as_deref(), Some("parked:This domain is for sale"));
+        assert_eq!(skip_reason(&refresh, &signatures()).as_deref(), Some("parked:dan.com/buy-domain"));
+        assert_eq!(skip_reason(&script, &signatures()).as_deref(), Some("parked:sedoparking.com"));
+    }
+
+    #[test]
+    fn parked_signatures_in_the_content_are_ignored() {
+        let article = page(
+            "text/html",
+            r#"<html><head><title>Como vender un dominio</title></head><body>
+               <p>Pon un cartel de "This domain is for sale" y publicalo en
+               <a href="https://sedoparking.com">Sedo</a> o en dan.com/buy-domain.</p>
+               <script>var parking = "sedoparking.com";</script>
+               </body></html>"#,
+        );
+
+        assert_eq!(skip_reason(&article, &signatures()), None);
+    }
+}
@@ -0,0 +1,294 @@
+use scraper::{Html, Node};
+use serde::Deserialize;
+use whatlang::Lang;
+
+use crate::db::DatabaseLanguageDetection;
+
+//Elementos cuyo texto no se ve en la pagina
+const HIDDEN_ELEMENTS: [&str; 6] = ["script", "style", "noscript", "template", "svg", "head"];
+
+/*
+ * Deteccion del idioma a partir del texto visible. Solo se detecta si hay
+ * al menos min_text_chars caracteres, y la deteccion solo cuenta (para
+ * guardar el idioma o marcar la discrepancia con el declarado) si supera
+ * min_confidence. max_text_chars limita el texto que se analiza.
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LanguageConfig {
+    pub min_text_chars: usize,
+    pub max_text_chars: usize,
+    pub min_confidence: f64,
+}
+
+impl Default for LanguageConfig {
+    fn default() -> Self {
+        Self {
+            min_text_chars: 200,
+            max_text_chars: 20000,
+            min_confidence: 0.3,
+        }
+    }
+}
+
+/*
+ * Texto visible del documento, sin scripts ni estilos, con los espacios
+ * colapsados
+ */
+pub fn visible_text(document: &Html, max_chars: usize) -> String {
+    let mut text = String::new();
+
+    for node in document.tree.root().descendants() {
+        let Node::Text(node_text) = node.value() else {
+            continue;
+        };
+        let hidden = node.ancestors().any(|ancestor| match ancestor.value() {
+            Node::Element(element) => HIDDEN_ELEMENTS.contains(&element.name()),
+            _ => false,
+        });
+        if hidden {
+            continue;
+        }
+
+        for word in node_text.split_whitespace() {
+            if !text.is_empty() {
+                text.push(' ');
+            }
+            text.push_str(word);
+        }
+        if text.len() >= max_chars {
+            break;
+        }
+    }
+
+    text
+}
+
+/*
+ * Idioma declarado y detectado de la pagina. El declarado se normaliza a
+ * BCP 47 y se compara con el detectado por el subtag principal.
+ */
+pub fn detect(config: &LanguageConfig, declared: Option<&str>, text: &str) -> DatabaseLanguageDetection {
+    let declared = declared.and_then(normalize_tag);
+
+    let info = if text.chars().count() >= config.min_text_chars {
+        whatlang::detect(text)
+    } else {
+        None
+    };
+    let detected = info.as_ref().map(|info| lang_to_bcp47(info.lang()).to_string());
+    let confidence = info.as_ref().map(|info| (info.confidence() * 100.0).round() / 100.0);
+    let reliable = info.as_ref().is_some_and(|info| info.confidence() >= config.min_confidence);
+
+    let mismatch = match (&declared, &detected) {
+        (Some(declared), Some(detected)) if reliable => primary_subtag(declared) != primary_subtag(detected),
+        _ => false,
+    };
+
+    DatabaseLanguageDetection {
+        declared,
+        detected,
+        confidence,
+        reliable,
+        mismatch,
+    }
+}
+
+/*
+ * Normaliza una etiqueta de idioma a BCP 47: "es_ES" -> "es-ES",
+ * "EN-us" -> "en-US", "zh-hant" -> "zh-Hant". Se queda con la primera si
+ * hay varias separadas por comas y descarta las que no son validas.
+ */
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.split([',', ';']).next()?.trim().replace('_', "-");
+    let mut subtags = tag.split('-').filter(|subtag| !subtag.is_empty());
+
+    let primary = subtags.next()?.to_lowercase();
+    if !(2..=3).contains(&primary.len()) || !primary.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    //Codigos antiguos que siguen apareciendo en las paginas
+    let primary = match primary.as_str() {
+        "iw" => "he".to_string(),
+        "in" => "id".to_string(),
+        "ji" => "yi".to_string(),
+        _ => primary,
+    };
+
+    let mut normalized = vec![primary];
+    for subtag in subtags {
+        if !subtag.chars().all(|c| c.is_ascii_alphanumeric()) {
+            break;
+        }
+        let subtag = match subtag.len() {
+            2 if subtag.chars().all(|c| c.is_ascii_alphabetic()) => subtag.to_uppercase(),
+            4 => subtag[..1].to_uppercase() + &subtag[1..].to_lowercase(),
+            _ => subtag.to_lowercase(),
+        };
+        normalized.push(subtag);
+    }
+
+    Some(normalized.join("-"))
+}
+
+//El noruego se declara como no, nb o nn indistintamente
+fn primary_subtag(tag: &str) -> &str {
+    match tag.split('-').next().unwrap_or(tag) {
+        "nb" | "nn" => "no",
+        primary => primary,
+    }
+}
+
+/*
+ * Codigo BCP 47 (ISO 639-1 si existe) de un idioma de whatlang
+ */
+fn lang_to_bcp47(lang: Lang) -> &'static str {
+    match lang {
+        Lang::Epo => "eo",
+        Lang::Eng => "en",
+        Lang::Rus => "ru",
+        Lang::Cmn => "zh",
+        Lang::Spa => "es",
+        Lang::Por => "pt",
+        Lang::Ita => "it",
+        Lang::Ben => "bn",
+        Lang::Fra => "fr",
+        Lang::Deu => "de",
+        Lang::Ukr => "uk",
+        Lang::Kat => "ka",
+        Lang::Ara => "ar",
+        Lang::Hin => "hi",
+        Lang::Jpn => "ja",
+        Lang::Heb => "he",
+        Lang::Yid => "yi",
+        Lang::Pol => "pl",
+        Lang::Amh => "am",
+        Lang::Jav => "jv",
+        Lang::Kor => "ko",
+        Lang::Nob => "nb",
+        Lang::Dan => "da",
+        Lang::Swe => "sv",
+        Lang::Fin => "fi",
+        Lang::Tur => "tr",
+        Lang::Nld => "nl",
+        Lang::Hun => "hu",
+        Lang::Ces => "cs",
+        Lang::Ell => "el",
+        Lang::Bul => "bg",
+        Lang::Bel => "be",
+        Lang::Mar => "mr",
+        Lang::Kan => "kn",
+        Lang::Ron => "ro",
+        Lang::Slv => "sl",
+        Lang::Hrv => "hr",
+        Lang::Srp => "sr",
+        Lang::Mkd => "mk",
+        Lang::Lit => "lt",
+        Lang::Lav => "lv",
+        Lang::Est => "et",
+        Lang::Tam => "ta",
+        Lang::Vie => "vi",
+        Lang::Urd => "ur",
+        Lang::Tha => "th",
+        Lang::Guj => "gu",
+        Lang::Uzb => "uz",
+        Lang::Pan => "pa",
+        Lang::Aze => "az",
+        Lang::Ind => "id",
+        Lang::Tel => "te",
+        Lang::Pes => "fa",
+        Lang::Mal => "ml",
+        Lang::Ori => "or",
+        Lang::Mya => "my",
+        Lang::Nep => "ne",
+        Lang::Sin => "si",
+        Lang::Khm => "km",
+        Lang::Tuk => "tk",
+        Lang::Aka => "ak",
+        Lang::Zul => "zu",
+        Lang::Sna => "sn",
+        Lang::Afr => "af",
+        Lang::Lat => "la",
+        Lang::Slk => "sk",
+        Lang::Cat => "ca",
+        Lang::Tgl => "tl",
+        Lang::Hye => "hy",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPANISH: &str = "El ayuntamiento ha aprobado hoy el nuevo plan de movilidad para el centro de la ciudad, \
+        que incluye mas carriles para bicicletas, nuevas lineas de autobus y la ampliacion de las zonas peatonales \
+        en los barrios historicos durante los proximos cuatro años.";
+
+    #[test]
+    fn normalize_tag_formats() {
+        assert_eq!(normalize_tag("es_ES").as_deref(), Some("es-ES"));
+        assert_eq!(normalize_tag("EN-us").as_deref(), Some("en-US"));
+        assert_eq!(normalize_tag("zh-hant-tw").as_deref(), Some("zh-Hant-TW"));
+        assert_eq!(normalize_tag("es-419").as_deref(), Some("es-419"));
+        assert_eq!(normalize_tag(" fr ").as_deref(), Some("fr"));
+    }
+
+    #[test]
+    fn normalize_tag_keeps_the_first_of_a_list() {
+        assert_eq!(normalize_tag("en-GB, en;q=0.8").as_deref(), Some("en-GB"));
+    }
+
+    #[test]
+    fn normalize_tag_replaces_deprecated_codes() {
+        assert_eq!(normalize_tag("iw").as_deref(), Some("he"));
+        assert_eq!(normalize_tag("in-ID").as_deref(), Some("id-ID"));
+        assert_eq!(normalize_tag("ji").as_deref(), Some("yi"));
+    }
+
+    #[test]
+    fn normalize_tag_rejects_invalid_tags() {
+        assert_eq!(normalize_tag(""), None);
+        assert_eq!(normalize_tag("x-default"), None);
+        assert_eq!(normalize_tag("english"), None);
+        assert_eq!(normalize_tag("e1"), None);
+        //Los subtags invalidos cortan la etiqueta
+        assert_eq!(normalize_tag("de-DE-#x").as_deref(), Some("de-DE"));
+    }
+
+    #[test]
+    fn detect_flags_mismatch_with_declared_language() {
+        let config = LanguageConfig::default();
+
+        let detection = detect(&config, Some("en_US"), SPANISH);
+        assert_eq!(detection.declared.as_deref(), Some("en-US"));
+        assert_eq!(detection.detected.as_deref(), Some("es"));
+        assert!(detection.reliable);
+        assert!(detection.mismatch);
+
+        assert!(!detect(&config, Some("es-ES"), SPANISH).mismatch);
+    }
+
+    #[test]
+    fn detect_skips_short_texts() {
+        let detection = detect(&LanguageConfig::default(), Some("en"), "Hola mundo");
+
+        assert_eq!(detection.detected, None);
+        assert!(!detection.reliable);
+        assert!(!detection.mismatch);
+    }
+
+    #[test]
+    fn norwegian_variants_are_the_same_language() {
+        assert_eq!(primary_subtag("nb-NO"), primary_subtag("nn"));
+    }
+
+    #[test]
+    fn visible_text_skips_hidden_elements() {
+        let document = Html::parse_document(
+            "<html><head><title>Titulo</title><style>p {}</style></head>\
+             <body><p>Hola   mundo</p><script>var x;</script><noscript>js</noscript><p>adios</p></body></html>",
+        );
+
+        assert_eq!(visible_text(&document, 1000), "Hola mundo adios");
+    }
+}
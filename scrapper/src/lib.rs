@@ -1,21 +1,50 @@
+mod config;
+mod contacts;
 mod db;
+mod dns;
+mod domain;
+mod error;
+mod favicon;
+mod filter;
+mod geo;
+mod hreflang;
+mod http;
+mod language;
+mod priority;
+mod probe;
+mod rank;
+mod ranges;
+mod retry;
+mod schedule;
+mod security;
+mod seo;
+mod structured;
+mod tls;
 
-use cdns_rs::sync::request;
 use regex::Regex;
 use scraper::Html;
 use serde::{Deserialize, Serialize};
 
-use std::sync::Once;
+use std::sync::{Arc, Once};
 
-use db::{DatabaseDomain, DatabaseWebTechnology, DatabaseWebpage};
+pub use config::Config;
+pub use db::{DatabasePagerankDivergence, DatabaseTechnologyMatch};
+pub use error::{FetchError, ScrapperError};
+use db::{DatabaseDomain, DatabaseFetchInfo, DatabaseRedirect, DatabaseWebTechnology, DatabaseWebpage};
+use domain::SuffixList;
+use filter::{DomainFilter, FilterVerdict};
 
 #[macro_use]
 extern crate log;
 
 static INIT: Once = Once::new();
 
+const RANK_REFRESH_JOB: &str = "rank_refresh";
+
 const TAGTYPESTRING: &str = "String";
 const TAGTYPESTRINGREGEX: &str = "StringRegex";
+const TAGTYPEDNS: &str = "Dns";
+const TAGTYPEFAVICONHASH: &str = "FaviconHash";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Tags {
@@ -26,12 +55,28 @@ struct Tags {
     parents: Vec<Tags>,
 }
 
+fn load_tags(path: &str) -> Result<Vec<Tags>, ScrapperError> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| ScrapperError::Config(format!("no se pudo abrir {}: {}", path, e)))?;
+
+    serde_yaml::from_reader(file).map_err(|e| ScrapperError::Config(format!("{} invalido: {}", path, e)))
+}
+
+pub(crate) fn parse_selector(selector: &str) -> Result<scraper::Selector, ScrapperError> {
+    scraper::Selector::parse(selector).map_err(|e| ScrapperError::Parse(format!("selector {}: {:?}", selector, e)))
+}
+
+fn parse_tag_regex(tag: &Tags, value: &str) -> Result<Regex, ScrapperError> {
+    Regex::new(value).map_err(|e| ScrapperError::Config(format!("regex de {} invalida: {}", tag.name, e)))
+}
+
 async fn get_page_language(
     db_client: mongodb::Client,
+    config: &language::LanguageConfig,
     document: &Html,
     database_domain: &DatabaseDomain,
     database_webpage: &DatabaseWebpage,
-) {
+) -> Result<(), ScrapperError> {
     info!("     Buscando el idioma de la pagina");
 
     let mut active_lang: String = "".to_string();
@@ -43,125 +88,61 @@ async fn get_page_language(
     ];
 
     for meta_selector in meta_selector_list.iter() {
-        let meta_language = scraper::Selector::parse(meta_selector).unwrap();
-        let metas = document.select(&meta_language);
-        for meta in metas {
-            info!(
-                "         meta({}) = {:?}",
-                meta_selector,
-                meta.value().attr("content").unwrap()
-            );
-            active_lang = meta.value().attr("content").unwrap().to_string();
-
-            break;
+        let meta_language = parse_selector(meta_selector)?;
+        let meta = document.select(&meta_language).next();
+        if let Some(content) = meta.and_then(|meta| meta.value().attr("content")) {
+            info!("         meta({}) = {:?}", meta_selector, content);
+            active_lang = content.to_string();
         }
     }
 
-    let html_selector = scraper::Selector::parse("html").unwrap();
+    let html_selector = parse_selector("html")?;
     let html = document.select(&html_selector).next();
     if let Some(lang) = html {
         if let Some(lang_html) = lang.value().attr("lang") {
             info!("         html lang = {:?}", lang_html);
 
-            if active_lang != lang_html.to_string() {
+            if active_lang != lang_html {
                 active_lang = lang_html.to_string();
             }
         }
     }
 
-    if active_lang != "" {
-        /*
-        tokio::runtime::Runtime::new()
-            .unwrap()
-            .block_on(db::update_database_webpage_language(
-                active_lang,
-                database_domain._id,
-                database_webpage._id,
-            ))
-            .unwrap();
-            */
+    //Las paginas no siempre declaran bien su idioma, se detecta tambien en el texto
+    let text = language::visible_text(document, config.max_text_chars);
+    let declared = (!active_lang.is_empty()).then_some(active_lang.as_str());
+    let detection = language::detect(config, declared, &text);
+    info!(
+        "         declarado = {:?}, detectado = {:?} ({:?}), discrepancia = {}",
+        detection.declared, detection.detected, detection.confidence, detection.mismatch
+    );
+
+    let detected = detection.detected.clone().filter(|_| detection.reliable);
+    if let Some(language) = detection.declared.clone().or(detected) {
         db::update_database_webpage_language(
                 db_client,
-                active_lang,
+                language,
+                &detection,
                 database_domain._id,
                 database_webpage._id,
-            ).await.unwrap();
-    }
-}
-
-async fn get_pagerank(site_url: &str) -> f64 {
-    let url = format!(
-        "https://openpagerank.com/api/v1.0/getPageRank?domains[]={}",
-        site_url
-    );
-    let client = reqwest::Client::new();
-    let response = client
-        .get(url.as_str())
-        .header("API-OPR", "4cccg4w8ck0gg08csgcsc4o8c4wso4s4swk8oowo")
-        .send().await;
-
-    match response {
-        Ok(response) => {
-            let response_txt = response.text().await.unwrap();
-            let response_json =
-                serde_json::from_str::<serde_json::Value>(response_txt.as_str()).unwrap();
-            if response_json["status_code"] == 200 {
-                info!(
-                    "     Pagerank = {:?}",
-                    response_json["response"][0]["page_rank_decimal"]
-                );
-                info!("     Rank = {:?}", response_json["response"][0]["rank"]);
-
-                response_json["response"][0]["page_rank_decimal"]
-                    .as_f64()
-                    .unwrap()
-            } else {
-                warn!(
-                    "      Error al obtener el pagerank: {:?}",
-                    response_json["status_msg"]
-                );
-
-                0.00
-            }
-        }
-        Err(e) => {
-            warn!("     Error al obtener el pagerank: {:?}", e);
-
-            0.00
-        }
+            ).await?;
     }
-}
-
-async fn get_country_region_from_ip(ip: Vec<std::net::IpAddr>) {
-    let url = format!("https://ipapi.co/{}/json/", ip[0]);
-
-    info!("     url = {}", url);
-    info!("     Obtener el pais y region de la ip {}", ip[0]);
 
-    let client = reqwest::Client::new();
-    let response = client.get(url.as_str()).send().await;
-    match response {
-        Ok(response) => {
-            let response_txt = response.text().await.unwrap();
-            let response_json =
-                serde_json::from_str::<serde_json::Value>(response_txt.as_str()).unwrap();
-
-            if response_json["error"] != true {
-                info!("         Country = {:?}", response_json["country_name"]);
-                info!("          Region = {:?}", response_json["region"]);
-            } else {
-                warn!("         response_json = {:?}", response_json);
-            }
-        }
-        Err(e) => {
-            warn!("         Error al obtener el pais y region: {:?}", e);
-        }
-    }
+    Ok(())
 }
 
 pub struct Scrapper {
     site_url: String,
     site_domain: String,
+    config: Config,
+    client: reqwest::Client,
+    resolver: hickory_resolver::TokioAsyncResolver,
+    geo: Box<dyn geo::GeoProvider>,
+    ip_ranges: ranges::IpRanges,
+    rank: rank::RankCache,
+    sitemaps: hreflang::SitemapCache,
+    suffixes: Arc<SuffixList>,
+    domain_filter: DomainFilter,
 }
 
 impl Scrapper {
@@ -169,8 +150,16 @@ impl Scrapper {
      * Obtiene las urls de sitios externos que puedan existir en el html
      *
      */
-    async fn get_external_urls(&mut self, db_client: mongodb::Client, document: &Html, site_url: &str) {
-        let a_selector = scraper::Selector::parse("a").unwrap();
+    async fn get_external_urls(
+        &mut self,
+        db_client: mongodb::Client,
+        document: &Html,
+        site_url: &str,
+        page_url: &str,
+    ) -> Result<(), ScrapperError> {
+        let site_registrable = self.suffixes.registrable_domain(site_url);
+
+        let a_selector = parse_selector("a")?;
         let a_list = document.select(&a_selector);
         for a in a_list {
             let href = match a.value().attr("href") {
@@ -178,63 +167,173 @@ impl Scrapper {
                 None => continue,
             };
 
-            let site_url = site_url
-                .replace("https://", "")
-                .replace("http://", "")
-                .replace("www.", "")
-                .replace("/", "");
-
-            let banned_list = [
-                "facebook.com",
-                "twitter.com",
-                "instagram.com",
-                "www.youtube.com",
-                "linkedin.com",
-                "pinterest.com",
-                "tumblr.com",
-                "reddit.com",
-                "www.snapchat.com",
-                "www.whatsapp.com",
-                "www.messenger.com",
-                "www.quora.com",
-                "www.vk.com",
-                "www.flickr.com",
-                "www.meetup.com",
-                "apple.com",
-                "tiktok.com",
-                "google.com",
-                "spotify.com",
-                "bit.ly",
-            ];
-
-            if href.contains(&site_url) || !href.starts_with("http") 
-                || !href.starts_with("https") {
+            let domain = match domain::host_from_url(href) {
+                Some(domain) => domain,
+                None => continue,
+            };
+
+            if self.suffixes.registrable_domain(&domain) == site_registrable {
                 continue;
             }
-            if banned_list.iter().any(|banned| href.contains(banned)) {
+            if let FilterVerdict::Blocked(_) = self.domain_filter.check(&domain) {
                 continue;
             }
 
-            let re = Regex::new(r"(https?://)?(www\.)?([a-zA-Z0-9\-\.]+)").unwrap();
-            let domain = re.captures(&href).unwrap().get(3).unwrap().as_str();
+            self.add_webpage(db_client.clone(), Some(page_url), &domain, href).await?;
+        }
 
-            let domain_id = db::add_domain_to_database(db_client.clone(), domain.to_string()).await;
-            if domain_id != None {
-                let database_domain = db::get_database_domain(db_client.clone(), domain).await;
+        for (rule, dropped) in self.domain_filter.dropped_counts() {
+            info!("     Enlaces descartados por {} (acumulado): {}", rule, dropped);
+        }
 
-                //Si hay menos de 2 webpages de este dominio lo añadimos a la base de datos
-                //para que se pueda scrapear
-                let webpages_count = db::get_webpages_count_from_domain(db_client.clone(), domain_id.unwrap()).await;
-                if webpages_count < 2 { //FIXME: Usar constante
-                    info!("     Enlace externo: {:?}", href);
+        Ok(())
+    }
+
+    /*
+     * Versiones de la pagina en otros idiomas (hreflang del html y del
+     * sitemap). Sus idiomas se añaden al dominio y, si esta configurado,
+     * se encolan para que se analice todo el sitio y no solo la portada.
+     */
+    async fn get_alternate_languages(
+        &mut self,
+        db_client: mongodb::Client,
+        document: &Html,
+        page_url: &str,
+        database_domain: &DatabaseDomain,
+        database_webpage: &DatabaseWebpage,
+    ) -> Result<(), ScrapperError> {
+        info!("     Buscando versiones en otros idiomas");
+
+        let html_alternates = hreflang::from_document(document, page_url)?;
+        let sitemap_alternates = if self.config.hreflang.sitemap {
+            self.sitemaps
+                .alternates(&self.client, &self.config.http, &self.config.hreflang, page_url)
+                .await
+        } else {
+            vec![]
+        };
+        let alternates = hreflang::merge(html_alternates, sitemap_alternates);
+        if alternates.is_empty() {
+            return Ok(());
+        }
 
-                    let web_page = DatabaseWebpage::new(domain_id.unwrap(), href.to_string(), database_domain.pagerank);
-                    db::add_webpage_to_database(db_client.clone(), web_page).await;
-                } else {
-                    //info!("     Ya hay {} paginas de este dominio", webpages_count);
+        let languages = hreflang::languages(&alternates);
+        info!("         {} alternativas, idiomas = {:?}", alternates.len(), languages);
+        db::update_database_webpage_alternates(
+            db_client.clone(),
+            database_domain._id,
+            database_webpage._id,
+            &alternates,
+            &languages,
+        )
+        .await?;
+
+        if self.config.hreflang.enqueue {
+            let mut enqueued = 0;
+            for alternate in alternates.iter() {
+                if enqueued >= self.config.hreflang.max_enqueued {
+                    break;
+                }
+                if alternate.url == page_url {
+                    continue;
+                }
+                let Some(domain) = domain::host_from_url(&alternate.url) else {
+                    continue;
+                };
+                if let FilterVerdict::Blocked(_) = self.domain_filter.check(&domain) {
+                    continue;
+                }
+
+                if self.add_alternate_webpage(db_client.clone(), page_url, &domain, &alternate.url).await? {
+                    enqueued += 1;
                 }
             }
+            info!("         {} alternativas añadidas a la cola", enqueued);
+        }
+
+        Ok(())
+    }
+
+    /*
+     * Añade a la cola una version en otro idioma. A diferencia de los enlaces
+     * externos no hay limite de webpages por dominio.
+     */
+    async fn add_alternate_webpage(
+        &mut self,
+        db_client: mongodb::Client,
+        source: &str,
+        domain: &str,
+        url: &str,
+    ) -> Result<bool, ScrapperError> {
+        let new_link = db::add_webpage_link(db_client.clone(), source, url).await?;
+        if db::register_webpage_inbound_link(db_client.clone(), url, new_link).await?.is_some() {
+            return Ok(false);
+        }
+
+        let domain_id = db::add_domain_to_database(db_client.clone(), domain.to_string()).await?;
+        let database_domain = db::get_database_domain(db_client.clone(), domain).await?;
+
+        let mut web_page = DatabaseWebpage::new(domain_id, url.to_string());
+        web_page.inbound_links = Some(u64::from(new_link));
+        web_page.priority = Some(priority::compute_priority(&self.config.priority, &web_page, database_domain.pagerank));
+        web_page.queue_priority = Some(priority::queue_priority(&self.config.priority, &web_page));
+        db::add_webpage_to_database(db_client.clone(), web_page).await?;
+
+        Ok(true)
+    }
+
+    /*
+     * Añade la url a la cola si su dominio tiene menos de 2 webpages. source
+     * es la pagina donde aparece el enlace; cada pagina de origen cuenta una
+     * sola vez como enlace entrante.
+     */
+    async fn add_webpage(
+        &mut self,
+        db_client: mongodb::Client,
+        source: Option<&str>,
+        domain: &str,
+        url: &str,
+    ) -> Result<bool, ScrapperError> {
+        let new_link = match source {
+            Some(source) => db::add_webpage_link(db_client.clone(), source, url).await?,
+            None => false,
+        };
+
+        //Si la pagina ya esta en la cola solo se cuenta el enlace entrante
+        let existing = db::register_webpage_inbound_link(db_client.clone(), url, new_link).await?;
+        if let Some(webpage) = existing {
+            if !new_link {
+                return Ok(false);
+            }
+
+            let domain_pagerank = db::get_database_domain_pagerank(db_client.clone(), webpage.domain_id).await?;
+            let priority = priority::compute_priority(&self.config.priority, &webpage, domain_pagerank);
+            db::update_database_webpage_priority(db_client.clone(), webpage._id, priority, &self.config.priority).await?;
+
+            return Ok(false);
+        }
+
+        let domain_id = db::add_domain_to_database(db_client.clone(), domain.to_string()).await?;
+        let database_domain = db::get_database_domain(db_client.clone(), domain).await?;
+
+        //Si hay menos de 2 webpages de este dominio lo añadimos a la base de datos
+        //para que se pueda scrapear
+        let webpages_count = db::get_webpages_count_from_domain(db_client.clone(), domain_id).await?;
+        if webpages_count < 2 { //FIXME: Usar constante
+            info!("     Enlace externo: {:?}", url);
+
+            let mut web_page = DatabaseWebpage::new(domain_id, url.to_string());
+            web_page.inbound_links = Some(u64::from(new_link));
+            web_page.priority = Some(priority::compute_priority(&self.config.priority, &web_page, database_domain.pagerank));
+            web_page.queue_priority = Some(priority::queue_priority(&self.config.priority, &web_page));
+            db::add_webpage_to_database(db_client.clone(), web_page).await?;
+
+            return Ok(true);
+        } else {
+            //info!("     Ya hay {} paginas de este dominio", webpages_count);
         }
+
+        Ok(false)
     }
 
     async fn search_tags_in_html(
@@ -244,16 +343,16 @@ impl Scrapper {
         tags_list: Vec<Tags>,
         _database_domain: &DatabaseDomain,
         database_webpage: &DatabaseWebpage,
-    ) {
+    ) -> Result<Vec<DatabaseWebTechnology>, ScrapperError> {
         info!("     Buscando tags en el html");
 
         let mut database_web_technologies = vec![];
 
-        tags_list.iter().for_each(|tag| {
+        for tag in tags_list.iter() {
             //println!("Usar tag: {:?}", tag.name);
 
-            if tag.tag_type == TAGTYPESTRING.to_string() {
-                tag.values.iter().for_each(|value| {
+            if tag.tag_type == TAGTYPESTRING {
+                for value in tag.values.iter() {
                     if response.contains(value) {
                         info!(
                             "         Encontrado tecnología {:?} {:?}",
@@ -274,10 +373,10 @@ impl Scrapper {
                     } else {
                         //println!("{}: not found", tag.name);
                     }
-                })
-            } else if tag.tag_type == TAGTYPESTRINGREGEX.to_string() {
-                tag.values.iter().for_each(|value| {
-                    let regex = Regex::new(value).unwrap();
+                }
+            } else if tag.tag_type == TAGTYPESTRINGREGEX {
+                for value in tag.values.iter() {
+                    let regex = parse_tag_regex(tag, value)?;
 
                     //check if regex match
                     if regex.is_match(&response) {
@@ -300,18 +399,20 @@ impl Scrapper {
                     } else {
                         //println!("{}: ({:?}) not found", tag.name, value);
                     }
-                });
+                }
             } else {
                 warn!("     Tag type desconocido: {:?}", tag.tag_type);
             }
-        });
+        }
 
         //info!("         database_web_technologies = {:?}", database_web_technologies);
         db::update_database_web_technologies(
                 db_client.clone(),
                 &database_web_technologies,
                 database_webpage._id,
-            ).await.unwrap();
+            ).await?;
+
+        Ok(database_web_technologies)
     }
 
     async fn search_tags_in_headers(
@@ -321,15 +422,20 @@ impl Scrapper {
         tags_list: Vec<Tags>,
         _database_domain: &DatabaseDomain,
         database_webpage: &DatabaseWebpage,
-    ) {
+    ) -> Result<Vec<DatabaseWebTechnology>, ScrapperError> {
         info!("     Buscando tags en los headers");
         let mut database_web_headers = vec![];
 
-        tags_list.iter().for_each(|tag| {
-            if tag.tag_type == TAGTYPESTRING.to_string() {
-                tag.values.iter().for_each(|value| {
-                    headers.iter().for_each(|(_header_key, header_value)| {
-                        if header_value.to_str().unwrap().contains(value) {
+        for tag in tags_list.iter() {
+            if tag.tag_type == TAGTYPESTRING {
+                for value in tag.values.iter() {
+                    for (_header_key, header_value) in headers.iter() {
+                        let header_value = match header_value.to_str() {
+                            Ok(header_value) => header_value,
+                            Err(_) => continue,
+                        };
+
+                        if header_value.contains(value) {
                             info!(
                                 "         Encontrado tecnología {:?} {:?}",
                                 tag.tag_name, tag.name
@@ -348,14 +454,19 @@ impl Scrapper {
                         } else {
                             //println!("{}: ({:?}) not found", tag.name, value);
                         }
-                    });
-                })
-            } else if tag.tag_type == TAGTYPESTRINGREGEX.to_string() {
-                tag.values.iter().for_each(|value| {
-                    let regex = Regex::new(value).unwrap();
-
-                    headers.iter().for_each(|(_key, value)| {
-                        if regex.is_match(&value.to_str().unwrap()) {
+                    }
+                }
+            } else if tag.tag_type == TAGTYPESTRINGREGEX {
+                for value in tag.values.iter() {
+                    let regex = parse_tag_regex(tag, value)?;
+
+                    for (_key, value) in headers.iter() {
+                        let value = match value.to_str() {
+                            Ok(value) => value,
+                            Err(_) => continue,
+                        };
+
+                        if regex.is_match(value) {
                             info!(
                                 "         Encontrado tecnología {:?} {:?}",
                                 tag.tag_name, tag.name
@@ -374,50 +485,375 @@ impl Scrapper {
                         } else {
                             //println!("{}: ({:?}) not found", tag.name, value);
                         }
-                    });
-                });
+                    }
+                }
             } else {
                 warn!("     Tag type desconocido: {:?}", tag.tag_type);
             }
-        });
+        }
 
         db::update_database_web_headers(
                 db_client.clone(),
                 &database_web_headers,
                 database_webpage._id,
-            ).await.unwrap();
+            ).await?;
+
+        Ok(database_web_headers)
+    }
+
+    /*
+     * Busca tecnologias en los registros dns del dominio. Los valores de los
+     * tags Dns son "<tipo>:<regex>", con tipo a, aaaa, cname, mx, ns, txt o caa.
+     */
+    fn search_tags_in_dns(
+        &mut self,
+        dns_records: &db::DatabaseDnsRecords,
+        tags_list: &[Tags],
+    ) -> Result<Vec<DatabaseWebTechnology>, ScrapperError> {
+        info!("     Buscando tags en el dns");
+        let mut database_dns_technologies = vec![];
+
+        let mx: Vec<String> = dns_records.mx.iter().map(|mx| mx.exchange.clone()).collect();
+        for tag in tags_list.iter() {
+            if tag.tag_type != TAGTYPEDNS {
+                warn!("     Tag type desconocido: {:?}", tag.tag_type);
+                continue;
+            }
+
+            for value in tag.values.iter() {
+                let (record_type, pattern) = value
+                    .split_once(':')
+                    .ok_or_else(|| ScrapperError::Config(format!("tag dns de {} sin tipo: {}", tag.name, value)))?;
+                let records = match record_type.to_lowercase().as_str() {
+                    "a" => &dns_records.a,
+                    "aaaa" => &dns_records.aaaa,
+                    "cname" => &dns_records.cname,
+                    "mx" => &mx,
+                    "ns" => &dns_records.ns,
+                    "txt" => &dns_records.txt,
+                    "caa" => &dns_records.caa,
+                    _ => {
+                        return Err(ScrapperError::Config(format!(
+                            "tipo de registro dns {} desconocido en {}",
+                            record_type, tag.name
+                        )))
+                    }
+                };
+                let regex = parse_tag_regex(tag, pattern)?;
+
+                if records.iter().any(|record| regex.is_match(record)) {
+                    info!(
+                        "         Encontrado tecnología {:?} {:?}",
+                        tag.tag_name, tag.name
+                    );
+
+                    if !database_dns_technologies.iter().any(
+                        |web_technology: &DatabaseWebTechnology| {
+                            web_technology.name == tag.name
+                        },
+                    ) {
+                        database_dns_technologies.push(DatabaseWebTechnology {
+                            ttype: tag.tag_name.clone(),
+                            name: tag.name.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(database_dns_technologies)
     }
 
-    pub fn new() -> Self {
-        Self {
+    /*
+     * Tecnologias cuyo hash de favicon (el http.favicon.hash de Shodan)
+     * coincide con el del dominio
+     */
+    fn search_tags_in_favicon(&mut self, hash: i32, tags_list: &[Tags]) -> Result<Vec<DatabaseWebTechnology>, ScrapperError> {
+        info!("     Buscando tags en el favicon ({})", hash);
+        let mut database_favicon_technologies = vec![];
+
+        for tag in tags_list.iter() {
+            if tag.tag_type != TAGTYPEFAVICONHASH {
+                warn!("     Tag type desconocido: {:?}", tag.tag_type);
+                continue;
+            }
+
+            for value in tag.values.iter() {
+                let tag_hash = value.trim().parse::<i32>().map_err(|e| {
+                    ScrapperError::Config(format!("hash de favicon de {} invalido: {} ({})", tag.name, value, e))
+                })?;
+
+                if tag_hash == hash {
+                    info!(
+                        "         Encontrado tecnología {:?} {:?}",
+                        tag.tag_name, tag.name
+                    );
+
+                    if !database_favicon_technologies.iter().any(
+                        |web_technology: &DatabaseWebTechnology| {
+                            web_technology.name == tag.name
+                        },
+                    ) {
+                        database_favicon_technologies.push(DatabaseWebTechnology {
+                            ttype: tag.tag_name.clone(),
+                            name: tag.name.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(database_favicon_technologies)
+    }
+
+    /*
+     * Favicon del dominio con su hash. Solo se descarga si ha cambiado la
+     * url o el guardado es antiguo; si no, o si falla la descarga, se usa el
+     * que ya tiene el dominio.
+     */
+    async fn get_favicon(
+        &mut self,
+        db_client: mongodb::Client,
+        database_domain: &DatabaseDomain,
+        url: Option<&str>,
+    ) -> Result<Option<db::DatabaseFavicon>, ScrapperError> {
+        if !self.config.favicon.enabled {
+            return Ok(None);
+        }
+        let url = match url {
+            Some(url) if favicon::needs_refresh(&self.config.favicon, database_domain, url) => url,
+            _ => return Ok(database_domain.favicon.clone()),
+        };
+
+        info!("     Descargando el favicon {}", url);
+        let favicon = favicon::fetch(&self.client, &self.config.http, url).await?;
+        if let Some(favicon) = &favicon {
+            info!("         hash = {}, {} bytes", favicon.hash, favicon.size);
+            db::update_database_domain_favicon(db_client.clone(), database_domain._id, favicon).await?;
+        }
+
+        Ok(favicon.or_else(|| database_domain.favicon.clone()))
+    }
+
+    pub fn new() -> Result<Self, ScrapperError> {
+        Self::with_config(Config::load()?)
+    }
+
+    /*
+     * Paginas con una tecnologia cuyo dominio supera un pagerank
+     */
+    pub async fn search_technology(
+        &mut self,
+        technology: String,
+        min_pagerank: f64,
+    ) -> Result<Vec<db::DatabaseTechnologyMatch>, ScrapperError> {
+        self.init_logger();
+
+        let db_client = db::get_mongodb().await?;
+        db::find_webpages_by_technology(db_client, &technology, min_pagerank).await
+    }
+
+    /*
+     * Quita el pagerank duplicado de las webpages, corrigiendo su prioridad
+     */
+    pub async fn migrate_pagerank(&mut self) -> Result<u64, ScrapperError> {
+        self.init_logger();
+
+        let db_client = db::get_mongodb().await?;
+        let migrated = db::migrate_webpages_pagerank(db_client, &self.config.priority).await?;
+        info!("Migradas {} paginas", migrated);
+
+        Ok(migrated)
+    }
+
+    pub async fn check_pagerank(&mut self) -> Result<Vec<db::DatabasePagerankDivergence>, ScrapperError> {
+        self.init_logger();
+
+        let db_client = db::get_mongodb().await?;
+        db::check_pagerank_consistency(db_client).await
+    }
+
+    /*
+     * Refresca el pagerank de todos los dominios pendientes. Si hay un
+     * refresco a medias se continua desde donde se quedo.
+     */
+    pub async fn refresh_ranks(&mut self) -> Result<u64, ScrapperError> {
+        self.init_logger();
+        let dbclient = db::get_mongodb().await?;
+
+        while !self.refresh_ranks_batch(dbclient.clone()).await? {}
+
+        let job = db::get_database_job(dbclient, RANK_REFRESH_JOB).await?;
+
+        Ok(job.map(|job| job.processed).unwrap_or(0))
+    }
+
+    async fn rank_refresh_due(&mut self, db_client: mongodb::Client) -> Result<bool, ScrapperError> {
+        if self.config.rank.refresh_interval_hours <= 0 {
+            return Ok(false);
+        }
+
+        let job = db::get_database_job(db_client, RANK_REFRESH_JOB).await?;
+
+        Ok(rank::refresh_due(&self.config.rank, job.and_then(|job| job.finished_at)))
+    }
+
+    /*
+     * Procesa un lote del refresco de pagerank. Devuelve true cuando ya no
+     * quedan dominios pendientes.
+     */
+    async fn refresh_ranks_batch(&mut self, db_client: mongodb::Client) -> Result<bool, ScrapperError> {
+        let mut job = match db::get_database_job(db_client.clone(), RANK_REFRESH_JOB).await? {
+            Some(job) if job.finished_at.is_none() => job,
+            _ => {
+                let started_at = chrono::Utc::now();
+                let stale_before = started_at - chrono::Duration::hours(self.config.rank.refresh_after_hours);
+                let total = db::count_domains_to_rank(db_client.clone(), stale_before).await?;
+                info!("Refrescando el pagerank de {} dominios", total);
+
+                db::DatabaseJob {
+                    _id: RANK_REFRESH_JOB.to_string(),
+                    last_id: None,
+                    processed: 0,
+                    total,
+                    started_at,
+                    finished_at: None,
+                }
+            }
+        };
+
+        let stale_before = job.started_at - chrono::Duration::hours(self.config.rank.refresh_after_hours);
+        let domains = db::get_domains_to_rank(
+            db_client.clone(),
+            stale_before,
+            job.last_id,
+            self.config.rank.refresh_batch,
+        ).await?;
+
+        if domains.is_empty() {
+            job.finished_at = Some(chrono::Utc::now());
+            db::save_database_job(db_client, &job).await?;
+            info!("Refresco de pagerank terminado: {} dominios", job.processed);
+
+            return Ok(true);
+        }
+
+        let registrable_domains: Vec<String> = domains
+            .iter()
+            .map(|database_domain| self.suffixes.registrable_domain(&database_domain.host))
+            .collect();
+        let mut unique_domains = registrable_domains.clone();
+        unique_domains.sort();
+        unique_domains.dedup();
+        let ranks = self.rank.ranks(&unique_domains).await;
+
+        for (database_domain, registrable_domain) in domains.iter().zip(registrable_domains.iter()) {
+            match ranks.get(registrable_domain) {
+                Some(pr) => {
+                    db::update_database_domain_pagerank(db_client.clone(), database_domain._id, *pr, &self.config.priority).await?;
+                }
+                None => warn!("     Sin pagerank para {}", database_domain.host),
+            }
+
+            job.last_id = Some(database_domain._id);
+            job.processed += 1;
+        }
+        db::save_database_job(db_client, &job).await?;
+
+        info!(
+            "Pagerank refrescado en {}/{} dominios ({:.1}%)",
+            job.processed,
+            job.total,
+            100.0 * job.processed as f64 / job.total.max(1) as f64
+        );
+
+        Ok(false)
+    }
+
+    /*
+     * Regenera el fichero de rangos de ips y lo vuelve a cargar
+     */
+    pub async fn update_ip_ranges(&mut self) -> Result<usize, ScrapperError> {
+        self.init_logger();
+
+        let count = ranges::update_ranges(&self.client, &self.config.http, &self.config.ip_ranges).await?;
+        self.ip_ranges = ranges::IpRanges::load(&self.config.ip_ranges.file)?;
+
+        Ok(count)
+    }
+
+    pub fn with_config(config: Config) -> Result<Self, ScrapperError> {
+        let suffixes = Arc::new(SuffixList::load(config.public_suffix_list.as_deref()));
+        let resolver = dns::build_resolver(&config.dns)?;
+        let client = http::build_client(&config.http, resolver.clone())?;
+        let geo = geo::build_provider(&config.geo)?;
+        let ip_ranges = ranges::IpRanges::load(&config.ip_ranges.file)?;
+        let rank_provider = rank::build_provider(&config.rank, client.clone())?;
+        let rank = rank::RankCache::new(rank_provider, &config.rank);
+        let sitemaps = hreflang::SitemapCache::new(&config.hreflang);
+
+        Ok(Self {
             site_url: "".to_string(),
             site_domain: "".to_string(),
-        }
+            domain_filter: DomainFilter::new(&config.domain_filter, suffixes.clone())?,
+            suffixes,
+            client,
+            resolver,
+            geo,
+            ip_ranges,
+            rank,
+            sitemaps,
+            config,
+        })
     }
 
     fn init_logger(&mut self) {
         INIT.call_once(env_logger::init);
     }
 
-    pub async fn scrap_all(&mut self) {
+    pub async fn scrap_all(&mut self) -> Result<(), ScrapperError> {
         self.init_logger();
 
         info!("Scrapping all...");
-        let dbclient = db::get_mongodb().await.unwrap();
+        let dbclient = db::get_mongodb().await?;
+
+        db::ensure_webpages_indexes(dbclient.clone()).await?;
+        let backfilled = db::backfill_webpages_priority(dbclient.clone(), &self.config.rescrap, &self.config.priority).await?;
+        if backfilled > 0 {
+            info!("Prioridad calculada para {} paginas", backfilled);
+        }
 
         loop {
             std::thread::sleep(std::time::Duration::from_secs(5));
 
-            let database_webpage = db::get_database_webpage_to_scrap(dbclient.clone()).await;
+            //El refresco de pagerank avanza un lote por vuelta cuando toca
+            match self.rank_refresh_due(dbclient.clone()).await {
+                Ok(true) => {
+                    if let Err(e) = self.refresh_ranks_batch(dbclient.clone()).await {
+                        error!("Error al refrescar el pagerank: {}", e);
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => error!("Error al consultar el refresco de pagerank: {}", e),
+            }
 
+            let database_webpage = match db::get_database_webpage_to_scrap(dbclient.clone()).await {
+                Ok(database_webpage) => database_webpage,
+                Err(e) => {
+                    error!("Error al obtener la siguiente pagina: {}", e);
 
-            if database_webpage.is_none() {
-                info!("No hay paginas para scrapear");
+                    continue;
+                }
+            };
 
-                return ();
-            }
+            let database_webpage = match database_webpage {
+                Some(database_webpage) => database_webpage,
+                None => {
+                    info!("No hay paginas para scrapear");
 
-            let database_webpage = database_webpage.unwrap();
+                    return Ok(());
+                }
+            };
 
             //println!("database_webpage = {:?}", database_webpage);
 
@@ -436,115 +872,561 @@ impl Scrapper {
                 Ok(_) => {
                     info!("Pagina {} scrapeada correctamente", database_webpage.url);
                 }
+                Err(e) if !e.is_page_error() => {
+                    error!("Error al scrapear la pagina {}: {}", database_webpage.url, e);
+
+                    let postpone_at = self.config.retry.postpone_at();
+                    info!("Se aplaza {} hasta el {}", database_webpage.url, postpone_at);
+                    if let Err(e) = db::postpone_database_webpage(dbclient.clone(), database_webpage._id, &e, postpone_at, &self.config.priority).await {
+                        error!("Error al aplazar {}: {}", database_webpage.url, e);
+                    }
+                }
                 Err(e) => {
-                    error!("Error al scrapear la pagina {}: {:?}", database_webpage.url, e);
+                    error!("Error al scrapear la pagina {}: {}", database_webpage.url, e);
 
-                    db::update_database_webpages_set_scrappeable(
+                    let failed_attempts = database_webpage.failed_attempts.unwrap_or(0) + 1;
+                    let retry_at = self.config.retry.next_attempt_at(&e, failed_attempts);
+                    match retry_at {
+                        Some(retry_at) => info!("Reintento {} de {} el {}", failed_attempts, database_webpage.url, retry_at),
+                        None => warn!("La pagina {} deja de ser scrappeable tras {} fallos", database_webpage.url, failed_attempts),
+                    }
+
+                    if let Err(e) = db::record_database_webpage_failure(
                         dbclient.clone(),
                         database_webpage._id,
-                        false,
-                    ).await.unwrap();
+                        failed_attempts,
+                        &e,
+                        retry_at,
+                        &self.config.priority,
+                    ).await {
+                        error!("Error al guardar el fallo de {}: {}", database_webpage.url, e);
+                    }
                 }
             }
         }
     }
 
-    pub async fn scrap_site(&mut self, site_url: String, dbclient: Option<mongodb::Client>) -> Result<(), reqwest::Error> {
-        let db_client;
-        if dbclient.is_none() {
-            db_client = db::get_mongodb().await.unwrap();
+    /*
+     * Alta manual de una url en la cola de scrapeo, pasando por el mismo
+     * filtro de dominios que los enlaces descubiertos
+     */
+    pub async fn submit_url(&mut self, url: String) -> Result<bool, ScrapperError> {
+        self.init_logger();
+
+        let domain = domain::host_from_url(&url).ok_or_else(|| ScrapperError::Parse(format!("url {}", url)))?;
+
+        if let FilterVerdict::Blocked(rule) = self.domain_filter.check(&domain) {
+            warn!("La url {} esta bloqueada por la regla {}", url, rule);
+
+            return Ok(false);
+        }
+
+        let db_client = db::get_mongodb().await?;
+        self.add_webpage(db_client, None, &domain, &url).await
+    }
+
+    /*
+     * Hash de un favicon (url o fichero local) para añadirlo a
+     * favicon_tags.yaml. None si la url no devuelve una imagen.
+     */
+    pub async fn favicon_hash(&mut self, source: String) -> Result<Option<i32>, ScrapperError> {
+        self.init_logger();
+
+        if source.starts_with("http://") || source.starts_with("https://") {
+            let favicon = favicon::fetch(&self.client, &self.config.http, &source).await?;
+            return Ok(favicon.map(|favicon| favicon.hash));
+        }
+
+        let bytes = std::fs::read(&source)
+            .map_err(|e| ScrapperError::Config(format!("no se pudo leer {}: {}", source, e)))?;
+
+        Ok(Some(favicon::favicon_hash(&bytes)))
+    }
+
+    /*
+     * Boost manual de la prioridad de una url que ya esta en la cola
+     */
+    pub async fn boost_url(&mut self, url: String, boost: f64) -> Result<bool, ScrapperError> {
+        self.init_logger();
+
+        let db_client = db::get_mongodb().await?;
+        match db::update_database_webpage_boost(db_client.clone(), &url, boost).await? {
+            Some(webpage) => {
+                let domain_pagerank = db::get_database_domain_pagerank(db_client.clone(), webpage.domain_id).await?;
+                let priority = priority::compute_priority(&self.config.priority, &webpage, domain_pagerank);
+                db::update_database_webpage_priority(db_client, webpage._id, priority, &self.config.priority).await?;
+
+                info!("Prioridad de {} = {}", url, priority);
+
+                Ok(true)
+            }
+            None => {
+                warn!("La url {} no esta en la cola", url);
+
+                Ok(false)
+            }
+        }
+    }
+
+    /*
+     * Guarda la cadena de redirecciones en la pagina de origen y, si la
+     * redireccion cambia de dominio registrable, enlaza ambos dominios como alias
+     */
+    async fn save_redirects(
+        &mut self,
+        db_client: mongodb::Client,
+        site_url: &str,
+        redirects: &[http::RedirectHop],
+        final_url: &str,
+        final_domain: &DatabaseDomain,
+        canonical_rewrite: bool,
+    ) -> Result<(), ScrapperError> {
+        let source_domain = domain::host_from_url(site_url)
+            .ok_or_else(|| ScrapperError::Parse(format!("url {}", site_url)))?;
+
+        let database_redirects: Vec<DatabaseRedirect> = redirects
+            .iter()
+            .map(|hop| {
+                let target_host = reqwest::Url::parse(&hop.url)
+                    .and_then(|url| url.join(&hop.location))
+                    .ok()
+                    .and_then(|url| url.host_str().map(|host| host.to_string()));
+                let cross_domain = match (domain::host_from_url(&hop.url), target_host) {
+                    (Some(from), Some(to)) => {
+                        self.suffixes.registrable_domain(&from) != self.suffixes.registrable_domain(&to)
+                    }
+                    _ => false,
+                };
+
+                info!("     Redireccion {} {} -> {}", hop.status, hop.url, hop.location);
+
+                DatabaseRedirect {
+                    url: hop.url.clone(),
+                    status: hop.status,
+                    location: hop.location.clone(),
+                    cross_domain,
+                }
+            })
+            .collect();
+
+        let source_database_domain = db::get_database_domain(db_client.clone(), &source_domain).await?;
+        let source_webpage = db::set_database_webpage(
+            db_client.clone(),
+            site_url.to_string(),
+            source_database_domain._id,
+        ).await?;
+
+        db::update_database_webpage_redirects(
+            db_client.clone(),
+            source_webpage._id,
+            &database_redirects,
+            final_url.to_string(),
+        ).await?;
+
+        //La pagina de origen no se analiza. Si se ha cambiado por la de su origen canonico
+        //se retira de la cola, porque se descarga siempre la canonica; si es una
+        //redireccion del servidor solo se programa su siguiente visita
+        if canonical_rewrite {
+            info!("     {} se scrapea como {}", site_url, final_url);
+            db::retire_database_webpage(db_client.clone(), source_webpage._id).await?;
         } else {
-            db_client = dbclient.unwrap();
+            let interval_hours = self.config.rescrap.next_interval_hours(
+                &source_domain,
+                &[],
+                source_webpage.rescrap_interval_hours,
+                false,
+            );
+            db::update_database_webpage_schedule(db_client.clone(), source_webpage._id, interval_hours, &self.config.priority).await?;
         }
+
+        if self.suffixes.registrable_domain(&source_domain) != self.suffixes.registrable_domain(&final_domain.host) {
+            info!(" {} es alias de {}", source_domain, final_domain.host);
+
+            db::link_database_domain_aliases(db_client, source_database_domain._id, final_domain._id).await?;
+        }
+
+        Ok(())
+    }
+
+    /*
+     * Certificado del servidor de una url https, leido en una conexion aparte
+     * que acepta certificados invalidos. Los errores solo se registran.
+     */
+    async fn inspect_certificate(&self, url: &str) -> Option<db::DatabaseCertificate> {
+        let url = reqwest::Url::parse(url).ok()?;
+        if url.scheme() != "https" {
+            return None;
+        }
+        let host = url.host_str()?.trim_start_matches('[').trim_end_matches(']');
+        let port = url.port_or_known_default()?;
+
+        match tls::inspect(&self.config.tls, host, port).await {
+            Ok(certificate) => {
+                info!(
+                    " Certificado = {} ({}), caduca {}",
+                    certificate.issuer.as_deref().unwrap_or("?"),
+                    certificate.issuer_category,
+                    certificate.not_after
+                );
+                if certificate.status != "valid" {
+                    warn!(
+                        " Certificado de {} en estado {} ({})",
+                        host,
+                        certificate.status,
+                        certificate.verify_error.as_deref().unwrap_or("-")
+                    );
+                }
+                Some(certificate)
+            }
+            Err(e) => {
+                warn!(" No se pudo analizar el certificado de {}: {}", host, e);
+                None
+            }
+        }
+    }
+
+    /*
+     * Origen canonico del dominio de la url. La primera vez que se ve el
+     * dominio, y despues cada probe.refresh_hours, se prueban sus variantes
+     * http/https y apex/www.
+     */
+    async fn canonical_origin(&mut self, db_client: mongodb::Client, site_url: &str) -> Result<Option<String>, ScrapperError> {
+        let host = domain::host_from_url(site_url)
+            .ok_or_else(|| ScrapperError::Parse(format!("url {}", site_url)))?;
+        let database_domain = db::get_database_domain(db_client.clone(), &host).await?;
+
+        if !probe::needs_refresh(&self.config.probe, &database_domain) {
+            return Ok(database_domain.probe.and_then(|probe| probe.canonical_origin));
+        }
+
+        info!(" Probando las variantes de {}", host);
+        let probe = probe::probe_domain(&self.client, &self.config.http, &host).await;
+        info!(
+            "     https = {}, http -> https = {}, apex = {}, www = {}, canonico = {:?}",
+            probe.https, probe.http_redirects_to_https, probe.apex_live, probe.www_live, probe.canonical_origin
+        );
+        db::update_database_domain_probe(db_client, database_domain._id, &probe).await?;
+
+        Ok(probe.canonical_origin)
+    }
+
+    pub async fn scrap_site(&mut self, site_url: String, dbclient: Option<mongodb::Client>) -> Result<(), ScrapperError> {
+        let db_client = match dbclient {
+            Some(dbclient) => dbclient,
+            None => db::get_mongodb().await?,
+        };
         self.init_logger();
 
         info!("Scrapping... {}", site_url);
 
-        let response        = reqwest::get(site_url.as_str()).await?;
-        let headers         = response.headers().clone();
-        let response_txt    = response.text().await?.clone();
+        //Las paginas se descargan siempre desde el origen canonico del dominio
+        let fetch_url = match self.canonical_origin(db_client.clone(), &site_url).await? {
+            Some(canonical_origin) => match probe::canonical_url(&site_url, &canonical_origin) {
+                Some(canonical_url) => {
+                    info!(" Origen canonico {} -> {}", site_url, canonical_url);
+                    canonical_url
+                }
+                None => site_url.clone(),
+            },
+            None => site_url.clone(),
+        };
+
+        let page = match http::fetch(&self.client, &self.config.http, fetch_url.as_str()).await {
+            Ok(page) => page,
+            Err(e) => {
+                //Con un certificado invalido la descarga falla, pero el certificado se guarda igualmente
+                if let FetchError::Tls(_) = e {
+                    if let Some(certificate) = self.inspect_certificate(&fetch_url).await {
+                        if let Some(host) = domain::host_from_url(&fetch_url) {
+                            let database_domain = db::get_database_domain(db_client.clone(), &host).await?;
+                            db::update_database_domain_certificate(db_client.clone(), database_domain._id, &certificate).await?;
+                        }
+                    }
+                }
+                return Err(e.into());
+            }
+        };
+        info!(
+            " Status = {} {} ({} bytes, ttfb {:?}, total {:?})",
+            page.status, page.http_version, page.body_size, page.ttfb, page.total_time
+        );
 
         self.site_url = site_url.clone();
 
-        let re      = Regex::new(r"(https?://)?(www\.)?([a-zA-Z0-9\-\.]+)").unwrap();
-        let domain  = re.captures(&site_url).unwrap().get(3).unwrap().as_str();
+        //Con redirecciones el analisis se hace sobre la url final y su dominio
+        let redirected = !page.redirects.is_empty() || fetch_url != site_url;
+        let analysed_url = if !redirected {
+            site_url.clone()
+        } else {
+            page.final_url.to_string()
+        };
+
+        let domain  = domain::host_from_url(&analysed_url)
+            .ok_or_else(|| ScrapperError::Parse(format!("url {}", analysed_url)))?;
+        let domain  = domain.as_str();
         info!(" Domain = {}", domain);
 
-        let database_domain = db::get_database_domain(db_client.clone(), domain).await;
+        let database_domain = db::get_database_domain(db_client.clone(), domain).await?;
         let database_webpage = db::set_database_webpage(
             db_client.clone(),
-            site_url.clone(),
+            analysed_url.clone(),
             database_domain._id
-        ).await;
+        ).await?;
+
+        if redirected {
+            self.save_redirects(
+                db_client.clone(),
+                &site_url,
+                &page.redirects,
+                &analysed_url,
+                &database_domain,
+                fetch_url != site_url && analysed_url != site_url,
+            ).await?;
+        }
+
+        if let Some(certificate) = self.inspect_certificate(&analysed_url).await {
+            db::update_database_domain_certificate(db_client.clone(), database_domain._id, &certificate).await?;
+        }
+
+        let skipped = http::skip_reason(&page, &self.config.parked_signatures);
+        let fetch_info = DatabaseFetchInfo {
+            status: page.status.as_u16(),
+            http_version: page.http_version.clone(),
+            content_type: page.content_type.clone(),
+            charset: page.charset.clone(),
+            encoding: Some(page.encoding.clone()),
+            compression: page.compression.clone(),
+            transfer_size: page.transfer_size as u64,
+            body_size: page.body_size as u64,
+            ttfb_ms: page.ttfb.as_millis() as u64,
+            total_ms: page.total_time.as_millis() as u64,
+            skipped: skipped.clone(),
+        };
+        db::update_database_webpage_fetch(db_client.clone(), database_webpage._id, &fetch_info).await?;
+
+        if let Some(reason) = skipped {
+            info!(" La pagina no se analiza: {}", reason);
+
+            let interval_hours = self.config.rescrap.next_interval_hours(
+                domain,
+                &[],
+                database_webpage.rescrap_interval_hours,
+                false,
+            );
+            db::update_database_webpage_schedule(db_client.clone(), database_webpage._id, interval_hours, &self.config.priority).await?;
+
+            return Ok(());
+        }
+
+        let headers         = page.headers;
+        let response_txt    = page.body;
 
         info!(" domain_id = {:?}", database_domain._id);
 
         self.site_domain = domain.to_string();
 
-        if database_domain.pagerank.is_none() {
-            info!("Obtener el pagerank del sitio {}", domain);
-            let pr = get_pagerank(domain).await;
-            db::update_database_domain_pagerank(db_client.clone(), database_domain._id, pr).await;
+        match database_domain.pagerank {
+            Some(pr) => info!(" Pagerank = {:?}", pr),
+            None => {
+                //El ranking es del dominio registrable, no del subdominio
+                let registrable_domain = self.suffixes.registrable_domain(domain);
+                info!("Obtener el pagerank del sitio {}", registrable_domain);
 
-            info!(" Pagerank = {:?}", pr);
-        } else {
-            let pr = database_domain.pagerank.unwrap();
-            info!(" Pagerank = {:?}", pr)
-        }
+                match self.rank.rank(&registrable_domain).await {
+                    Some(pr) => {
+                        db::update_database_domain_pagerank(db_client.clone(), database_domain._id, pr, &self.config.priority).await?;
 
-        let res_a = request::resolve_fqdn(domain, None);
-        let ip = match res_a {
-            Ok(ip) => ip,
-            Err(e) => {
-                error!("Error al obtener la ip: {:?}", e);
+                        info!(" Pagerank = {:?}", pr);
+                    }
+                    None => info!(" Pagerank no disponible, se pedira en la proxima visita"),
+                }
+            }
+        }
 
-                return Ok(());
+        //Los registros dns son del dominio, solo se piden si los guardados han caducado
+        let dns_records = match database_domain.dns.clone() {
+            Some(dns_records) if !dns::needs_refresh(&self.config.dns, &database_domain) => {
+                info!(" DNS resuelto el {}", dns_records.resolved_at);
+                dns_records
+            }
+            _ => {
+                let dns_records = dns::collect_records(&self.resolver, domain).await?;
+                db::update_database_domain_dns(db_client.clone(), database_domain._id, &dns_records).await?;
+                dns_records
             }
         };
-
+        info!(
+            " DNS = A {:?}, AAAA {:?}, CNAME {:?}, MX {}, NS {:?}",
+            dns_records.a, dns_records.aaaa, dns_records.cname, dns_records.mx.len(), dns_records.ns
+        );
+
+        let ip: Vec<std::net::IpAddr> = dns_records
+            .a
+            .iter()
+            .chain(dns_records.aaaa.iter())
+            .filter_map(|ip| ip.parse().ok())
+            .collect();
         info!(" IP = {:?}", ip);
-        if ip.len() > 0 {
-            get_country_region_from_ip(ip.clone()).await;
+        if !ip.is_empty() {
+            let mut geo_ips = vec![];
+            for address in ip.iter() {
+                match self.geo.lookup(*address) {
+                    Ok(Some(geo_ip)) => {
+                        info!(
+                            "     {} = {:?} {:?} {:?} AS{:?} {:?}",
+                            address, geo_ip.country_code, geo_ip.region, geo_ip.city, geo_ip.asn, geo_ip.organization
+                        );
+                        geo_ips.push(geo_ip);
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!("         Error al geolocalizar la ip {}: {}", address, e),
+                }
+            }
+            if !geo_ips.is_empty() {
+                db::update_database_domain_geo(db_client.clone(), database_domain._id, &geo_ips).await?;
+            }
+
+            let (hosting, cdn) = self.ip_ranges.attribute(&ip);
+            info!(" Hosting = {:?}, CDN = {:?}", hosting, cdn);
+            db::update_database_domain_providers(db_client.clone(), database_domain._id, &hosting, &cdn).await?;
 
             db::update_database_domain_ip(
                     db_client.clone(),
                     database_domain._id,
                     ip[0].to_string(),
-            ).await;
+            ).await?;
         }
 
-        //Listado de Tags
-        let file = std::fs::File::open("body_tags.yaml").unwrap();
-        let body_tags_list: Vec<Tags> = serde_yaml::from_reader(file).unwrap();
+        //Ahora analizar el html
+        let document = scraper::Html::parse_document(&response_txt);
 
-        let file = std::fs::File::open("headers_tags.yaml").unwrap();
-        let headers_tags_list: Vec<Tags> = serde_yaml::from_reader(file).unwrap();
+        //Metadatos SEO
+        info!("     Extrayendo los metadatos SEO");
+        let seo = seo::extract(&document, &analysed_url)?;
+        info!("         title = {:?}, canonical = {:?}, robots = {:?}", seo.title, seo.canonical, seo.robots);
+        db::update_database_webpage_seo(db_client.clone(), database_webpage._id, &seo).await?;
+
+        //Listado de Tags
+        let body_tags_list = load_tags("body_tags.yaml")?;
+        let headers_tags_list = load_tags("headers_tags.yaml")?;
+        let dns_tags_list = load_tags("dns_tags.yaml")?;
+        let favicon_tags_list = load_tags("favicon_tags.yaml")?;
 
         
         //println!("headers = {:?}", headers);
-        self.search_tags_in_html(
+        let security_audit = security::audit_headers(&headers, analysed_url.starts_with("https://"));
+        info!(" Seguridad = {} ({})", security_audit.grade, security_audit.score);
+        db::update_database_webpage_security(db_client.clone(), database_webpage._id, &security_audit).await?;
+
+        let web_technologies = self.search_tags_in_html(
             db_client.clone(),
             response_txt.clone(),
             body_tags_list.clone(),
             &database_domain,
             &database_webpage,
-        ).await;
-        self.search_tags_in_headers(
+        ).await?;
+        let web_headers = self.search_tags_in_headers(
             db_client.clone(),
             headers,
             headers_tags_list.clone(),
             &database_domain,
             &database_webpage,
-        ).await;
-
-        //Ahora analizar el html
-        let document = scraper::Html::parse_document(&response_txt);
+        ).await?;
+
+        //Programar la siguiente visita segun hayan cambiado o no las detecciones
+        let changed = schedule::detections_changed(&database_webpage.technologies, &web_technologies)
+            || schedule::detections_changed(&database_webpage.headers, &web_headers);
+        let detections: Vec<DatabaseWebTechnology> = web_technologies.into_iter().chain(web_headers).collect();
+
+        //Las tecnologias del dominio juntan las del dns y el favicon con las de
+        //sus paginas. Las que vienen del dns o del favicon se sustituyen cada
+        //vez por las actuales.
+        let web_dns = self.search_tags_in_dns(&dns_records, &dns_tags_list)?;
+        let web_favicon = match self.get_favicon(db_client.clone(), &database_domain, seo.favicon.as_deref()).await? {
+            Some(favicon) => self.search_tags_in_favicon(favicon.hash, &favicon_tags_list)?,
+            None => vec![],
+        };
+        let mut domain_technologies: Vec<DatabaseWebTechnology> = database_domain
+            .technologies
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|technology| {
+                !dns_tags_list
+                    .iter()
+                    .chain(favicon_tags_list.iter())
+                    .any(|tag| tag.name == technology.name)
+            })
+            .collect();
+        for technology in detections.iter().chain(web_dns.iter()).chain(web_favicon.iter()) {
+            if !domain_technologies.iter().any(|domain_technology| domain_technology.name == technology.name) {
+                domain_technologies.push(technology.clone());
+            }
+        }
+        db::update_database_domain_technologies(db_client.clone(), database_domain._id, &domain_technologies).await?;
+        let interval_hours = self.config.rescrap.next_interval_hours(
+            domain,
+            &detections,
+            database_webpage.rescrap_interval_hours,
+            changed,
+        );
+        info!(" Siguiente visita en {} horas (cambios = {})", interval_hours, changed);
+        db::update_database_webpage_schedule(db_client.clone(), database_webpage._id, interval_hours, &self.config.priority).await?;
 
         //buscamos el idioma de la pagina
-        get_page_language(db_client.clone(), &document, &database_domain, &database_webpage).await;
+        get_page_language(
+            db_client.clone(),
+            &self.config.language,
+            &document,
+            &database_domain,
+            &database_webpage,
+        )
+        .await?;
+
+        //Datos estructurados (json-ld y microdata)
+        info!("     Buscando datos estructurados");
+        let entities = structured::extract(&document)?;
+        if !entities.is_empty() {
+            info!("         {} entidades", entities.len());
+            db::update_database_webpage_structured_data(db_client.clone(), database_webpage._id, &entities).await?;
+        }
+        if let Some(organization) = structured::organization(&entities) {
+            info!("         Organizacion = {:?}", organization.name);
+            db::update_database_domain_organization(db_client.clone(), database_domain._id, &organization).await?;
+        }
+
+        //Perfiles sociales, emails y telefonos. Las redes sociales no se
+        //scrapean pero sus enlaces son los perfiles del sitio
+        let contacts = contacts::extract(&self.config.contacts, &document)?;
+        if !contacts.social.is_empty() || !contacts.emails.is_empty() || !contacts.phones.is_empty() {
+            info!(
+                "     Contactos: {} perfiles, {} emails, {} telefonos",
+                contacts.social.len(),
+                contacts.emails.len(),
+                contacts.phones.len()
+            );
+            db::update_database_domain_contacts(
+                db_client.clone(),
+                database_domain._id,
+                &contacts,
+                self.config.contacts.max_per_domain,
+            )
+            .await?;
+        }
+
+        //y sus versiones en otros idiomas
+        self.get_alternate_languages(db_client.clone(), &document, &analysed_url, &database_domain, &database_webpage)
+            .await?;
 
         //Obtener urls de sitios externos
-        self.get_external_urls(db_client.clone(), &document, domain).await;
+        self.get_external_urls(db_client.clone(), &document, domain, &analysed_url).await?;
+
+        //Recalcular la prioridad ahora que scrapped_at ha cambiado
+        if let Some(webpage) = db::get_database_webpage(db_client.clone(), database_webpage._id).await? {
+            let domain_pagerank = db::get_database_domain_pagerank(db_client.clone(), webpage.domain_id).await?;
+            let priority = priority::compute_priority(&self.config.priority, &webpage, domain_pagerank);
+            db::update_database_webpage_priority(db_client.clone(), webpage._id, priority, &self.config.priority).await?;
+        }
 
         info!("Scraping finished!");
         
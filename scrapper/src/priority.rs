@@ -0,0 +1,130 @@
+use chrono::Utc;
+use serde::Deserialize;
+
+use crate::db::DatabaseWebpage;
+
+/*
+ * Pesos de cada componente de la prioridad. Cada componente esta
+ * normalizado aproximadamente entre 0 y 10 antes de aplicar el peso.
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PriorityConfig {
+    pub pagerank_weight: f64,
+    pub freshness_weight: f64,
+    pub freshness_cap_days: i64,
+    pub inbound_links_weight: f64,
+}
+
+impl Default for PriorityConfig {
+    fn default() -> Self {
+        Self {
+            pagerank_weight: 1.0,
+            freshness_weight: 1.0,
+            freshness_cap_days: 30,
+            inbound_links_weight: 1.0,
+        }
+    }
+}
+
+/*
+ * Parte guardada de la prioridad de la pagina en la cola de scrapeo:
+ *  - pagerank del dominio (0-10), que se guarda solo en el dominio
+ *  - enlaces entrantes descubiertos, en escala logaritmica
+ *  - boost manual, que se suma tal cual
+ * La frescura no forma parte de ella, se suma en queue_priority.
+ */
+pub fn compute_priority(config: &PriorityConfig, webpage: &DatabaseWebpage, domain_pagerank: Option<f64>) -> f64 {
+    let pagerank = domain_pagerank.unwrap_or(0.0);
+
+    let inbound_links = webpage.inbound_links.unwrap_or(0) as f64;
+    let links = (2.0 * (1.0 + inbound_links).ln()).min(10.0);
+
+    config.pagerank_weight * pagerank
+        + config.inbound_links_weight * links
+        + webpage.boost.unwrap_or(0.0)
+}
+
+/*
+ * Prioridad con la que se ordena la cola: la guardada mas la frescura, los
+ * dias entre el ultimo scrapeo y la fecha en que la pagina vuelve a la cola,
+ * saturados en freshness_cap_days. Al actualizar webpages la misma cuenta
+ * la hace la base de datos (ver db::queue_priority_stage).
+ */
+pub fn queue_priority(config: &PriorityConfig, webpage: &DatabaseWebpage) -> f64 {
+    let cap_days = config.freshness_cap_days.max(1);
+    let due_at = webpage.next_scrap_at.unwrap_or_else(Utc::now);
+    let freshness_days = (due_at - webpage.scrapped_at).num_days().clamp(0, cap_days);
+
+    webpage.priority.unwrap_or(0.0) + config.freshness_weight * 10.0 / cap_days as f64 * freshness_days as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webpage(inbound_links: u64, boost: Option<f64>) -> DatabaseWebpage {
+        let mut webpage = DatabaseWebpage::new(mongodb::bson::oid::ObjectId::new(), "https://acme.com/".to_string());
+        webpage.inbound_links = Some(inbound_links);
+        webpage.boost = boost;
+        webpage
+    }
+
+    #[test]
+    fn priority_adds_pagerank_links_and_boost() {
+        let config = PriorityConfig::default();
+
+        assert_eq!(compute_priority(&config, &webpage(0, None), None), 0.0);
+        assert_eq!(compute_priority(&config, &webpage(0, None), Some(6.5)), 6.5);
+        assert_eq!(compute_priority(&config, &webpage(0, Some(3.0)), Some(6.5)), 9.5);
+        assert_eq!(compute_priority(&config, &webpage(0, Some(-2.0)), Some(6.5)), 4.5);
+
+        let links = compute_priority(&config, &webpage(10, None), None);
+        assert!((links - 2.0 * 11f64.ln()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn inbound_links_are_logarithmic_and_capped() {
+        let config = PriorityConfig::default();
+
+        let few = compute_priority(&config, &webpage(1, None), None);
+        let some = compute_priority(&config, &webpage(100, None), None);
+        let many = compute_priority(&config, &webpage(1_000_000, None), None);
+
+        assert!(few < some && some < many);
+        assert_eq!(many, 10.0);
+    }
+
+    #[test]
+    fn weights_scale_each_component() {
+        let config = PriorityConfig {
+            pagerank_weight: 2.0,
+            inbound_links_weight: 0.0,
+            ..PriorityConfig::default()
+        };
+
+        assert_eq!(compute_priority(&config, &webpage(1_000_000, Some(1.0)), Some(5.0)), 11.0);
+    }
+
+    #[test]
+    fn queue_priority_adds_capped_freshness() {
+        let config = PriorityConfig::default();
+        let now = Utc::now();
+        let mut page = webpage(0, None);
+        page.priority = Some(4.0);
+        page.next_scrap_at = Some(now);
+
+        //Nunca scrapeada (scrapped_at en 1970): frescura maxima
+        assert_eq!(queue_priority(&config, &page), 14.0);
+
+        page.scrapped_at = now - chrono::Duration::days(3);
+        assert_eq!(queue_priority(&config, &page), 5.0);
+
+        page.scrapped_at = now - chrono::Duration::hours(12);
+        assert_eq!(queue_priority(&config, &page), 4.0);
+
+        //Un reintento programado antes del ultimo scrapeo no resta
+        page.next_scrap_at = Some(now - chrono::Duration::days(1));
+        assert_eq!(queue_priority(&config, &page), 4.0);
+    }
+}
@@ -0,0 +1,326 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::db::{DatabaseDomain, DatabaseProbe, DatabaseProbeResult};
+use crate::http::HttpConfig;
+
+/*
+ * Las variantes del dominio se vuelven a probar pasadas refresh_hours, por
+ * si el sitio cambia de origen canonico (pasa a https, quita el www...)
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ProbeConfig {
+    pub refresh_hours: i64,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self { refresh_hours: 720 }
+    }
+}
+
+/*
+ * Indica si hay que volver a probar el dominio o vale la prueba que tiene
+ */
+pub fn needs_refresh(config: &ProbeConfig, domain: &DatabaseDomain) -> bool {
+    match &domain.probe {
+        Some(probe) => chrono::Utc::now() - probe.probed_at > chrono::Duration::hours(config.refresh_hours),
+        None => true,
+    }
+}
+
+/*
+ * Prueba a la vez las cuatro combinaciones http/https y apex/www de un
+ * dominio siguiendo las redirecciones sin leer el cuerpo. El origen
+ * canonico es aquel al que llegan mas variantes, prefiriendo https en caso
+ * de empate.
+ */
+pub async fn probe_domain(client: &reqwest::Client, config: &HttpConfig, host: &str) -> DatabaseProbe {
+    let www_host = format!("www.{}", host);
+
+    let urls = [
+        format!("http://{}/", host),
+        format!("http://{}/", www_host),
+        format!("https://{}/", host),
+        format!("https://{}/", www_host),
+    ];
+    let (http_apex, http_www, https_apex, https_www) = futures_util::join!(
+        probe_origin(client, config, &urls[0]),
+        probe_origin(client, config, &urls[1]),
+        probe_origin(client, config, &urls[2]),
+        probe_origin(client, config, &urls[3]),
+    );
+    let results = vec![http_apex, http_www, https_apex, https_www];
+
+    let live = |scheme: &str, probe_host: &str| {
+        results
+            .iter()
+            .any(|result| result.url == format!("{}://{}/", scheme, probe_host) && result.live)
+    };
+    let https = live("https", host) || live("https", &www_host);
+    let apex_live = live("http", host) || live("https", host);
+    let www_live = live("http", &www_host) || live("https", &www_host);
+
+    //Las variantes http que estan vivas deben acabar todas en https
+    let http_results: Vec<&DatabaseProbeResult> =
+        results.iter().filter(|result| result.url.starts_with("http://") && result.live).collect();
+    let http_redirects_to_https = !http_results.is_empty()
+        && http_results.iter().all(|result| {
+            result
+                .final_url
+                .as_deref()
+                .is_some_and(|final_url| final_url.starts_with("https://"))
+        });
+
+    let mut origins: HashMap<String, usize> = HashMap::new();
+    for result in results.iter().filter(|result| result.live) {
+        if let Some(origin) = result.final_url.as_deref().and_then(origin) {
+            *origins.entry(origin).or_default() += 1;
+        }
+    }
+    let canonical_origin = origins
+        .into_iter()
+        .max_by_key(|(origin, count)| (*count, origin.starts_with("https://"), std::cmp::Reverse(origin.len())))
+        .map(|(origin, _)| origin);
+
+    DatabaseProbe {
+        results,
+        https,
+        http_redirects_to_https,
+        apex_live,
+        www_live,
+        canonical_origin,
+        probed_at: chrono::Utc::now(),
+    }
+}
+
+async fn probe_origin(client: &reqwest::Client, config: &HttpConfig, url: &str) -> DatabaseProbeResult {
+    let mut result = DatabaseProbeResult {
+        url: url.to_string(),
+        status: None,
+        final_url: None,
+        live: false,
+        error: None,
+    };
+
+    let mut current_url = match reqwest::Url::parse(url) {
+        Ok(url) => url,
+        Err(e) => {
+            result.error = Some(e.to_string());
+            return result;
+        }
+    };
+
+    for _ in 0..=config.max_redirects {
+        let response = match client.get(current_url.clone()).send().await {
+            Ok(response) => response,
+            Err(e) => {
+                result.error = Some(crate::error::FetchError::from(e).kind().to_string());
+                return result;
+            }
+        };
+
+        //El codigo que se guarda es el de la primera respuesta
+        if result.status.is_none() {
+            result.status = Some(response.status().as_u16());
+        }
+
+        let next_url = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| current_url.join(location).ok());
+        match next_url {
+            Some(next_url) if response.status().is_redirection() => current_url = next_url,
+            _ => {
+                result.live = response.status().is_success();
+                result.final_url = Some(current_url.to_string());
+                return result;
+            }
+        }
+    }
+
+    result.error = Some("too_many_redirects".to_string());
+    result
+}
+
+/*
+ * scheme://host[:port] de una url
+ */
+pub fn origin(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let origin = url.origin();
+
+    origin.is_tuple().then(|| origin.ascii_serialization())
+}
+
+/*
+ * La misma url pero con el origen canonico, o None si ya lo tiene
+ */
+pub fn canonical_url(url: &str, canonical_origin: &str) -> Option<String> {
+    if origin(url)? == canonical_origin {
+        return None;
+    }
+
+    let url = reqwest::Url::parse(url).ok()?;
+    let mut canonical = reqwest::Url::parse(canonical_origin).ok()?;
+    canonical.set_path(url.path());
+    canonical.set_query(url.query());
+
+    Some(canonical.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::ssl::{Ssl, SslAcceptor, SslMethod};
+    use std::pin::Pin;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+
+    /*
+     * Proxy local que hace de todos los sitios: contesta a cada origen
+     * (scheme://host) con su respuesta y abre los tuneles https con un
+     * certificado autofirmado. Los origenes que no estan cierran la conexion.
+     */
+    async fn serve(sites: Vec<(&'static str, &'static str)>) -> reqwest::Client {
+        let (key, certificate) = crate::tls::tests::self_signed("acme.test", 1, 90);
+        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+        acceptor.set_private_key(&key).unwrap();
+        acceptor.set_certificate(&certificate).unwrap();
+        let acceptor = acceptor.build();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let sites = sites.clone();
+                let context = acceptor.context().to_owned();
+                tokio::spawn(async move {
+                    let request = read_request(&mut stream).await;
+                    let mut parts = request.split_whitespace();
+                    let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+
+                    if method == "CONNECT" {
+                        let host = target.trim_end_matches(":443");
+                        let Some(response) = respond(&sites, &format!("https://{}", host)) else {
+                            return;
+                        };
+                        stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n").await.unwrap();
+                        let mut stream = tokio_openssl::SslStream::new(Ssl::new(&context).unwrap(), stream).unwrap();
+                        if Pin::new(&mut stream).accept().await.is_ok() {
+                            read_request(&mut stream).await;
+                            let _ = stream.write_all(response.as_bytes()).await;
+                        }
+                    } else if let Some(response) = origin(target).and_then(|origin| respond(&sites, &origin)) {
+                        let _ = stream.write_all(response.as_bytes()).await;
+                    }
+                });
+            }
+        });
+
+        reqwest::Client::builder()
+            .proxy(reqwest::Proxy::all(format!("http://127.0.0.1:{}", port)).unwrap())
+            .danger_accept_invalid_certs(true)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+    }
+
+    async fn read_request<S: AsyncRead + Unpin>(stream: &mut S) -> String {
+        let mut request = vec![];
+        let mut buffer = [0u8; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+            match stream.read(&mut buffer).await {
+                Ok(0) | Err(_) => break,
+                Ok(read) => request.extend_from_slice(&buffer[..read]),
+            }
+        }
+
+        String::from_utf8_lossy(&request).to_string()
+    }
+
+    fn respond(sites: &[(&'static str, &'static str)], origin: &str) -> Option<String> {
+        let (_, location) = sites.iter().find(|(site, _)| *site == origin)?;
+        if location.is_empty() {
+            return Some("HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string());
+        }
+
+        Some(format!(
+            "HTTP/1.1 301 Moved Permanently\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            location
+        ))
+    }
+
+    async fn probe(sites: Vec<(&'static str, &'static str)>) -> DatabaseProbe {
+        let client = serve(sites).await;
+        probe_domain(&client, &HttpConfig::default(), "acme.test").await
+    }
+
+    fn urls(probe: &DatabaseProbe) -> Vec<&str> {
+        probe.results.iter().map(|result| result.url.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn http_redirects_to_https() {
+        //Sin location el sitio contesta 200
+        let probe = probe(vec![
+            ("http://acme.test", "https://acme.test/"),
+            ("http://www.acme.test", "https://acme.test/"),
+            ("https://acme.test", ""),
+            ("https://www.acme.test", "https://acme.test/"),
+        ])
+        .await;
+
+        assert_eq!(
+            urls(&probe),
+            vec!["http://acme.test/", "http://www.acme.test/", "https://acme.test/", "https://www.acme.test/"]
+        );
+        assert!(probe.results.iter().all(|result| result.live));
+        assert_eq!(probe.results[0].status, Some(301));
+        assert!(probe.https);
+        assert!(probe.http_redirects_to_https);
+        assert!(probe.apex_live && probe.www_live);
+        assert_eq!(probe.canonical_origin.as_deref(), Some("https://acme.test"));
+    }
+
+    #[tokio::test]
+    async fn apex_redirects_to_www() {
+        let probe = probe(vec![
+            ("http://acme.test", "http://www.acme.test/"),
+            ("http://www.acme.test", ""),
+        ])
+        .await;
+
+        assert!(probe.results[0].live);
+        assert_eq!(probe.results[0].final_url.as_deref(), Some("http://www.acme.test/"));
+        assert!(probe.results[2].error.is_some() && probe.results[3].error.is_some());
+        assert!(!probe.https);
+        assert!(!probe.http_redirects_to_https);
+        assert!(probe.apex_live && probe.www_live);
+        assert_eq!(probe.canonical_origin.as_deref(), Some("http://www.acme.test"));
+    }
+
+    #[tokio::test]
+    async fn unreachable_host_has_no_origin() {
+        let probe = probe(vec![]).await;
+
+        assert_eq!(probe.results.len(), 4);
+        assert!(probe
+            .results
+            .iter()
+            .all(|result| !result.live && result.status.is_none() && result.error.is_some()));
+        assert!(!probe.https && !probe.http_redirects_to_https);
+        assert!(!probe.apex_live && !probe.www_live);
+        assert_eq!(probe.canonical_origin, None);
+    }
+
+    #[test]
+    fn canonical_url_keeps_path_and_query() {
+        assert_eq!(
+            canonical_url("http://acme.test/tienda?page=2", "https://www.acme.test").as_deref(),
+            Some("https://www.acme.test/tienda?page=2")
+        );
+        assert_eq!(canonical_url("https://www.acme.test/tienda", "https://www.acme.test"), None);
+    }
+}
@@ -0,0 +1,390 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+use crate::db::DatabaseProviderMatch;
+use crate::error::ScrapperError;
+use crate::http::{self, HttpConfig};
+
+const DEFAULT_RANGES_FILE: &str = "ip_ranges.yaml";
+
+/*
+ * Rangos de ips de proveedores de hosting y cdn. El fichero se puede
+ * regenerar con update_ranges a partir de las listas publicas de sources.
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct IpRangesConfig {
+    pub file: String,
+    pub sources: Vec<IpRangeSource>,
+}
+
+impl Default for IpRangesConfig {
+    fn default() -> Self {
+        Self {
+            file: DEFAULT_RANGES_FILE.to_string(),
+            sources: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderKind {
+    Hosting,
+    Cdn,
+}
+
+/*
+ * Lista publica de rangos. format es plain (un cidr por linea), aws, gcp o
+ * fastly. En aws se puede elegir por servicio: services se queda solo con
+ * los rangos de esos servicios y exclude_services quita los rangos que
+ * aparecen en esos (CLOUDFRONT es cdn aunque tambien figura en AMAZON).
+ */
+#[derive(Debug, Clone, Deserialize)]
+pub struct IpRangeSource {
+    pub provider: String,
+    pub kind: ProviderKind,
+    pub url: String,
+    pub format: String,
+    #[serde(default)]
+    pub services: Vec<String>,
+    #[serde(default)]
+    pub exclude_services: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderRanges {
+    pub provider: String,
+    pub kind: ProviderKind,
+    pub ranges: Vec<String>,
+}
+
+/*
+ * Arbol binario de prefijos, uno para ipv4 y otro para ipv6. Cada nodo
+ * guarda el indice del proveedor si algun rango termina en el.
+ */
+struct PrefixTree {
+    children: Vec<[Option<u32>; 2]>,
+    values: Vec<Option<usize>>,
+}
+
+impl PrefixTree {
+    fn new() -> Self {
+        Self {
+            children: vec![[None, None]],
+            values: vec![None],
+        }
+    }
+
+    fn insert(&mut self, bits: u128, width: u8, prefix_len: u8, value: usize) {
+        let mut node = 0;
+        for i in 0..prefix_len {
+            let bit = ((bits >> (width - 1 - i)) & 1) as usize;
+            node = match self.children[node][bit] {
+                Some(child) => child as usize,
+                None => {
+                    self.children.push([None, None]);
+                    self.values.push(None);
+                    let child = self.children.len() - 1;
+                    self.children[node][bit] = Some(child as u32);
+                    child
+                }
+            };
+        }
+        self.values[node] = Some(value);
+    }
+
+    //Devuelve el valor del prefijo mas largo que contiene la ip
+    fn longest_match(&self, bits: u128, width: u8) -> Option<usize> {
+        let mut node = 0;
+        let mut best = self.values[0];
+        for i in 0..width {
+            let bit = ((bits >> (width - 1 - i)) & 1) as usize;
+            match self.children[node][bit] {
+                Some(child) => node = child as usize,
+                None => break,
+            }
+            if self.values[node].is_some() {
+                best = self.values[node];
+            }
+        }
+
+        best
+    }
+}
+
+pub struct IpRanges {
+    providers: Vec<(String, ProviderKind)>,
+    v4: PrefixTree,
+    v6: PrefixTree,
+}
+
+impl IpRanges {
+    /*
+     * Carga el fichero de rangos. Si no existe se usa una lista vacia.
+     */
+    pub fn load(path: &str) -> Result<Self, ScrapperError> {
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("No se pudo abrir el fichero de rangos {}: {:?}, no se detectaran proveedores", path, e);
+
+                return Ok(Self::from_ranges(&[]));
+            }
+        };
+        let ranges: Vec<ProviderRanges> = serde_yaml::from_reader(file)
+            .map_err(|e| ScrapperError::Config(format!("{} invalido: {}", path, e)))?;
+
+        Ok(Self::from_ranges(&ranges))
+    }
+
+    pub fn from_ranges(ranges: &[ProviderRanges]) -> Self {
+        let mut ip_ranges = Self {
+            providers: vec![],
+            v4: PrefixTree::new(),
+            v6: PrefixTree::new(),
+        };
+
+        for provider in ranges {
+            let index = ip_ranges.providers.len();
+            ip_ranges.providers.push((provider.provider.clone(), provider.kind));
+
+            for range in &provider.ranges {
+                match parse_cidr(range) {
+                    Some((IpAddr::V4(ip), prefix_len)) => {
+                        ip_ranges.v4.insert(u32::from(ip) as u128, 32, prefix_len, index)
+                    }
+                    Some((IpAddr::V6(ip), prefix_len)) => {
+                        ip_ranges.v6.insert(u128::from(ip), 128, prefix_len, index)
+                    }
+                    None => warn!("Rango {} de {} invalido", range, provider.provider),
+                }
+            }
+        }
+
+        ip_ranges
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Option<(&str, ProviderKind)> {
+        let index = match ip {
+            IpAddr::V4(ip) => self.v4.longest_match(u32::from(ip) as u128, 32),
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => self.v4.longest_match(u32::from(ip) as u128, 32),
+                None => self.v6.longest_match(u128::from(ip), 128),
+            },
+        }?;
+        let (provider, kind) = &self.providers[index];
+
+        Some((provider.as_str(), *kind))
+    }
+
+    /*
+     * Proveedor de hosting y cdn de un dominio a partir de sus ips. La
+     * confianza es la fraccion de ips que pertenecen al proveedor elegido.
+     */
+    pub fn attribute(&self, ips: &[IpAddr]) -> (Option<DatabaseProviderMatch>, Option<DatabaseProviderMatch>) {
+        let mut counts: HashMap<(&str, ProviderKind), usize> = HashMap::new();
+        for ip in ips {
+            if let Some(provider) = self.lookup(*ip) {
+                *counts.entry(provider).or_default() += 1;
+            }
+        }
+
+        let best = |kind: ProviderKind| {
+            counts
+                .iter()
+                .filter(|((_, provider_kind), _)| *provider_kind == kind)
+                .max_by_key(|((provider, _), count)| (**count, std::cmp::Reverse(*provider)))
+                .map(|((provider, _), count)| DatabaseProviderMatch {
+                    provider: provider.to_string(),
+                    confidence: *count as f64 / ips.len() as f64,
+                })
+        };
+
+        (best(ProviderKind::Hosting), best(ProviderKind::Cdn))
+    }
+}
+
+/*
+ * Descarga las listas de sources y reescribe el fichero de rangos. Los
+ * proveedores del fichero que no tienen source se conservan tal cual.
+ */
+pub async fn update_ranges(
+    client: &reqwest::Client,
+    http_config: &HttpConfig,
+    config: &IpRangesConfig,
+) -> Result<usize, ScrapperError> {
+    let mut ranges: Vec<ProviderRanges> = match std::fs::File::open(&config.file) {
+        Ok(file) => serde_yaml::from_reader(file)
+            .map_err(|e| ScrapperError::Config(format!("{} invalido: {}", config.file, e)))?,
+        Err(_) => vec![],
+    };
+
+    for source in &config.sources {
+        info!("Descargando rangos de {} ({})", source.provider, source.url);
+        let body = http::fetch(client, http_config, &source.url).await?.body;
+        let source_ranges = parse_source(source, &body)?;
+        info!("     {} rangos", source_ranges.len());
+
+        ranges.retain(|provider| provider.provider != source.provider);
+        ranges.push(ProviderRanges {
+            provider: source.provider.clone(),
+            kind: source.kind,
+            ranges: source_ranges,
+        });
+    }
+
+    let yaml = serde_yaml::to_string(&ranges)
+        .map_err(|e| ScrapperError::Config(format!("no se pudo generar {}: {}", config.file, e)))?;
+    std::fs::write(&config.file, yaml)
+        .map_err(|e| ScrapperError::Config(format!("no se pudo escribir {}: {}", config.file, e)))?;
+
+    Ok(ranges.iter().map(|provider| provider.ranges.len()).sum())
+}
+
+fn parse_source(source: &IpRangeSource, body: &str) -> Result<Vec<String>, ScrapperError> {
+    if source.format == "plain" {
+        return Ok(body
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.to_string())
+            .collect());
+    }
+
+    let json: serde_json::Value = serde_json::from_str(body)
+        .map_err(|e| ScrapperError::Parse(format!("rangos de {}: {}", source.provider, e)))?;
+    let fields: &[(&str, &str)] = match source.format.as_str() {
+        "aws" => &[("prefixes", "ip_prefix"), ("ipv6_prefixes", "ipv6_prefix")],
+        "gcp" => &[("prefixes", "ipv4Prefix"), ("prefixes", "ipv6Prefix")],
+        "fastly" => &[("addresses", ""), ("ipv6_addresses", "")],
+        format => {
+            return Err(ScrapperError::Config(format!(
+                "formato de rangos {} desconocido en {}",
+                format, source.provider
+            )))
+        }
+    };
+
+    let service_in = |item: &serde_json::Value, services: &[String]| {
+        item["service"]
+            .as_str()
+            .is_some_and(|service| services.iter().any(|wanted| wanted.eq_ignore_ascii_case(service)))
+    };
+
+    //Los rangos de los servicios excluidos se quitan aunque aparezcan tambien en otros servicios
+    let mut seen = HashSet::new();
+    for (list, field) in fields {
+        for item in json[*list].as_array().into_iter().flatten() {
+            if service_in(item, &source.exclude_services) {
+                if let Some(range) = range_value(item, field).as_str() {
+                    seen.insert(range);
+                }
+            }
+        }
+    }
+
+    let mut ranges = vec![];
+    for (list, field) in fields {
+        for item in json[*list].as_array().into_iter().flatten() {
+            if !source.services.is_empty() && !service_in(item, &source.services) {
+                continue;
+            }
+            if let Some(range) = range_value(item, field).as_str() {
+                if seen.insert(range) {
+                    ranges.push(range.to_string());
+                }
+            }
+        }
+    }
+
+    Ok(ranges)
+}
+
+//El rango esta en el campo field del elemento, o es el propio elemento si field esta vacio
+fn range_value<'a>(item: &'a serde_json::Value, field: &str) -> &'a serde_json::Value {
+    if field.is_empty() {
+        item
+    } else {
+        &item[field]
+    }
+}
+
+fn parse_cidr(range: &str) -> Option<(IpAddr, u8)> {
+    let (ip, prefix_len) = match range.split_once('/') {
+        Some((ip, prefix_len)) => (ip.parse::<IpAddr>().ok()?, prefix_len.parse::<u8>().ok()?),
+        None => {
+            let ip = range.parse::<IpAddr>().ok()?;
+            (ip, if ip.is_ipv4() { 32 } else { 128 })
+        }
+    };
+    let max_len = if ip.is_ipv4() { 32 } else { 128 };
+
+    (prefix_len <= max_len).then_some((ip, prefix_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AWS_JSON: &str = r#"{
+        "prefixes": [
+            { "ip_prefix": "13.32.0.0/15", "service": "AMAZON" },
+            { "ip_prefix": "13.32.0.0/15", "service": "CLOUDFRONT" },
+            { "ip_prefix": "3.0.0.0/9", "service": "AMAZON" },
+            { "ip_prefix": "3.0.0.0/9", "service": "EC2" }
+        ],
+        "ipv6_prefixes": [
+            { "ipv6_prefix": "2600:9000::/28", "service": "CLOUDFRONT" }
+        ]
+    }"#;
+
+    fn aws_source(services: &[&str], exclude_services: &[&str]) -> IpRangeSource {
+        IpRangeSource {
+            provider: "AWS".to_string(),
+            kind: ProviderKind::Hosting,
+            url: String::new(),
+            format: "aws".to_string(),
+            services: services.iter().map(|service| service.to_string()).collect(),
+            exclude_services: exclude_services.iter().map(|service| service.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn aws_excluded_services_are_dropped_from_every_service() {
+        let ranges = parse_source(&aws_source(&[], &["CLOUDFRONT"]), AWS_JSON).unwrap();
+
+        assert_eq!(ranges, vec!["3.0.0.0/9"]);
+    }
+
+    #[test]
+    fn aws_services_keep_only_those_services() {
+        let ranges = parse_source(&aws_source(&["cloudfront"], &[]), AWS_JSON).unwrap();
+
+        assert_eq!(ranges, vec!["13.32.0.0/15", "2600:9000::/28"]);
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        let ip_ranges = IpRanges::from_ranges(&[
+            ProviderRanges {
+                provider: "Amazon CloudFront".to_string(),
+                kind: ProviderKind::Cdn,
+                ranges: vec!["54.192.0.0/16".to_string()],
+            },
+            ProviderRanges {
+                provider: "AWS".to_string(),
+                kind: ProviderKind::Hosting,
+                ranges: vec!["54.192.0.0/12".to_string()],
+            },
+        ]);
+
+        assert_eq!(
+            ip_ranges.lookup("54.192.1.1".parse().unwrap()),
+            Some(("Amazon CloudFront", ProviderKind::Cdn))
+        );
+        assert_eq!(ip_ranges.lookup("54.200.1.1".parse().unwrap()), Some(("AWS", ProviderKind::Hosting)));
+        assert_eq!(ip_ranges.lookup("8.8.8.8".parse().unwrap()), None);
+    }
+}
@@ -0,0 +1,377 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::error::ScrapperError;
+
+const OPEN_PAGERANK_URL: &str = "https://openpagerank.com/api/v1.0/getPageRank";
+const OPEN_PAGERANK_KEY_ENV: &str = "OPEN_PAGERANK_API_KEY";
+//La api de Open PageRank admite hasta 100 dominios por peticion
+const OPEN_PAGERANK_BATCH: usize = 100;
+
+/*
+ * Origen del ranking de dominios: openpagerank, csv (listas tipo Tranco o
+ * Majestic) o none. La clave de Open PageRank se lee de OPEN_PAGERANK_API_KEY
+ * o, si no existe, de api_key.
+ *
+ * El trabajo de refresco se lanza cada refresh_interval_hours (0 lo
+ * desactiva) y actualiza, en lotes de refresh_batch, los dominios cuyo
+ * pagerank tiene mas de refresh_after_hours.
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RankConfig {
+    pub provider: String,
+    pub api_key: Option<String>,
+    pub csv_file: Option<String>,
+    pub cache_ttl_hours: u64,
+    pub timeout_secs: u64,
+    pub refresh_after_hours: i64,
+    pub refresh_interval_hours: i64,
+    pub refresh_batch: i64,
+}
+
+impl Default for RankConfig {
+    fn default() -> Self {
+        Self {
+            provider: "openpagerank".to_string(),
+            api_key: None,
+            csv_file: None,
+            cache_ttl_hours: 168,
+            timeout_secs: 15,
+            refresh_after_hours: 720,
+            refresh_interval_hours: 24,
+            refresh_batch: 100,
+        }
+    }
+}
+
+/*
+ * Indica si toca empezar otro refresco: nunca con refresh_interval_hours a 0,
+ * siempre si el anterior no termino (o no hubo) y si no cuando han pasado
+ * refresh_interval_hours desde que termino
+ */
+pub fn refresh_due(config: &RankConfig, finished_at: Option<chrono::DateTime<chrono::Utc>>) -> bool {
+    if config.refresh_interval_hours <= 0 {
+        return false;
+    }
+
+    match finished_at {
+        Some(finished_at) => chrono::Utc::now() - finished_at >= chrono::Duration::hours(config.refresh_interval_hours),
+        None => true,
+    }
+}
+
+/*
+ * Proveedor de ranking. Devuelve un valor de 0 a 10 para cada dominio que
+ * conoce; los dominios sin ranking se devuelven con 0 y los que no se han
+ * podido consultar no aparecen.
+ */
+#[async_trait]
+pub trait RankProvider: Send + Sync {
+    async fn ranks(&self, domains: &[String]) -> Result<HashMap<String, f64>, ScrapperError>;
+
+    fn max_batch(&self) -> usize;
+}
+
+pub struct NoRankProvider;
+
+#[async_trait]
+impl RankProvider for NoRankProvider {
+    async fn ranks(&self, _domains: &[String]) -> Result<HashMap<String, f64>, ScrapperError> {
+        Ok(HashMap::new())
+    }
+
+    fn max_batch(&self) -> usize {
+        usize::MAX
+    }
+}
+
+pub struct OpenPageRankProvider {
+    client: reqwest::Client,
+    api_key: String,
+}
+
+impl OpenPageRankProvider {
+    pub fn new(client: reqwest::Client, api_key: String) -> Self {
+        Self { client, api_key }
+    }
+}
+
+#[async_trait]
+impl RankProvider for OpenPageRankProvider {
+    async fn ranks(&self, domains: &[String]) -> Result<HashMap<String, f64>, ScrapperError> {
+        let query: Vec<(&str, &str)> = domains.iter().map(|domain| ("domains[]", domain.as_str())).collect();
+        let response = self
+            .client
+            .get(OPEN_PAGERANK_URL)
+            .query(&query)
+            .header("API-OPR", self.api_key.as_str())
+            //El cliente compartido no descomprime, se pide el cuerpo sin comprimir
+            .header(reqwest::header::ACCEPT_ENCODING, "identity")
+            .send()
+            .await?;
+
+        let response_json: serde_json::Value = serde_json::from_str(&response.text().await?)
+            .map_err(|e| ScrapperError::Parse(format!("respuesta de pagerank: {}", e)))?;
+        if response_json["status_code"] != 200 {
+            return Err(ScrapperError::Parse(format!(
+                "respuesta de pagerank: {:?}",
+                response_json["error"]
+            )));
+        }
+
+        let mut ranks = HashMap::new();
+        for result in response_json["response"].as_array().into_iter().flatten() {
+            let Some(domain) = result["domain"].as_str() else {
+                continue;
+            };
+            let rank = if result["status_code"] == 200 {
+                result["page_rank_decimal"].as_f64().unwrap_or(0.0)
+            } else {
+                0.0
+            };
+            ranks.insert(domain.to_lowercase(), rank);
+        }
+
+        Ok(ranks)
+    }
+
+    fn max_batch(&self) -> usize {
+        OPEN_PAGERANK_BATCH
+    }
+}
+
+/*
+ * Lista local de dominios ordenada por posicion. Acepta "posicion,dominio"
+ * (Tranco) o un csv con cabecera que tenga las columnas GlobalRank y Domain
+ * (Majestic). La posicion se pasa a 0-10 en escala logaritmica.
+ */
+pub struct CsvRankProvider {
+    ranks: HashMap<String, f64>,
+}
+
+impl CsvRankProvider {
+    pub fn load(path: &str) -> Result<Self, ScrapperError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| ScrapperError::Config(format!("no se pudo abrir {}: {}", path, e)))?;
+
+        let mut lines = content.lines().peekable();
+        let (rank_column, domain_column) = match lines.peek() {
+            Some(header) if header.to_lowercase().contains("domain") => {
+                let columns: Vec<String> = header.split(',').map(|column| column.trim().to_lowercase()).collect();
+                let rank_column = columns.iter().position(|column| column == "globalrank" || column == "rank");
+                let domain_column = columns.iter().position(|column| column == "domain");
+                lines.next();
+
+                match (rank_column, domain_column) {
+                    (Some(rank_column), Some(domain_column)) => (rank_column, domain_column),
+                    _ => return Err(ScrapperError::Config(format!("{}: faltan las columnas rank y domain", path))),
+                }
+            }
+            _ => (0, 1),
+        };
+
+        let mut positions = vec![];
+        for line in lines {
+            let columns: Vec<&str> = line.split(',').collect();
+            let position = columns.get(rank_column).and_then(|position| position.trim().parse::<u64>().ok());
+            if let (Some(position), Some(domain)) = (position, columns.get(domain_column)) {
+                positions.push((domain.trim().to_lowercase(), position.max(1)));
+            }
+        }
+
+        let max_position = positions.iter().map(|(_, position)| *position).max().unwrap_or(1).max(2) as f64;
+        let ranks = positions
+            .into_iter()
+            .map(|(domain, position)| {
+                let rank = 10.0 * (1.0 - (position as f64).ln() / max_position.ln());
+                (domain, (rank * 100.0).round() / 100.0)
+            })
+            .collect();
+
+        Ok(Self { ranks })
+    }
+}
+
+#[async_trait]
+impl RankProvider for CsvRankProvider {
+    async fn ranks(&self, domains: &[String]) -> Result<HashMap<String, f64>, ScrapperError> {
+        Ok(domains
+            .iter()
+            .map(|domain| (domain.clone(), self.ranks.get(domain).copied().unwrap_or(0.0)))
+            .collect())
+    }
+
+    fn max_batch(&self) -> usize {
+        usize::MAX
+    }
+}
+
+pub fn build_provider(config: &RankConfig, client: reqwest::Client) -> Result<Box<dyn RankProvider>, ScrapperError> {
+    match config.provider.as_str() {
+        "openpagerank" => {
+            let api_key = std::env::var(OPEN_PAGERANK_KEY_ENV).ok().or_else(|| config.api_key.clone());
+            match api_key {
+                Some(api_key) if !api_key.is_empty() => Ok(Box::new(OpenPageRankProvider::new(client, api_key))),
+                _ => {
+                    warn!("No hay clave de Open PageRank ({}), no se obtendra el pagerank", OPEN_PAGERANK_KEY_ENV);
+
+                    Ok(Box::new(NoRankProvider))
+                }
+            }
+        }
+        "csv" => {
+            let path = config
+                .csv_file
+                .as_deref()
+                .ok_or_else(|| ScrapperError::Config("rank.csv_file es obligatorio con el proveedor csv".to_string()))?;
+
+            Ok(Box::new(CsvRankProvider::load(path)?))
+        }
+        "none" => Ok(Box::new(NoRankProvider)),
+        provider => Err(ScrapperError::Config(format!("proveedor de ranking {} desconocido", provider))),
+    }
+}
+
+/*
+ * Cache con caducidad delante del proveedor. Los errores y timeouts del
+ * proveedor solo se registran: el dominio se queda sin ranking y se vuelve
+ * a pedir la proxima vez.
+ */
+pub struct RankCache {
+    provider: Box<dyn RankProvider>,
+    ttl: Duration,
+    timeout: Duration,
+    entries: Mutex<HashMap<String, (f64, Instant)>>,
+}
+
+impl RankCache {
+    pub fn new(provider: Box<dyn RankProvider>, config: &RankConfig) -> Self {
+        Self {
+            provider,
+            ttl: Duration::from_secs(config.cache_ttl_hours * 3600),
+            timeout: Duration::from_secs(config.timeout_secs),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn rank(&self, domain: &str) -> Option<f64> {
+        self.ranks(&[domain.to_string()]).await.remove(domain)
+    }
+
+    pub async fn ranks(&self, domains: &[String]) -> HashMap<String, f64> {
+        let mut ranks = HashMap::new();
+        let mut missing = vec![];
+        {
+            let entries = self.entries.lock().unwrap();
+            for domain in domains {
+                match entries.get(domain) {
+                    Some((rank, fetched_at)) if fetched_at.elapsed() < self.ttl => {
+                        ranks.insert(domain.clone(), *rank);
+                    }
+                    _ => missing.push(domain.clone()),
+                }
+            }
+        }
+
+        for batch in missing.chunks(self.provider.max_batch()) {
+            match tokio::time::timeout(self.timeout, self.provider.ranks(batch)).await {
+                Ok(Ok(batch_ranks)) => {
+                    let mut entries = self.entries.lock().unwrap();
+                    for (domain, rank) in batch_ranks {
+                        entries.insert(domain.clone(), (rank, Instant::now()));
+                        ranks.insert(domain, rank);
+                    }
+                }
+                Ok(Err(e)) => warn!("     Error al obtener el pagerank de {} dominios: {}", batch.len(), e),
+                Err(_) => warn!("     Timeout al obtener el pagerank de {} dominios", batch.len()),
+            }
+        }
+
+        ranks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    //Proveedor que devuelve 5 para todo y cuenta las llamadas
+    struct CountingProvider {
+        calls: Arc<AtomicUsize>,
+        fail: bool,
+    }
+
+    #[async_trait]
+    impl RankProvider for CountingProvider {
+        async fn ranks(&self, domains: &[String]) -> Result<HashMap<String, f64>, ScrapperError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.fail {
+                return Err(ScrapperError::Parse("fallo".to_string()));
+            }
+
+            Ok(domains.iter().map(|domain| (domain.clone(), 5.0)).collect())
+        }
+
+        fn max_batch(&self) -> usize {
+            2
+        }
+    }
+
+    fn cache(fail: bool) -> (RankCache, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let provider = CountingProvider {
+            calls: calls.clone(),
+            fail,
+        };
+
+        (RankCache::new(Box::new(provider), &RankConfig::default()), calls)
+    }
+
+    fn domains(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn ranks_are_requested_in_batches_and_cached() {
+        let (cache, calls) = cache(false);
+
+        let ranks = cache.ranks(&domains(&["a.com", "b.com", "c.com"])).await;
+        assert_eq!(ranks.len(), 3);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let ranks = cache.ranks(&domains(&["a.com", "d.com"])).await;
+        assert_eq!(ranks.get("d.com"), Some(&5.0));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn provider_errors_leave_domains_without_rank() {
+        let (cache, calls) = cache(true);
+
+        assert!(cache.ranks(&domains(&["a.com"])).await.is_empty());
+        assert!(cache.rank("a.com").await.is_none());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn refresh_is_due_after_the_interval() {
+        let config = RankConfig::default();
+        let now = chrono::Utc::now();
+
+        assert!(refresh_due(&config, None));
+        assert!(!refresh_due(&config, Some(now - chrono::Duration::hours(1))));
+        assert!(refresh_due(&config, Some(now - chrono::Duration::hours(25))));
+
+        let disabled = RankConfig {
+            refresh_interval_hours: 0,
+            ..RankConfig::default()
+        };
+        assert!(!refresh_due(&disabled, None));
+    }
+}
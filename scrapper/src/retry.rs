@@ -0,0 +1,128 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::error::{FetchError, ScrapperError};
+
+/*
+ * Politica de reintentos. Los errores transitorios se reintentan con
+ * backoff exponencial hasta max_failures; los permanentes (4xx, tls...)
+ * hasta max_permanent_failures. Despues la pagina deja de ser scrappeable.
+ * Los errores que no son de la pagina (base de datos, configuracion) no
+ * cuentan como fallo, solo la aplazan postpone_minutes.
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    pub max_failures: u32,
+    pub max_permanent_failures: u32,
+    pub base_delay_minutes: i64,
+    pub max_delay_hours: i64,
+    pub postpone_minutes: i64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            max_permanent_failures: 1,
+            base_delay_minutes: 30,
+            max_delay_hours: 48,
+            postpone_minutes: 15,
+        }
+    }
+}
+
+impl RetryConfig {
+    /*
+     * Fecha del siguiente intento, o None si hay que dejar de intentarlo
+     */
+    pub fn next_attempt_at(&self, error: &ScrapperError, failed_attempts: u32) -> Option<DateTime<Utc>> {
+        self.retry_delay(error, failed_attempts).map(|delay| chrono::Utc::now() + delay)
+    }
+
+    fn retry_delay(&self, error: &ScrapperError, failed_attempts: u32) -> Option<chrono::Duration> {
+        let max_failures = if error.is_transient() {
+            self.max_failures
+        } else {
+            self.max_permanent_failures
+        };
+        if failed_attempts >= max_failures {
+            return None;
+        }
+
+        let max_delay = chrono::Duration::hours(self.max_delay_hours);
+        let exponent = failed_attempts.saturating_sub(1).min(20);
+        let backoff = chrono::Duration::minutes(self.base_delay_minutes * 2_i64.pow(exponent)).min(max_delay);
+
+        let delay = match error {
+            ScrapperError::Fetch(FetchError::RateLimited { retry_after: Some(seconds) }) => {
+                chrono::Duration::seconds(*seconds as i64).min(max_delay)
+            }
+            _ => backoff,
+        };
+
+        Some(delay)
+    }
+
+    pub fn postpone_at(&self) -> DateTime<Utc> {
+        chrono::Utc::now() + chrono::Duration::minutes(self.postpone_minutes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minutes(minutes: i64) -> Option<chrono::Duration> {
+        Some(chrono::Duration::minutes(minutes))
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let config = RetryConfig {
+            max_failures: 20,
+            ..RetryConfig::default()
+        };
+        let error = ScrapperError::Fetch(FetchError::Timeout);
+
+        assert_eq!(config.retry_delay(&error, 1), minutes(30));
+        assert_eq!(config.retry_delay(&error, 2), minutes(60));
+        assert_eq!(config.retry_delay(&error, 3), minutes(120));
+        assert_eq!(config.retry_delay(&error, 7), minutes(30 * 64));
+        assert_eq!(config.retry_delay(&error, 8), minutes(48 * 60));
+        assert_eq!(config.retry_delay(&error, 19), minutes(48 * 60));
+    }
+
+    #[test]
+    fn gives_up_after_max_failures() {
+        let config = RetryConfig::default();
+        let transient = ScrapperError::Fetch(FetchError::ServerError(503));
+        let permanent = ScrapperError::Fetch(FetchError::ClientError(404));
+
+        assert!(config.retry_delay(&transient, 4).is_some());
+        assert_eq!(config.retry_delay(&transient, 5), None);
+        assert_eq!(config.next_attempt_at(&transient, 5), None);
+        assert_eq!(config.retry_delay(&permanent, 1), None);
+    }
+
+    #[test]
+    fn rate_limit_waits_retry_after() {
+        let config = RetryConfig::default();
+        let limited = |retry_after| ScrapperError::Fetch(FetchError::RateLimited { retry_after });
+
+        assert_eq!(config.retry_delay(&limited(Some(90)), 1), Some(chrono::Duration::seconds(90)));
+        assert_eq!(config.retry_delay(&limited(Some(30 * 86400)), 1), minutes(48 * 60));
+        assert_eq!(config.retry_delay(&limited(None), 2), minutes(60));
+    }
+
+    #[test]
+    fn next_attempt_is_in_the_future() {
+        let config = RetryConfig::default();
+        let before = chrono::Utc::now();
+
+        let next_attempt_at = config.next_attempt_at(&ScrapperError::Fetch(FetchError::Timeout), 1).unwrap();
+
+        assert!(next_attempt_at >= before + chrono::Duration::minutes(30));
+        assert!(next_attempt_at <= chrono::Utc::now() + chrono::Duration::minutes(30));
+    }
+}
@@ -0,0 +1,178 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::db::DatabaseWebTechnology;
+
+/*
+ * Intervalo de re-scrapeo en horas. domains y technologies sustituyen a
+ * default_hours como intervalo base; si coinciden varias tecnologias se usa
+ * la menor. A partir de la base el intervalo se adapta: si las detecciones
+ * cambian se divide por backoff_factor y si no cambian se multiplica,
+ * siempre entre min_hours y max_hours. El intervalo de domains es a la vez
+ * un limite propio: puede quedar por debajo de min_hours o por encima de
+ * max_hours.
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RescrapConfig {
+    pub default_hours: i64,
+    pub min_hours: i64,
+    pub max_hours: i64,
+    pub backoff_factor: f64,
+    pub domains: HashMap<String, i64>,
+    pub technologies: HashMap<String, i64>,
+}
+
+impl Default for RescrapConfig {
+    fn default() -> Self {
+        Self {
+            default_hours: 10 * 24,
+            min_hours: 24,
+            max_hours: 90 * 24,
+            backoff_factor: 2.0,
+            domains: HashMap::new(),
+            technologies: HashMap::new(),
+        }
+    }
+}
+
+impl RescrapConfig {
+    pub fn base_interval_hours(&self, host: &str, technologies: &[DatabaseWebTechnology]) -> i64 {
+        if let Some(hours) = self.domains.get(host) {
+            return *hours;
+        }
+
+        technologies
+            .iter()
+            .filter_map(|technology| self.technologies.get(&technology.name))
+            .min()
+            .copied()
+            .unwrap_or(self.default_hours)
+    }
+
+    pub fn next_interval_hours(
+        &self,
+        host: &str,
+        technologies: &[DatabaseWebTechnology],
+        previous_hours: Option<i64>,
+        changed: bool,
+    ) -> i64 {
+        let base = self.base_interval_hours(host, technologies);
+
+        let hours = match previous_hours {
+            None => base,
+            Some(previous) if changed => {
+                (previous.min(base) as f64 / self.backoff_factor.max(1.0)) as i64
+            }
+            Some(previous) => (previous as f64 * self.backoff_factor.max(1.0)) as i64,
+        };
+
+        let (min_hours, max_hours) = self.bounds(host);
+        hours.clamp(min_hours, max_hours)
+    }
+
+    /*
+     * Limites del intervalo del host, ampliados con el de domains si lo tiene
+     */
+    fn bounds(&self, host: &str) -> (i64, i64) {
+        let min_hours = self.min_hours.max(1);
+        let max_hours = self.max_hours.max(min_hours);
+
+        match self.domains.get(host) {
+            Some(hours) => {
+                let hours = (*hours).max(1);
+                (min_hours.min(hours), max_hours.max(hours))
+            }
+            None => (min_hours, max_hours),
+        }
+    }
+}
+
+/*
+ * Compara las detecciones de la visita anterior con las actuales
+ */
+pub fn detections_changed(
+    previous: &Option<Vec<DatabaseWebTechnology>>,
+    current: &[DatabaseWebTechnology],
+) -> bool {
+    let previous = match previous {
+        Some(previous) => previous,
+        None => return false,
+    };
+
+    let mut previous_names: Vec<&String> = previous.iter().map(|technology| &technology.name).collect();
+    let mut current_names: Vec<&String> = current.iter().map(|technology| &technology.name).collect();
+    previous_names.sort();
+    previous_names.dedup();
+    current_names.sort();
+    current_names.dedup();
+
+    previous_names != current_names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn technology(name: &str) -> DatabaseWebTechnology {
+        DatabaseWebTechnology {
+            ttype: "TecnologyBackend".to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    fn config() -> RescrapConfig {
+        RescrapConfig {
+            domains: HashMap::from([("news.example.com".to_string(), 6), ("archive.example.com".to_string(), 4000)]),
+            technologies: HashMap::from([("WordPress".to_string(), 72), ("Shopify".to_string(), 48)]),
+            ..RescrapConfig::default()
+        }
+    }
+
+    #[test]
+    fn base_interval_prefers_domain_then_lowest_technology() {
+        let config = config();
+        let technologies = [technology("WordPress"), technology("Shopify"), technology("jQuery")];
+
+        assert_eq!(config.base_interval_hours("news.example.com", &technologies), 6);
+        assert_eq!(config.base_interval_hours("example.com", &technologies), 48);
+        assert_eq!(config.base_interval_hours("example.com", &[technology("jQuery")]), 240);
+    }
+
+    #[test]
+    fn next_interval_adapts_to_changes() {
+        let config = config();
+
+        assert_eq!(config.next_interval_hours("example.com", &[], None, false), 240);
+        assert_eq!(config.next_interval_hours("example.com", &[], Some(240), false), 480);
+        assert_eq!(config.next_interval_hours("example.com", &[], Some(480), true), 120);
+    }
+
+    #[test]
+    fn next_interval_is_clamped() {
+        let config = config();
+
+        assert_eq!(config.next_interval_hours("example.com", &[], Some(20), true), 24);
+        assert_eq!(config.next_interval_hours("example.com", &[], Some(2000), false), 90 * 24);
+    }
+
+    #[test]
+    fn domain_interval_is_its_own_bound() {
+        let config = config();
+
+        assert_eq!(config.next_interval_hours("news.example.com", &[], None, false), 6);
+        assert_eq!(config.next_interval_hours("news.example.com", &[], Some(6), false), 12);
+        assert_eq!(config.next_interval_hours("news.example.com", &[], Some(12), true), 6);
+        assert_eq!(config.next_interval_hours("archive.example.com", &[], None, false), 4000);
+        assert_eq!(config.next_interval_hours("archive.example.com", &[], Some(4000), false), 4000);
+    }
+
+    #[test]
+    fn detections_changed_ignores_order_and_repeats() {
+        let previous = Some(vec![technology("WordPress"), technology("jQuery")]);
+
+        assert!(!detections_changed(&previous, &[technology("jQuery"), technology("WordPress"), technology("jQuery")]));
+        assert!(detections_changed(&previous, &[technology("WordPress")]));
+        assert!(!detections_changed(&None, &[technology("WordPress")]));
+    }
+}
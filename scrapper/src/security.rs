@@ -0,0 +1,246 @@
+use reqwest::header::HeaderMap;
+
+use crate::db::{DatabaseSecurityAudit, DatabaseSecurityCheck};
+
+//HSTS con menos de 180 dias no cuenta como activado
+const HSTS_MIN_MAX_AGE: u64 = 15_552_000;
+
+/*
+ * Revisa las cabeceras de seguridad de la respuesta. Cada comprobacion
+ * tiene un peso y la nota es la suma de los pesos de las que pasan (0 a 100).
+ * Las comprobaciones de cookies pasan si la respuesta no pone cookies.
+ */
+pub fn audit_headers(headers: &HeaderMap, https: bool) -> DatabaseSecurityAudit {
+    let mut checks = vec![];
+
+    let hsts = header(headers, "strict-transport-security");
+    let hsts_max_age = hsts
+        .as_deref()
+        .and_then(|hsts| directive(hsts, "max-age"))
+        .and_then(|max_age| max_age.parse::<u64>().ok());
+    checks.push(check(
+        "hsts",
+        20,
+        https && hsts_max_age.unwrap_or(0) >= HSTS_MIN_MAX_AGE,
+        hsts.clone(),
+        if !https { Some("la pagina no se sirve por https") } else { None },
+    ));
+
+    let csp = header(headers, "content-security-policy");
+    let csp_unsafe = csp.as_deref().is_some_and(|csp| csp.contains("'unsafe-inline'") || csp.contains("'unsafe-eval'"));
+    checks.push(check(
+        "content_security_policy",
+        20,
+        csp.is_some() && !csp_unsafe,
+        csp.clone(),
+        if csp_unsafe { Some("permite unsafe-inline o unsafe-eval") } else { None },
+    ));
+
+    //frame-ancestors en la CSP sustituye a X-Frame-Options
+    let frame_options = header(headers, "x-frame-options");
+    let frame_ancestors = csp.as_deref().is_some_and(|csp| csp.contains("frame-ancestors"));
+    checks.push(check(
+        "x_frame_options",
+        10,
+        frame_ancestors
+            || frame_options
+                .as_deref()
+                .is_some_and(|value| value.eq_ignore_ascii_case("deny") || value.eq_ignore_ascii_case("sameorigin")),
+        frame_options,
+        if frame_ancestors { Some("frame-ancestors en la CSP") } else { None },
+    ));
+
+    let content_type_options = header(headers, "x-content-type-options");
+    checks.push(check(
+        "x_content_type_options",
+        10,
+        content_type_options.as_deref().is_some_and(|value| value.eq_ignore_ascii_case("nosniff")),
+        content_type_options,
+        None,
+    ));
+
+    let referrer_policy = header(headers, "referrer-policy");
+    checks.push(check(
+        "referrer_policy",
+        10,
+        referrer_policy.as_deref().is_some_and(|value| {
+            let value = value.to_ascii_lowercase();
+            !value.contains("unsafe-url") && !value.contains("no-referrer-when-downgrade")
+        }),
+        referrer_policy,
+        None,
+    ));
+
+    let permissions_policy = header(headers, "permissions-policy");
+    checks.push(check("permissions_policy", 10, permissions_policy.is_some(), permissions_policy, None));
+
+    let cookies: Vec<String> = headers
+        .get_all(reqwest::header::SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .map(|value| value.to_ascii_lowercase())
+        .collect();
+    for (name, attribute, weight) in [
+        ("cookie_secure", "secure", 7),
+        ("cookie_httponly", "httponly", 7),
+        ("cookie_samesite", "samesite", 6),
+    ] {
+        let missing = cookies
+            .iter()
+            .filter(|cookie| !cookie.split(';').skip(1).any(|part| part.trim().starts_with(attribute)))
+            .count();
+        checks.push(DatabaseSecurityCheck {
+            name: name.to_string(),
+            passed: missing == 0,
+            weight,
+            value: Some(format!("{}/{}", cookies.len() - missing, cookies.len())),
+            detail: None,
+        });
+    }
+
+    let score: u32 = checks.iter().filter(|check| check.passed).map(|check| check.weight).sum();
+
+    DatabaseSecurityAudit {
+        checks,
+        score,
+        grade: grade(score).to_string(),
+        audited_at: chrono::Utc::now(),
+    }
+}
+
+fn grade(score: u32) -> &'static str {
+    match score {
+        90.. => "A",
+        75..=89 => "B",
+        60..=74 => "C",
+        40..=59 => "D",
+        _ => "F",
+    }
+}
+
+fn check(name: &str, weight: u32, passed: bool, value: Option<String>, detail: Option<&str>) -> DatabaseSecurityCheck {
+    DatabaseSecurityCheck {
+        name: name.to_string(),
+        passed,
+        weight,
+        value,
+        detail: detail.map(|detail| detail.to_string()),
+    }
+}
+
+fn header(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_string())
+}
+
+fn directive<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    header.split(';').find_map(|part| {
+        let (key, value) = part.split_once('=')?;
+        if key.trim().eq_ignore_ascii_case(name) {
+            Some(value.trim().trim_matches('"'))
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(values: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in values {
+            headers.append(*name, reqwest::header::HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    fn passed(audit: &DatabaseSecurityAudit, name: &str) -> bool {
+        audit.checks.iter().find(|check| check.name == name).unwrap().passed
+    }
+
+    #[test]
+    fn all_headers_get_an_a() {
+        let audit = audit_headers(
+            &headers(&[
+                ("strict-transport-security", "max-age=31536000; includeSubDomains"),
+                ("content-security-policy", "default-src 'self'; frame-ancestors 'none'"),
+                ("x-content-type-options", "nosniff"),
+                ("referrer-policy", "strict-origin-when-cross-origin"),
+                ("permissions-policy", "geolocation=()"),
+                ("set-cookie", "session=1; Secure; HttpOnly; SameSite=Lax"),
+            ]),
+            true,
+        );
+
+        assert_eq!(audit.score, 100);
+        assert_eq!(audit.grade, "A");
+        //frame-ancestors sustituye a X-Frame-Options
+        assert!(passed(&audit, "x_frame_options"));
+    }
+
+    #[test]
+    fn no_headers_get_an_f() {
+        let audit = audit_headers(&HeaderMap::new(), true);
+
+        //Sin cookies las comprobaciones de cookies pasan
+        assert_eq!(audit.score, 20);
+        assert_eq!(audit.grade, "F");
+    }
+
+    #[test]
+    fn hsts_needs_https_and_a_long_max_age() {
+        let long = headers(&[("strict-transport-security", "max-age=\"31536000\"")]);
+        let short = headers(&[("strict-transport-security", "max-age=86400")]);
+
+        assert!(passed(&audit_headers(&long, true), "hsts"));
+        assert!(!passed(&audit_headers(&long, false), "hsts"));
+        assert!(!passed(&audit_headers(&short, true), "hsts"));
+    }
+
+    #[test]
+    fn unsafe_csp_and_referrer_policies_fail() {
+        let audit = audit_headers(
+            &headers(&[
+                ("content-security-policy", "script-src 'self' 'unsafe-inline'"),
+                ("referrer-policy", "no-referrer-when-downgrade"),
+                ("x-frame-options", "ALLOW-FROM https://example.com"),
+            ]),
+            true,
+        );
+
+        assert!(!passed(&audit, "content_security_policy"));
+        assert!(!passed(&audit, "referrer_policy"));
+        assert!(!passed(&audit, "x_frame_options"));
+    }
+
+    #[test]
+    fn every_cookie_needs_the_attributes() {
+        let audit = audit_headers(
+            &headers(&[
+                ("set-cookie", "a=1; Secure; HttpOnly; SameSite=Strict"),
+                ("set-cookie", "b=2; HttpOnly"),
+            ]),
+            true,
+        );
+        let secure = audit.checks.iter().find(|check| check.name == "cookie_secure").unwrap();
+
+        assert!(!secure.passed);
+        assert_eq!(secure.value.as_deref(), Some("1/2"));
+        assert!(passed(&audit, "cookie_httponly"));
+        assert!(!passed(&audit, "cookie_samesite"));
+    }
+
+    #[test]
+    fn grade_thresholds() {
+        assert_eq!(grade(90), "A");
+        assert_eq!(grade(89), "B");
+        assert_eq!(grade(75), "B");
+        assert_eq!(grade(60), "C");
+        assert_eq!(grade(40), "D");
+        assert_eq!(grade(39), "F");
+    }
+}
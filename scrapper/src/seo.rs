@@ -0,0 +1,237 @@
+use scraper::{ElementRef, Html};
+
+use crate::db::{DatabaseHeadingCounts, DatabaseOpenGraph, DatabaseSeoMetadata, DatabaseTwitterCard};
+use crate::error::ScrapperError;
+use crate::parse_selector;
+
+/*
+ * Metadatos SEO de la pagina: title y description (con su longitud en
+ * caracteres), canonical, directivas robots, Open Graph, Twitter card,
+ * numero de encabezados y favicon. Las urls
+ * relativas se resuelven respecto a la url de la pagina; si no se declara
+ * favicon se usa /favicon.ico, que es lo que piden los navegadores.
+ */
+pub fn extract(document: &Html, page_url: &str) -> Result<DatabaseSeoMetadata, ScrapperError> {
+    let base = reqwest::Url::parse(page_url).ok();
+    let resolve = |href: Option<String>| -> Option<String> {
+        let href = href?;
+        match &base {
+            Some(base) => base.join(&href).ok().map(|url| url.to_string()),
+            None => Some(href),
+        }
+    };
+
+    let title = select_first(document, "title")?
+        .map(|title| collapse(&title.text().collect::<String>()))
+        .filter(|title| !title.is_empty());
+    let description = meta_content(document, "name", "description")?;
+    let canonical = resolve(select_first(document, "link[rel~=canonical][href]")?.and_then(|link| attr(link, "href")));
+
+    let mut robots = vec![];
+    for name in ["robots", "googlebot"] {
+        if let Some(content) = meta_content(document, "name", name)? {
+            for directive in content.split(',') {
+                let directive = directive.trim().to_lowercase();
+                if !directive.is_empty() && !robots.contains(&directive) {
+                    robots.push(directive);
+                }
+            }
+        }
+    }
+    let noindex = robots.iter().any(|directive| directive == "noindex" || directive == "none");
+    let nofollow = robots.iter().any(|directive| directive == "nofollow" || directive == "none");
+
+    let open_graph = DatabaseOpenGraph {
+        title: meta_content(document, "property", "og:title")?,
+        description: meta_content(document, "property", "og:description")?,
+        image: resolve(meta_content(document, "property", "og:image")?),
+        url: resolve(meta_content(document, "property", "og:url")?),
+        og_type: meta_content(document, "property", "og:type")?,
+        site_name: meta_content(document, "property", "og:site_name")?,
+    };
+    //Muchas paginas ponen las twitter card en property en vez de en name
+    let twitter = |field: &str| -> Result<Option<String>, ScrapperError> {
+        let name = format!("twitter:{}", field);
+        Ok(meta_content(document, "name", &name)?.or(meta_content(document, "property", &name)?))
+    };
+    let twitter_card = DatabaseTwitterCard {
+        card: twitter("card")?,
+        title: twitter("title")?,
+        description: twitter("description")?,
+        image: resolve(twitter("image")?),
+        site: twitter("site")?,
+        creator: twitter("creator")?,
+    };
+
+    let count = |selector: &str| -> Result<u32, ScrapperError> { Ok(document.select(&parse_selector(selector)?).count() as u32) };
+    let headings = DatabaseHeadingCounts {
+        h1: count("h1")?,
+        h2: count("h2")?,
+        h3: count("h3")?,
+        h4: count("h4")?,
+        h5: count("h5")?,
+        h6: count("h6")?,
+    };
+
+    let favicon = select_first(document, "link[rel~=icon][href]")?
+        .or(select_first(document, "link[rel~=apple-touch-icon][href]")?)
+        .and_then(|link| attr(link, "href"))
+        .or_else(|| Some("/favicon.ico".to_string()));
+
+    Ok(DatabaseSeoMetadata {
+        title_length: title.as_ref().map(|title| title.chars().count() as u32),
+        title,
+        description_length: description.as_ref().map(|description| description.chars().count() as u32),
+        description,
+        canonical,
+        robots,
+        noindex,
+        nofollow,
+        open_graph,
+        twitter_card,
+        headings,
+        favicon: resolve(favicon),
+    })
+}
+
+fn select_first<'a>(document: &'a Html, selector: &str) -> Result<Option<ElementRef<'a>>, ScrapperError> {
+    Ok(document.select(&parse_selector(selector)?).next())
+}
+
+fn attr(element: ElementRef, name: &str) -> Option<String> {
+    element
+        .value()
+        .attr(name)
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/*
+ * content del primer meta con ese name o property (sin distinguir mayusculas)
+ */
+fn meta_content(document: &Html, attribute: &str, name: &str) -> Result<Option<String>, ScrapperError> {
+    let selector = format!("meta[{}][content]", attribute);
+    for meta in document.select(&parse_selector(&selector)?) {
+        let matches = meta
+            .value()
+            .attr(attribute)
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case(name));
+        if matches {
+            return Ok(attr(meta, "content").map(|content| collapse(&content)));
+        }
+    }
+
+    Ok(None)
+}
+
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract_html(html: &str) -> DatabaseSeoMetadata {
+        extract(&Html::parse_document(html), "https://acme.com/blog/post").unwrap()
+    }
+
+    #[test]
+    fn title_and_description_with_their_length() {
+        let seo = extract_html(
+            r#"<html><head>
+                <title>
+                    Cafés   de especialidad
+                </title>
+                <meta name="Description" content="Tostamos café  cada semana.">
+            </head></html>"#,
+        );
+
+        assert_eq!(seo.title.as_deref(), Some("Cafés de especialidad"));
+        assert_eq!(seo.title_length, Some(21));
+        assert_eq!(seo.description.as_deref(), Some("Tostamos café cada semana."));
+        assert_eq!(seo.description_length, Some(26));
+    }
+
+    #[test]
+    fn missing_and_empty_tags() {
+        let seo = extract_html(r#"<html><head><title>  </title><meta name="description" content=""></head></html>"#);
+
+        assert_eq!(seo.title, None);
+        assert_eq!(seo.title_length, None);
+        assert_eq!(seo.description, None);
+        assert_eq!(seo.canonical, None);
+        assert!(seo.robots.is_empty() && !seo.noindex && !seo.nofollow);
+        assert_eq!(seo.open_graph.title, None);
+        assert_eq!(seo.twitter_card.card, None);
+        assert_eq!(seo.favicon.as_deref(), Some("https://acme.com/favicon.ico"));
+    }
+
+    #[test]
+    fn duplicate_tags_keep_the_first_one() {
+        let seo = extract_html(
+            r#"<html><head>
+                <title>Primero</title><title>Segundo</title>
+                <meta name="description" content="Uno"><meta name="description" content="Dos">
+                <link rel="canonical" href="/blog/post"><link rel="canonical" href="/otro">
+                <meta property="og:title" content="OG uno"><meta property="og:title" content="OG dos">
+            </head></html>"#,
+        );
+
+        assert_eq!(seo.title.as_deref(), Some("Primero"));
+        assert_eq!(seo.description.as_deref(), Some("Uno"));
+        assert_eq!(seo.canonical.as_deref(), Some("https://acme.com/blog/post"));
+        assert_eq!(seo.open_graph.title.as_deref(), Some("OG uno"));
+    }
+
+    #[test]
+    fn robots_directives_from_robots_and_googlebot() {
+        let seo = extract_html(
+            r#"<meta name="robots" content="NoIndex, follow"><meta name="googlebot" content="noindex, noarchive">"#,
+        );
+        assert_eq!(seo.robots, vec!["noindex", "follow", "noarchive"]);
+        assert!(seo.noindex && !seo.nofollow);
+
+        let seo = extract_html(r#"<meta name="robots" content="none">"#);
+        assert!(seo.noindex && seo.nofollow);
+    }
+
+    #[test]
+    fn heading_outline_counts() {
+        let seo = extract_html("<h1>a</h1><h2>b</h2><h2>c</h2><section><h3>d</h3><h3>e</h3><h3>f</h3></section><h6>g</h6>");
+
+        let headings = seo.headings;
+        assert_eq!(
+            [headings.h1, headings.h2, headings.h3, headings.h4, headings.h5, headings.h6],
+            [1, 2, 3, 0, 0, 1]
+        );
+    }
+
+    #[test]
+    fn open_graph_and_twitter_cards() {
+        let seo = extract_html(
+            r#"<head>
+                <meta property="og:title" content="Acme">
+                <meta property="og:type" content="article">
+                <meta property="og:image" content="/img/portada.jpg">
+                <meta property="og:url" content="https://acme.com/blog/post">
+                <meta property="og:site_name" content="Acme Blog">
+                <meta name="twitter:card" content="summary_large_image">
+                <meta property="twitter:site" content="@acme">
+                <meta name="twitter:image" content="img/card.png">
+                <link rel="shortcut icon" href="/static/icon.png">
+            </head>"#,
+        );
+
+        assert_eq!(seo.open_graph.title.as_deref(), Some("Acme"));
+        assert_eq!(seo.open_graph.og_type.as_deref(), Some("article"));
+        assert_eq!(seo.open_graph.image.as_deref(), Some("https://acme.com/img/portada.jpg"));
+        assert_eq!(seo.open_graph.site_name.as_deref(), Some("Acme Blog"));
+        assert_eq!(seo.open_graph.description, None);
+        assert_eq!(seo.twitter_card.card.as_deref(), Some("summary_large_image"));
+        //twitter:site en property en vez de en name
+        assert_eq!(seo.twitter_card.site.as_deref(), Some("@acme"));
+        assert_eq!(seo.twitter_card.image.as_deref(), Some("https://acme.com/blog/img/card.png"));
+        assert_eq!(seo.favicon.as_deref(), Some("https://acme.com/static/icon.png"));
+    }
+}
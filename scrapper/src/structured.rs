@@ -0,0 +1,391 @@
+use scraper::{ElementRef, Html};
+use serde_json::{Map, Value};
+
+use crate::db::{DatabaseOrganization, DatabaseStructuredEntity};
+use crate::error::ScrapperError;
+use crate::parse_selector;
+
+//Maximo de entidades que se guardan por pagina
+const MAX_ENTITIES: usize = 50;
+
+//Propiedades con organizaciones que no son la del sitio (la marca de un producto...)
+const SKIPPED_PROPERTIES: [&str; 6] = ["brand", "manufacturer", "author", "review", "offers", "seller"];
+
+/*
+ * Tipos de schema.org que se guardan y la categoria a la que pertenecen.
+ * Los subtipos mas habituales de LocalBusiness se guardan como tal.
+ */
+const ENTITY_TYPES: [(&str, &str); 14] = [
+    ("Organization", "Organization"),
+    ("Corporation", "Organization"),
+    ("NGO", "Organization"),
+    ("OnlineStore", "Organization"),
+    ("LocalBusiness", "LocalBusiness"),
+    ("Store", "LocalBusiness"),
+    ("Restaurant", "LocalBusiness"),
+    ("ProfessionalService", "LocalBusiness"),
+    ("MedicalBusiness", "LocalBusiness"),
+    ("LodgingBusiness", "LocalBusiness"),
+    ("AutomotiveBusiness", "LocalBusiness"),
+    ("FoodEstablishment", "LocalBusiness"),
+    ("Product", "Product"),
+    ("WebSite", "WebSite"),
+];
+
+/*
+ * Entidades de schema.org de los bloques <script type="application/ld+json">
+ * y de los elementos con itemscope (microdata). Los bloques que no son json
+ * valido se ignoran.
+ */
+pub fn extract(document: &Html) -> Result<Vec<DatabaseStructuredEntity>, ScrapperError> {
+    let mut entities = vec![];
+
+    let json_ld_selector = parse_selector("script[type='application/ld+json']")?;
+    for script in document.select(&json_ld_selector) {
+        let text = script.text().collect::<String>();
+        //Algunos cms envuelven el json en CDATA
+        let text = text.trim().trim_start_matches("<![CDATA[").trim_end_matches("]]>");
+        match serde_json::from_str::<Value>(text) {
+            Ok(json) => collect(&json, "json-ld", &mut entities),
+            Err(e) => info!("         Bloque json-ld invalido: {}", e),
+        }
+    }
+
+    //Solo los itemscope de primer nivel, los anidados salen como propiedades
+    let microdata_selector = parse_selector("[itemscope][itemtype]")?;
+    for item in document.select(&microdata_selector) {
+        let nested = item
+            .ancestors()
+            .filter_map(ElementRef::wrap)
+            .any(|ancestor| ancestor.value().attr("itemscope").is_some());
+        if !nested {
+            collect(&microdata_item(item), "microdata", &mut entities);
+        }
+    }
+
+    entities.truncate(MAX_ENTITIES);
+
+    Ok(entities)
+}
+
+/*
+ * Nombre y contacto de la organizacion, de la primera entidad Organization
+ * o LocalBusiness que tenga nombre
+ */
+pub fn organization(entities: &[DatabaseStructuredEntity]) -> Option<DatabaseOrganization> {
+    let entity = entities
+        .iter()
+        .filter(|entity| entity.category == "Organization" || entity.category == "LocalBusiness")
+        .find(|entity| entity.name.is_some())?;
+
+    Some(DatabaseOrganization {
+        name: entity.name.clone(),
+        entity_type: entity.entity_type.clone(),
+        url: entity.url.clone(),
+        logo: entity.logo.clone(),
+        telephone: entity.telephone.clone(),
+        email: entity.email.clone(),
+        address: entity.address.clone(),
+        same_as: entity.same_as.clone(),
+    })
+}
+
+/*
+ * Recorre el json buscando objetos con un @type conocido, tambien dentro de
+ * @graph y de otras entidades (el publisher de un WebSite, por ejemplo)
+ */
+fn collect(json: &Value, source: &str, entities: &mut Vec<DatabaseStructuredEntity>) {
+    match json {
+        Value::Array(items) => items.iter().for_each(|item| collect(item, source, entities)),
+        Value::Object(object) => {
+            let known = types(object)
+                .into_iter()
+                .find_map(|entity_type| category(&entity_type).map(|category| (entity_type, category)));
+            if let Some((entity_type, category)) = known {
+                entities.push(entity(object, entity_type, category, source));
+            }
+
+            for (property, value) in object.iter() {
+                if !SKIPPED_PROPERTIES.contains(&property.as_str()) {
+                    collect(value, source, entities);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn entity(object: &Map<String, Value>, entity_type: String, category: &str, source: &str) -> DatabaseStructuredEntity {
+    let offer = object.get("offers").map(|offers| match offers {
+        Value::Array(offers) => offers.first().unwrap_or(&Value::Null),
+        offer => offer,
+    });
+
+    DatabaseStructuredEntity {
+        entity_type,
+        category: category.to_string(),
+        source: source.to_string(),
+        name: text(object.get("name")),
+        url: text(object.get("url")),
+        logo: text(object.get("logo")).or_else(|| text(object.get("image"))),
+        telephone: text(object.get("telephone")).map(|telephone| telephone.trim_start_matches("tel:").to_string()),
+        email: text(object.get("email")).map(|email| email.trim_start_matches("mailto:").to_string()),
+        address: address(object.get("address")),
+        same_as: match object.get("sameAs") {
+            Some(Value::Array(links)) => links.iter().filter_map(|link| text(Some(link))).collect(),
+            link => text(link).into_iter().collect(),
+        },
+        brand: text(object.get("brand")),
+        sku: text(object.get("sku")),
+        price: offer.and_then(|offer| text(offer.get("price")).or_else(|| text(offer.get("lowPrice")))),
+        currency: offer.and_then(|offer| text(offer.get("priceCurrency"))),
+    }
+}
+
+/*
+ * Texto de una propiedad: una cadena, un numero, o el name (o url, o @id)
+ * de un objeto
+ */
+fn text(value: Option<&Value>) -> Option<String> {
+    let text = match value? {
+        Value::String(text) => text.trim().to_string(),
+        Value::Number(number) => number.to_string(),
+        Value::Array(values) => return values.iter().find_map(|value| text(Some(value))),
+        Value::Object(object) => {
+            return ["name", "url", "@id", "@value"]
+                .iter()
+                .find_map(|field| text(object.get(*field)))
+        }
+        _ => return None,
+    };
+
+    (!text.is_empty()).then_some(text)
+}
+
+/*
+ * Direccion en una linea, a partir de un texto o de un PostalAddress
+ */
+fn address(value: Option<&Value>) -> Option<String> {
+    let object = match value? {
+        Value::Array(values) => return values.iter().find_map(|value| address(Some(value))),
+        Value::Object(object) => object,
+        value => return text(Some(value)),
+    };
+
+    let parts: Vec<String> = [
+        "streetAddress",
+        "postalCode",
+        "addressLocality",
+        "addressRegion",
+        "addressCountry",
+    ]
+    .iter()
+    .filter_map(|field| text(object.get(*field)))
+    .collect();
+
+    (!parts.is_empty()).then(|| parts.join(", "))
+}
+
+/*
+ * Tipos del objeto sin el prefijo de schema.org ("http://schema.org/Product"
+ * o "schema:Product" -> "Product")
+ */
+fn types(object: &Map<String, Value>) -> Vec<String> {
+    let types = match object.get("@type") {
+        Some(Value::Array(types)) => types.iter().filter_map(|entity_type| entity_type.as_str()).collect(),
+        Some(Value::String(entity_type)) => vec![entity_type.as_str()],
+        _ => vec![],
+    };
+
+    types
+        .into_iter()
+        .map(|entity_type| entity_type.rsplit(['/', ':', '#']).next().unwrap_or(entity_type).to_string())
+        .collect()
+}
+
+fn category(entity_type: &str) -> Option<&'static str> {
+    ENTITY_TYPES
+        .iter()
+        .find(|(known, _)| *known == entity_type)
+        .map(|(_, category)| *category)
+}
+
+/*
+ * Convierte un elemento con itemscope en un objeto json como el de json-ld.
+ * Las propiedades son los itemprop descendientes que no estan dentro de
+ * otro itemscope.
+ */
+fn microdata_item(item: ElementRef) -> Value {
+    let mut object = Map::new();
+    if let Some(item_type) = item.value().attr("itemtype") {
+        let item_types = item_type.split_whitespace().map(|item_type| Value::String(item_type.to_string()));
+        object.insert("@type".to_string(), Value::Array(item_types.collect()));
+    }
+
+    microdata_properties(item, &mut object);
+
+    Value::Object(object)
+}
+
+fn microdata_properties(element: ElementRef, object: &mut Map<String, Value>) {
+    for child in element.children().filter_map(ElementRef::wrap) {
+        let scoped = child.value().attr("itemscope").is_some();
+
+        if let Some(names) = child.value().attr("itemprop") {
+            let value = if scoped {
+                microdata_item(child)
+            } else {
+                Value::String(microdata_value(child))
+            };
+            for name in names.split_whitespace() {
+                match object.get_mut(name) {
+                    Some(Value::Array(values)) => values.push(value.clone()),
+                    Some(existing) => *existing = Value::Array(vec![existing.clone(), value.clone()]),
+                    None => {
+                        object.insert(name.to_string(), value.clone());
+                    }
+                }
+            }
+        }
+
+        if !scoped {
+            microdata_properties(child, object);
+        }
+    }
+}
+
+fn microdata_value(element: ElementRef) -> String {
+    let value = element.value();
+    let attribute = match value.name() {
+        "meta" => value.attr("content"),
+        "a" | "link" | "area" => value.attr("href"),
+        "img" | "audio" | "video" | "source" | "iframe" | "embed" => value.attr("src"),
+        "object" => value.attr("data"),
+        "data" | "meter" => value.attr("value"),
+        "time" => value.attr("datetime"),
+        _ => value.attr("content"),
+    };
+
+    match attribute {
+        Some(attribute) => attribute.trim().to_string(),
+        None => element.text().collect::<Vec<&str>>().join(" ").split_whitespace().collect::<Vec<&str>>().join(" "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract_html(html: &str) -> Vec<DatabaseStructuredEntity> {
+        extract(&Html::parse_document(html)).unwrap()
+    }
+
+    #[test]
+    fn json_ld_graph_with_nested_entities() {
+        let entities = extract_html(
+            r#"<script type="application/ld+json">{
+                "@context": "https://schema.org",
+                "@graph": [
+                    {
+                        "@type": "WebSite",
+                        "name": "Acme",
+                        "url": "https://acme.com/",
+                        "publisher": { "@type": "Organization", "name": "Acme S.L.", "logo": { "@type": "ImageObject", "url": "https://acme.com/logo.png" } }
+                    },
+                    {
+                        "@type": "Product",
+                        "name": "Taza",
+                        "sku": "T-1",
+                        "brand": { "@type": "Organization", "name": "Otra marca" },
+                        "offers": [{ "@type": "Offer", "price": 9.5, "priceCurrency": "EUR" }]
+                    }
+                ]
+            }</script>"#,
+        );
+
+        let found: Vec<(&str, Option<&str>)> = entities
+            .iter()
+            .map(|entity| (entity.entity_type.as_str(), entity.name.as_deref()))
+            .collect();
+        //La marca del producto no es la organizacion del sitio
+        assert_eq!(found, vec![("WebSite", Some("Acme")), ("Organization", Some("Acme S.L.")), ("Product", Some("Taza"))]);
+        assert_eq!(entities[1].logo.as_deref(), Some("https://acme.com/logo.png"));
+        assert_eq!(entities[2].brand.as_deref(), Some("Otra marca"));
+        assert_eq!(entities[2].price.as_deref(), Some("9.5"));
+        assert_eq!(entities[2].currency.as_deref(), Some("EUR"));
+    }
+
+    #[test]
+    fn type_arrays_and_prefixes() {
+        let entities = extract_html(
+            r#"<script type="application/ld+json">[
+                { "@type": ["Thing", "http://schema.org/Restaurant"], "name": "Casa Pepe", "telephone": "+34 910 000 000",
+                  "address": { "@type": "PostalAddress", "streetAddress": "Calle Mayor 1", "postalCode": "28013", "addressLocality": "Madrid" } },
+                { "@type": "schema:Corporation", "name": "Pepe Group", "sameAs": ["https://x.com/pepe", "https://linkedin.com/company/pepe"] }
+            ]</script>"#,
+        );
+
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities[0].entity_type, "Restaurant");
+        assert_eq!(entities[0].category, "LocalBusiness");
+        assert_eq!(entities[0].address.as_deref(), Some("Calle Mayor 1, 28013, Madrid"));
+        assert_eq!(entities[1].category, "Organization");
+        assert_eq!(entities[1].same_as.len(), 2);
+
+        let organization = organization(&entities).unwrap();
+        assert_eq!(organization.name.as_deref(), Some("Casa Pepe"));
+        assert_eq!(organization.telephone.as_deref(), Some("+34 910 000 000"));
+    }
+
+    #[test]
+    fn malformed_json_ld_does_not_abort_the_page() {
+        let entities = extract_html(
+            r#"<script type="application/ld+json">{ "@type": "Organization", "name": "Rota", }</script>
+               <script type="application/ld+json">
+               <![CDATA[{ "@type": "Organization", "name": "Acme", "email": "mailto:hola@acme.com" }]]>
+               </script>"#,
+        );
+
+        assert_eq!(entities.len(), 1);
+        assert_eq!(entities[0].name.as_deref(), Some("Acme"));
+        assert_eq!(entities[0].email.as_deref(), Some("hola@acme.com"));
+    }
+
+    #[test]
+    fn microdata_nested_itemprops() {
+        let entities = extract_html(
+            r#"<div itemscope itemtype="https://schema.org/LocalBusiness">
+                <h1 itemprop="name">  Taller   Lopez </h1>
+                <div itemprop="address" itemscope itemtype="https://schema.org/PostalAddress">
+                    <span itemprop="streetAddress">Av. Sol 3</span>
+                    <span itemprop="addressLocality">Sevilla</span>
+                </div>
+                <p>Llamanos al <a itemprop="telephone" href="tel:+34954000000">954 000 000</a></p>
+                <div itemprop="makesOffer" itemscope itemtype="https://schema.org/Offer">
+                    <span itemprop="name">Cambio de aceite</span>
+                </div>
+                <link itemprop="sameAs" href="https://facebook.com/tallerlopez">
+            </div>"#,
+        );
+
+        assert_eq!(entities.len(), 1);
+        let entity = &entities[0];
+        assert_eq!(entity.source, "microdata");
+        //El name de la oferta anidada no sustituye al del negocio
+        assert_eq!(entity.name.as_deref(), Some("Taller Lopez"));
+        assert_eq!(entity.address.as_deref(), Some("Av. Sol 3, Sevilla"));
+        assert_eq!(entity.telephone.as_deref(), Some("+34954000000"));
+        assert_eq!(entity.same_as, vec!["https://facebook.com/tallerlopez"]);
+    }
+
+    #[test]
+    fn pages_without_known_entities() {
+        let entities = extract_html(
+            r#"<script type="application/ld+json">{ "@type": "BreadcrumbList", "itemListElement": [] }</script>
+               <div itemscope itemtype="https://schema.org/Person"><span itemprop="name">Ana</span></div>"#,
+        );
+
+        assert!(entities.is_empty());
+        assert!(organization(&entities).is_none());
+    }
+}
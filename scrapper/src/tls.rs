@@ -0,0 +1,319 @@
+use chrono::{DateTime, Utc};
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use openssl::x509::X509VerifyResult;
+use serde::Deserialize;
+use std::pin::Pin;
+use std::time::Duration;
+use x509_parser::prelude::*;
+use x509_parser::public_key::PublicKey;
+
+use crate::db::{DatabaseCertificate, DatabaseChainCertificate};
+use crate::error::{FetchError, ScrapperError};
+
+/*
+ * Revision de certificados. Un certificado que caduca en menos de
+ * expiring_days dias se marca como "expiring". La conexion para leer el
+ * certificado tiene un limite de timeout_secs segundos.
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    pub expiring_days: i64,
+    pub timeout_secs: u64,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            expiring_days: 14,
+            timeout_secs: 10,
+        }
+    }
+}
+
+/*
+ * Certificados que presenta el servidor, en DER y empezando por el del
+ * propio servidor, junto con el error de validacion si lo hay. La conexion
+ * es independiente de la del cliente http y acepta certificados invalidos
+ * (caducados, autofirmados o de otro host) para poder registrarlos.
+ */
+pub async fn peer_chain(config: &TlsConfig, host: &str, port: u16) -> Result<(Vec<Vec<u8>>, Option<String>), ScrapperError> {
+    let tls_error = |e: &dyn std::fmt::Display| ScrapperError::Fetch(FetchError::Tls(format!("{}:{}: {}", host, port, e)));
+
+    let mut builder = SslConnector::builder(SslMethod::tls_client()).map_err(|e| tls_error(&e))?;
+    //Se valida igualmente (tambien el nombre del host) pero sin cortar la conexion
+    builder.set_verify(SslVerifyMode::NONE);
+    let ssl = builder
+        .build()
+        .configure()
+        .and_then(|configuration| configuration.into_ssl(host))
+        .map_err(|e| tls_error(&e))?;
+
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let stream = tokio::time::timeout(timeout, tokio::net::TcpStream::connect((host, port)))
+        .await
+        .map_err(|_| ScrapperError::Fetch(FetchError::Timeout))?
+        .map_err(|e| ScrapperError::Fetch(FetchError::Connect(format!("{}:{}: {}", host, port, e))))?;
+    let mut stream = tokio_openssl::SslStream::new(ssl, stream).map_err(|e| tls_error(&e))?;
+    tokio::time::timeout(timeout, Pin::new(&mut stream).connect())
+        .await
+        .map_err(|_| ScrapperError::Fetch(FetchError::Timeout))?
+        .map_err(|e| tls_error(&e))?;
+
+    let mut chain = vec![];
+    for certificate in stream.ssl().peer_cert_chain().into_iter().flatten() {
+        chain.push(certificate.to_der().map_err(|e| tls_error(&e))?);
+    }
+    //Algunos servidores no mandan la cadena, solo su certificado
+    if chain.is_empty() {
+        if let Some(certificate) = stream.ssl().peer_certificate() {
+            chain.push(certificate.to_der().map_err(|e| tls_error(&e))?);
+        }
+    }
+
+    let verify_result = stream.ssl().verify_result();
+    let verify_error = (verify_result != X509VerifyResult::OK).then(|| verify_result.error_string().to_string());
+
+    Ok((chain, verify_error))
+}
+
+/*
+ * Conecta con el servidor y devuelve el resumen de su certificado con la
+ * cadena completa
+ */
+pub async fn inspect(config: &TlsConfig, host: &str, port: u16) -> Result<DatabaseCertificate, ScrapperError> {
+    let (chain, verify_error) = peer_chain(config, host, port).await?;
+    inspect_chain(config, &chain, verify_error)
+}
+
+/*
+ * Resumen del primer certificado de la cadena (el del servidor) con el resto
+ * de la cadena. Si la validacion fallo por otro motivo que las fechas el
+ * estado es "untrusted".
+ */
+pub fn inspect_chain(
+    config: &TlsConfig,
+    chain: &[Vec<u8>],
+    verify_error: Option<String>,
+) -> Result<DatabaseCertificate, ScrapperError> {
+    let leaf = chain
+        .first()
+        .ok_or_else(|| ScrapperError::Parse("certificado: el servidor no presento ninguno".to_string()))?;
+    let mut certificate = inspect_certificate(config, leaf)?;
+
+    for der in chain {
+        let (_, parsed) = X509Certificate::from_der(der)
+            .map_err(|e| ScrapperError::Parse(format!("certificado de la cadena: {}", e)))?;
+        certificate.chain.push(DatabaseChainCertificate {
+            subject: first_attribute(parsed.subject().iter_common_name()),
+            issuer: first_attribute(parsed.issuer().iter_common_name()),
+            not_after: asn1_to_chrono(parsed.validity().not_after)?,
+            self_signed: parsed.subject() == parsed.issuer(),
+        });
+    }
+
+    if verify_error.is_some() && (certificate.status == "valid" || certificate.status == "expiring") {
+        certificate.status = "untrusted".to_string();
+    }
+    certificate.verify_error = verify_error;
+
+    Ok(certificate)
+}
+
+/*
+ * Resumen de un certificado en DER, sin la cadena ni el resultado de la
+ * validacion
+ */
+fn inspect_certificate(config: &TlsConfig, der: &[u8]) -> Result<DatabaseCertificate, ScrapperError> {
+    let (_, certificate) = X509Certificate::from_der(der)
+        .map_err(|e| ScrapperError::Parse(format!("certificado: {}", e)))?;
+
+    let not_before = asn1_to_chrono(certificate.validity().not_before)?;
+    let not_after = asn1_to_chrono(certificate.validity().not_after)?;
+
+    let subject = first_attribute(certificate.subject().iter_common_name());
+    let issuer = first_attribute(certificate.issuer().iter_common_name());
+    let issuer_organization = first_attribute(certificate.issuer().iter_organization());
+    let self_signed = certificate.subject() == certificate.issuer();
+
+    let mut san = vec![];
+    if let Ok(Some(extension)) = certificate.subject_alternative_name() {
+        for name in &extension.value.general_names {
+            match name {
+                GeneralName::DNSName(dns) => san.push(dns.to_string()),
+                GeneralName::IPAddress(ip) => san.push(format_ip(ip)),
+                _ => {}
+            }
+        }
+    }
+
+    let (key_type, key_bits) = match certificate.public_key().parsed() {
+        Ok(PublicKey::RSA(key)) => ("rsa", key.key_size()),
+        Ok(PublicKey::EC(key)) => ("ec", key.key_size()),
+        Ok(PublicKey::DSA(key)) => ("dsa", key.len() * 8),
+        //ed25519 y ed448 no los interpreta x509-parser
+        _ => match certificate.public_key().algorithm.algorithm.to_id_string().as_str() {
+            "1.3.101.112" => ("ed25519", 256),
+            "1.3.101.113" => ("ed448", 456),
+            _ => ("unknown", 0),
+        },
+    };
+
+    let now = Utc::now();
+    let status = if now < not_before {
+        "not_yet_valid"
+    } else if now > not_after {
+        "expired"
+    } else if (not_after - now).num_days() < config.expiring_days {
+        "expiring"
+    } else {
+        "valid"
+    };
+
+    Ok(DatabaseCertificate {
+        issuer_category: issuer_category(issuer_organization.as_deref(), issuer.as_deref(), self_signed).to_string(),
+        subject,
+        issuer,
+        issuer_organization,
+        serial: certificate.raw_serial_as_string(),
+        not_before,
+        not_after,
+        san,
+        key_type: key_type.to_string(),
+        key_bits: key_bits as u32,
+        self_signed,
+        status: status.to_string(),
+        verify_error: None,
+        chain: vec![],
+        checked_at: now,
+    })
+}
+
+/*
+ * Tipo de emisor: lets_encrypt, cloudflare, self_signed o commercial
+ */
+fn issuer_category(organization: Option<&str>, common_name: Option<&str>, self_signed: bool) -> &'static str {
+    if self_signed {
+        return "self_signed";
+    }
+
+    let issuer = format!("{} {}", organization.unwrap_or_default(), common_name.unwrap_or_default()).to_lowercase();
+    if issuer.contains("let's encrypt") {
+        "lets_encrypt"
+    } else if issuer.contains("cloudflare") {
+        "cloudflare"
+    } else {
+        "commercial"
+    }
+}
+
+fn first_attribute<'a>(mut attributes: impl Iterator<Item = &'a AttributeTypeAndValue<'a>>) -> Option<String> {
+    attributes
+        .next()
+        .and_then(|attribute| attribute.as_str().ok())
+        .map(|value| value.to_string())
+}
+
+fn asn1_to_chrono(time: ASN1Time) -> Result<DateTime<Utc>, ScrapperError> {
+    DateTime::from_timestamp(time.timestamp(), 0)
+        .ok_or_else(|| ScrapperError::Parse(format!("fecha de certificado {}", time)))
+}
+
+fn format_ip(ip: &[u8]) -> String {
+    match ip.len() {
+        4 => std::net::Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]).to_string(),
+        16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(ip);
+            std::net::Ipv6Addr::from(octets).to_string()
+        }
+        _ => format!("{:?}", ip),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::{PKey, Private};
+    use openssl::rsa::Rsa;
+    use openssl::ssl::SslAcceptor;
+    use openssl::x509::{X509NameBuilder, X509};
+
+    pub(crate) fn self_signed(common_name: &str, not_before_days: u32, not_after_days: i64) -> (PKey<Private>, X509) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", common_name).unwrap();
+        let name = name.build();
+
+        let now = Utc::now().timestamp();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::from_unix(now - not_before_days as i64 * 86400).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::from_unix(now + not_after_days * 86400).unwrap())
+            .unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+
+        (key, builder.build())
+    }
+
+    /*
+     * Servidor tls local que acepta una conexion con el certificado dado
+     */
+    pub(crate) async fn serve(key: PKey<Private>, certificate: X509) -> u16 {
+        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+        acceptor.set_private_key(&key).unwrap();
+        acceptor.set_certificate(&certificate).unwrap();
+        let acceptor = acceptor.build();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let ssl = openssl::ssl::Ssl::new(acceptor.context()).unwrap();
+            let mut stream = tokio_openssl::SslStream::new(ssl, stream).unwrap();
+            let _ = Pin::new(&mut stream).accept().await;
+        });
+
+        port
+    }
+
+    #[tokio::test]
+    async fn records_self_signed_certificate_of_another_host() {
+        let (key, certificate) = self_signed("sitemade.test", 1, 90);
+        let port = serve(key, certificate).await;
+
+        let certificate = inspect(&TlsConfig::default(), "127.0.0.1", port).await.unwrap();
+
+        assert_eq!(certificate.subject.as_deref(), Some("sitemade.test"));
+        assert!(certificate.self_signed);
+        assert_eq!(certificate.issuer_category, "self_signed");
+        assert_eq!(certificate.status, "untrusted");
+        assert!(certificate.verify_error.is_some());
+        assert_eq!(certificate.chain.len(), 1);
+        assert!(certificate.chain[0].self_signed);
+    }
+
+    #[tokio::test]
+    async fn records_expired_certificate() {
+        let (key, certificate) = self_signed("localhost", 30, -1);
+        let port = serve(key, certificate).await;
+
+        let certificate = inspect(&TlsConfig::default(), "localhost", port).await.unwrap();
+
+        assert_eq!(certificate.status, "expired");
+        assert!(certificate.verify_error.is_some());
+    }
+
+    #[test]
+    fn empty_chain_is_an_error() {
+        assert!(inspect_chain(&TlsConfig::default(), &[], None).is_err());
+    }
+}
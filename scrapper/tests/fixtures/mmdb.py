@@ -0,0 +1,148 @@
+#!/usr/bin/env python3
+# Genera los mmdb de prueba de geo.rs (formato MaxMind DB 2.0, arbol ipv6
+# con las ipv4 en ::/96), con algunas redes de los ficheros de test de
+# MaxMind:
+#
+#   python3 mmdb.py
+import ipaddress
+import struct
+
+
+def encode(value):
+    if isinstance(value, dict):
+        out = control(7, len(value))
+        for key, item in value.items():
+            out += encode(key) + encode(item)
+        return out
+    if isinstance(value, list):
+        return control(11, len(value)) + b"".join(encode(item) for item in value)
+    if isinstance(value, bool):
+        return control(14, int(value))
+    if isinstance(value, str):
+        data = value.encode()
+        return control(2, len(data)) + data
+    if isinstance(value, tuple):
+        kind, number = value
+        data = number.to_bytes((number.bit_length() + 7) // 8, "big")
+        return control(kind, len(data)) + data
+    raise TypeError(value)
+
+
+def control(kind, size):
+    if size < 29:
+        extra, size = b"", size
+    elif size < 285:
+        extra, size = bytes([size - 29]), 29
+    else:
+        extra, size = (size - 285).to_bytes(2, "big"), 30
+    if kind > 7:
+        return bytes([size]) + bytes([kind - 7]) + extra
+    return bytes([(kind << 5) | size]) + extra
+
+
+def uint16(number):
+    return (5, number)
+
+
+def uint32(number):
+    return (6, number)
+
+
+def uint64(number):
+    return (9, number)
+
+
+def write(path, database_type, networks):
+    root = [None, None]
+    data = b""
+    for network, record in networks:
+        network = ipaddress.ip_network(network)
+        # Las ipv4 cuelgan de ::/96, los 96 primeros bits a cero
+        bits = int(network.network_address)
+        length = network.prefixlen + (96 if network.version == 4 else 0)
+        offset = len(data)
+        data += encode(record)
+        node = root
+        for i in range(length):
+            bit = (bits >> (127 - i)) & 1
+            if i == length - 1:
+                node[bit] = ("data", offset)
+            else:
+                if not isinstance(node[bit], list):
+                    node[bit] = [None, None]
+                node = node[bit]
+
+    nodes = []
+    queue = [root]
+    while queue:
+        node = queue.pop(0)
+        nodes.append(node)
+        queue.extend(child for child in node if isinstance(child, list))
+    ids = {id(node): index for index, node in enumerate(nodes)}
+
+    def record(child):
+        if child is None:
+            return len(nodes)
+        if isinstance(child, list):
+            return ids[id(child)]
+        return len(nodes) + 16 + child[1]
+
+    tree = b"".join(struct.pack(">I", record(node[0]))[1:] + struct.pack(">I", record(node[1]))[1:] for node in nodes)
+    metadata = {
+        "binary_format_major_version": uint16(2),
+        "binary_format_minor_version": uint16(0),
+        "build_epoch": uint64(1704067200),
+        "database_type": database_type,
+        "description": {"en": "%s de prueba" % database_type},
+        "ip_version": uint16(6),
+        "languages": ["en", "es"],
+        "node_count": uint32(len(nodes)),
+        "record_size": uint16(24),
+    }
+    with open(path, "wb") as file:
+        file.write(tree + b"\0" * 16 + data + b"\xab\xcd\xefMaxMind.com" + encode(metadata))
+
+
+def names(en, es=None):
+    return {"en": en, "es": es} if es else {"en": en}
+
+
+united_kingdom = {"geoname_id": uint32(2635167), "iso_code": "GB", "names": names("United Kingdom", "Reino Unido")}
+england = {"geoname_id": uint32(6269131), "iso_code": "ENG", "names": names("England", "Inglaterra")}
+
+write(
+    "GeoIP2-City-Test.mmdb",
+    "GeoIP2-City",
+    [
+        (
+            "81.2.69.142/31",
+            {
+                "city": {"geoname_id": uint32(2643743), "names": names("London", "Londres")},
+                "country": united_kingdom,
+                "subdivisions": [england],
+            },
+        ),
+        (
+            "2.125.160.216/29",
+            {
+                "city": {"geoname_id": uint32(2655045), "names": names("Boxford")},
+                "country": united_kingdom,
+                "subdivisions": [england, {"geoname_id": uint32(3333217), "iso_code": "WBK", "names": names("West Berkshire")}],
+            },
+        ),
+        (
+            "2001:218::/32",
+            {"country": {"geoname_id": uint32(1861060), "iso_code": "JP", "names": names("Japan", "Japón")}},
+        ),
+    ],
+)
+
+write(
+    "GeoLite2-ASN-Test.mmdb",
+    "GeoLite2-ASN",
+    [
+        ("1.128.0.0/11", {"autonomous_system_number": uint32(1221), "autonomous_system_organization": "Telstra Pty Ltd"}),
+        ("81.2.69.0/24", {"autonomous_system_number": uint32(20712), "autonomous_system_organization": "Andrews & Arnold Ltd"}),
+        ("2001:218::/32", {"autonomous_system_number": uint32(2914), "autonomous_system_organization": "NTT America, Inc."}),
+    ],
+)